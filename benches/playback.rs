@@ -0,0 +1,103 @@
+//! Baselines for parsing and rendering, `cargo bench` runs them
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use xmia::{
+    context::{XmPlaybackConfig, XmPlaybackContext},
+    instrument::{
+        XmResamplingType, XmSampleBitDepth, XmSampleHeader, XmSampleLoopType, XmSamplePcmData,
+        XmSampleType,
+    },
+    note::XmNote,
+    pattern::XmPatternSlot,
+    XmModule,
+};
+
+const MODULE: &[u8] = include_bytes!("../src/test_xms/test_wo_mpt_ext.xm");
+
+const SAMPLE_RATE: u32 = 44100;
+
+const RENDERED_SECONDS: usize = 10;
+
+/// The bundled module with a looping sine wave in every instrument and every note playing
+/// instrument 1, its own samples are empty and its notes have no instrument
+fn module() -> XmModule {
+    let (_, mut module) = xmia::parse(MODULE).unwrap();
+
+    let data = (0..256)
+        .map(|e| ((e as f32 / 256.0 * core::f32::consts::TAU).sin() * 32767.0) as i16)
+        .collect::<Vec<_>>();
+    let header = XmSampleHeader {
+        length: data.len() as u32 * 2,
+        loop_start: 0,
+        loop_length: data.len() as u32 * 2,
+        volume: 64,
+        finetune: 0,
+        kind: XmSampleType::new()
+            .with_loop_type(XmSampleLoopType::ForwardLoop)
+            .with_depth(XmSampleBitDepth::Bit16),
+        panning: 128,
+        relative_note_num: 0,
+        name: String::new(),
+    };
+
+    for (_, samples) in module.instruments.iter_mut() {
+        *samples = vec![(header.clone(), XmSamplePcmData::Bit16Data(data.clone()))];
+    }
+
+    for (_, rows) in module.patterns.iter_mut() {
+        for slot in rows.0.iter_mut().flat_map(|row| row.0.iter_mut()) {
+            if let XmNote::Note { .. } = slot.note() {
+                *slot = XmPatternSlot::new(
+                    slot.note().clone(),
+                    Some(1),
+                    slot.volume_column().cloned(),
+                    slot.effect().cloned(),
+                );
+            }
+        }
+    }
+
+    module
+}
+
+fn parse(c: &mut Criterion) {
+    c.bench_function("parse", |b| {
+        b.iter(|| xmia::parse(black_box(MODULE)).unwrap())
+    });
+}
+
+fn render(c: &mut Criterion) {
+    let module = module();
+    let mut out = vec![0.0; SAMPLE_RATE as usize * RENDERED_SECONDS * 2];
+
+    let mut group = c.benchmark_group("render");
+    group.sample_size(10);
+
+    for resampling in [
+        XmResamplingType::NoInterpolation,
+        XmResamplingType::LinearInterpolation,
+        XmResamplingType::CubicInterpolation,
+    ] {
+        let config = XmPlaybackConfig {
+            resampling,
+            ..Default::default()
+        };
+
+        group.bench_function(
+            BenchmarkId::from_parameter(format!("{:?}", resampling)),
+            |b| {
+                b.iter(|| {
+                    let mut context =
+                        XmPlaybackContext::new_with_config(&module, SAMPLE_RATE, config);
+                    context.render(&mut out);
+                    black_box(&out);
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, parse, render);
+criterion_main!(benches);
@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// Malformed modules should only ever produce errors. Inputs which used to panic live in
+// `fuzz/regressions` and are checked by the regular test suite.
+fuzz_target!(|data: &[u8]| {
+    let _ = xmia::parse(data);
+    let _ = xmia::parse_with_options(data, xmia::ParseOptions { strict: false });
+});
@@ -1,7 +1,14 @@
-use log::warn;
-
 use crate::{
-    instrument::XmInstrumentHeader, pattern::XmPatternSlot, XmModule, XmPattern, XmSample,
+    effect::{DoubleU4, XmEffect, XmVolumeColumn, XmVolumeColumnCommand},
+    extension::XmMacroCommand,
+    frequency::FrequencyTable,
+    instrument::{
+        XmEnvelope, XmInstrumentHeader, XmInstrumentSampleOpts, XmResamplingType, XmSampleHeader,
+        XmSampleLoopType, XmSamplePcmData, XmVibratoType,
+    },
+    note::XmNote,
+    pattern::{is_order_marker, pattern_delay, XmPatternRow, XmPatternSlot, XmRowCursor},
+    XmModule, XmSample,
 };
 
 #[derive(Clone)]
@@ -13,42 +20,307 @@ pub struct XmInstrumentState<'a> {
     period: f32,
     frequency: f32,
     step: f32,
+    // the period `frequency` was last computed for, notes mostly hold their period from one
+    // tick to the next so the `powf` can be skipped
+    frequency_period: Option<f32>,
     ping: bool,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct XmChannelContext<'a> {
-    /// this property is in Hz
-    fine_tune: f32,
-
     /// if it is `None`, no instrument is being executed/played
     /// everytime this is `Some(_)` it will get read and played
     instrument_state: Option<XmInstrumentState<'a>>,
 
-    pattern_slot_state: Option<XmPatternSlot>,
+    pattern_slot_state: Option<&'a XmPatternSlot>,
     volume: f32,
     panning: f32,
+
+    /// 1-based index of the last instrument referenced on this channel
+    instrument_index: Option<u8>,
+
+    /// last note triggered on this channel, pitch effects are relative to it
+    note: XmNote,
+
+    /// set through `E5x`, replaces the sample finetune for the note on the current row
+    finetune_override: Option<i8>,
+
+    /// semitones added on top of `note` for the current tick
+    arpeggio_offset: u8,
+
+    /// last non-zero `9xx` parameter
+    sample_offset: u8,
+
+    /// set through `XAx`, extends `sample_offset` above 65535 samples
+    high_offset: u8,
+
+    /// last non-zero speed and depth, shared by the volume column and `4xy`
+    vibrato_speed: u8,
+    vibrato_depth: u8,
+    /// 256 steps per waveform cycle, moves by 4 steps per unit of speed
+    vibrato_position: u8,
+    /// period offset applied for the current tick
+    vibrato_offset: f32,
+
+    /// last non-zero `Yxy` nibbles
+    panbrello_speed: u8,
+    panbrello_depth: u8,
+    /// 256 steps per waveform cycle, moves by 1 step per unit of speed
+    panbrello_position: u8,
+    /// set through `X5x`
+    panbrello_waveform: XmVibratoType,
+    /// panning offset applied for the current tick
+    panbrello_offset: f32,
+
+    /// instrument vibrato, ticks since the note was triggered for the sweep
+    autovibrato_ticks: u16,
+    /// 256 steps per waveform cycle
+    autovibrato_position: u8,
+    /// period offset applied for the current tick, on top of `vibrato_offset`
+    autovibrato_offset: f32,
+
+    /// last non-zero `Txy` parameter
+    tremor: DoubleU4,
+    /// ticks into the current tremor on/off cycle
+    tremor_position: u8,
+    /// set while tremor is in its off phase, silences the channel
+    tremor_muted: bool,
+
+    /// last non-zero `Rxy` parameters, `x` picks the volume change and `y` the interval in ticks
+    retrigger_volume: u8,
+    retrigger_interval: u8,
+    /// ticks since the last `Rxy` retrigger, carries over between rows
+    retrigger_counter: u8,
+
+    /// last non-zero `Pxy` parameter
+    panning_slide: DoubleU4,
+
+    /// last non-zero `E1x`, `E2x`, `X1x` and `X2x` parameters
+    fine_portamento_up: u8,
+    fine_portamento_down: u8,
+    extra_fine_portamento_up: u8,
+    extra_fine_portamento_down: u8,
+
+    /// last non-zero tone portamento speed, in `3xx` units
+    portamento_speed: u8,
+    /// period the tone portamento slides towards
+    portamento_target: Option<f32>,
+    /// set through `E3x`, the tone portamento is heard in semitone steps while the period
+    /// itself keeps sliding smoothly
+    glissando: bool,
+
+    /// `EFx` parameter, picks the macro `Z00` to `Z7F` send
+    active_macro: u8,
+    /// set through the MIDI macros, nothing is filtered yet so they're only kept track of
+    filter_cutoff: Option<u8>,
+    filter_resonance: Option<u8>,
+
+    /// set through `X9x`, surround inverts the right side of the channel, the rest are only
+    /// kept track of
+    surround: bool,
+    reverb: bool,
+    highpass_filter: bool,
+    play_backwards: bool,
+
+    /// `false` once the note has been released, lets envelopes move past their sustain point
+    key_held: bool,
+    volume_envelope_frame: u16,
+    /// volume envelope output for the current tick, scales `volume`
+    envelope_volume: f32,
+    /// starts at 1.0 and goes down by `volume_fadeout / 65536` every tick once the note has been
+    /// released, the channel is freed when it reaches 0.0
+    fadeout_volume: f32,
+    panning_envelope_frame: u16,
+    /// panning envelope output for the current tick, 0.5 leaves `panning` untouched
+    envelope_panning: f32,
 }
 
 impl<'a> Default for XmChannelContext<'a> {
     fn default() -> Self {
         Self {
-            fine_tune: 0.0,
             instrument_state: None,
             pattern_slot_state: None,
             volume: 1.0,
             panning: 0.5,
+            instrument_index: None,
+            note: XmNote::NoNote,
+            finetune_override: None,
+            arpeggio_offset: 0,
+            sample_offset: 0,
+            high_offset: 0,
+            vibrato_speed: 0,
+            vibrato_depth: 0,
+            vibrato_position: 0,
+            vibrato_offset: 0.0,
+            panbrello_speed: 0,
+            panbrello_depth: 0,
+            panbrello_position: 0,
+            panbrello_waveform: XmVibratoType::Sine,
+            panbrello_offset: 0.0,
+            autovibrato_ticks: 0,
+            autovibrato_position: 0,
+            autovibrato_offset: 0.0,
+            tremor: DoubleU4::new(),
+            tremor_position: 0,
+            tremor_muted: false,
+            retrigger_volume: 0,
+            retrigger_interval: 0,
+            retrigger_counter: 0,
+            panning_slide: DoubleU4::new(),
+            fine_portamento_up: 0,
+            fine_portamento_down: 0,
+            extra_fine_portamento_up: 0,
+            extra_fine_portamento_down: 0,
+            portamento_speed: 0,
+            portamento_target: None,
+            glissando: false,
+            active_macro: 0,
+            filter_cutoff: None,
+            filter_resonance: None,
+            surround: false,
+            reverb: false,
+            highpass_filter: false,
+            play_backwards: false,
+            key_held: false,
+            volume_envelope_frame: 0,
+            envelope_volume: 1.0,
+            fadeout_volume: 1.0,
+            panning_envelope_frame: 0,
+            envelope_panning: 0.5,
+        }
+    }
+}
+
+/// Evaluates an oscillator waveform at `position` (256 steps per cycle), the result is in the
+/// `-1.0..=1.0` range
+pub(crate) fn waveform(kind: &XmVibratoType, position: u8) -> f32 {
+    let phase = position as f32 / 256.0;
+
+    match kind {
+        XmVibratoType::Sine => (phase * core::f32::consts::TAU).sin(),
+        XmVibratoType::Square => {
+            if phase < 0.5 {
+                1.0
+            } else {
+                -1.0
+            }
         }
+        XmVibratoType::RampDown => 1.0 - phase * 2.0,
+        XmVibratoType::RampUp => phase * 2.0 - 1.0,
+    }
+}
+
+/// Returns the `3xx` equivalent speed if `slot` slides to its note, either through the effect or
+/// the volume column
+fn tone_portamento_speed(slot: &XmPatternSlot) -> Option<u8> {
+    if let Some(XmEffect::TonePortamento(a)) = slot.effect {
+        return Some(a);
+    }
+
+    match slot.volume_column {
+        // FT2 scales the volume column nibble by 16
+        Some(ref column)
+            if column.0 >= 0x10
+                && matches!(column.command(), XmVolumeColumnCommand::TonePortamento) =>
+        {
+            Some(column.argument() << 4)
+        }
+        _ => None,
+    }
+}
+
+/// Returns how far the sample position moves per output sample when `note` is played on
+/// `sample` at `sample_rate`, which is the `source_rate / output_rate` ratio needed to set up an
+/// external resampler. The sample's relative note and finetune are applied like in playback.
+/// `None` if `note` isn't an actual note or if the relative note shifts it out of range.
+pub fn playback_step(
+    note: &XmNote,
+    sample: &XmSampleHeader,
+    frequency_table: FrequencyTable,
+    sample_rate: u32,
+) -> Option<f32> {
+    let period = frequency_table.note_period(note, sample.finetune, sample.relative_note_num)?;
+
+    Some(frequency_table.frequency(period) / sample_rate as f32)
+}
+
+/// Stores `parameter` into `memory` unless it's zero, and returns the parameter to use
+fn remember(memory: &mut u8, parameter: u8) -> u8 {
+    if parameter != 0 {
+        *memory = parameter;
+    }
+
+    *memory
+}
+
+fn lookup_instrument(module: &XmModule, index: u8) -> Option<&(XmInstrumentHeader, Vec<XmSample>)> {
+    // instruments are 1-indexed in pattern data
+    module.instruments.get((index as usize).checked_sub(1)?)
+}
+
+// the instrument and sample are named rather than dumped along with all of their sample data
+impl core::fmt::Debug for XmInstrumentState<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("XmInstrumentState")
+            .field("instrument", &self.instrument.name)
+            .field("sample", &self.sample.0.name)
+            .field("sample_position", &self.sample_position)
+            .field("period", &self.period)
+            .field("frequency", &self.frequency)
+            .field("step", &self.step)
+            .field("ping", &self.ping)
+            .finish()
     }
 }
 
 impl<'a> XmInstrumentState<'a> {
+    fn new(instrument: &'a XmInstrumentHeader, sample: &'a XmSample, period: f32) -> Self {
+        Self {
+            instrument,
+            sample,
+            sample_position: 0.0,
+
+            period,
+            frequency: 0.0,
+            step: 0.0,
+            frequency_period: None,
+            ping: true,
+        }
+    }
+
+    /// Loop start and end in samples, the header stores them in bytes. The header may not agree
+    /// with the decoded data, so the loop is cut off at the end of the data.
+    fn loop_bounds(&self) -> (f32, f32) {
+        let bytes_per_sample = match self.sample.1 {
+            XmSamplePcmData::Bit8Data(_) => 1,
+            XmSamplePcmData::Bit16Data(_) => 2,
+        };
+        let length = self.sample.1.len() as u32;
+
+        let start = (self.sample.0.loop_start / bytes_per_sample).min(length);
+        let end = (start + self.sample.0.loop_length / bytes_per_sample).min(length);
+
+        (start as f32, end as f32)
+    }
+
+    /// The loop type of the sample, loops which are empty after `loop_bounds` are played as if
+    /// the sample didn't loop at all
+    fn loop_type(&self) -> XmSampleLoopType {
+        let (loop_start, loop_end) = self.loop_bounds();
+
+        if loop_end > loop_start {
+            self.sample.0.kind.loop_type()
+        } else {
+            XmSampleLoopType::NoLoop
+        }
+    }
+
     fn advance(&mut self) -> bool {
-        if self.sample.1.len() == 0 {
+        if self.sample.1.is_empty() {
             return true;
         }
 
-        match self.sample.0.kind.loop_type() {
+        match self.loop_type() {
             crate::instrument::XmSampleLoopType::NoLoop
             | crate::instrument::XmSampleLoopType::Unknown => {
                 self.sample_position += self.step;
@@ -64,9 +336,9 @@ impl<'a> XmInstrumentState<'a> {
             crate::instrument::XmSampleLoopType::ForwardLoop => {
                 self.sample_position += self.step;
 
-                let loop_end = self.sample.0.loop_start + self.sample.0.loop_length;
-                if self.sample_position >= loop_end as f32 {
-                    self.sample_position = self.sample.0.loop_start as f32;
+                let (loop_start, loop_end) = self.loop_bounds();
+                if self.sample_position >= loop_end {
+                    self.sample_position = loop_start;
                 }
 
                 false
@@ -78,16 +350,20 @@ impl<'a> XmInstrumentState<'a> {
                     self.sample_position -= self.step;
                 };
 
-                let loop_end = self.sample.0.loop_start + self.sample.0.loop_length;
+                // the overshoot is reflected back into the loop, which is clamped again in case
+                // the step is longer than the loop itself
+                let (loop_start, loop_end) = self.loop_bounds();
                 if self.ping {
-                    if self.sample_position >= loop_end as f32 {
+                    if self.sample_position >= loop_end {
                         self.ping = false;
-                        self.sample_position = loop_end as f32;
+                        self.sample_position =
+                            (loop_end * 2.0 - self.sample_position).max(loop_start);
                     }
                 } else {
-                    if self.sample_position <= self.sample.0.loop_start as f32 {
+                    if self.sample_position <= loop_start {
                         self.ping = true;
-                        self.sample_position = self.sample.0.loop_start as f32;
+                        self.sample_position =
+                            (loop_start * 2.0 - self.sample_position).min(loop_end);
                     }
                 }
 
@@ -96,148 +372,1364 @@ impl<'a> XmInstrumentState<'a> {
         }
     }
 
-    fn sample(&self) -> f32 {
-        if self.sample.1.len() == 0 {
+    fn sample(&self, resampling: XmResamplingType) -> f32 {
+        if self.sample.1.is_empty() {
             // nothing to generate since there is no sample
             return 0.0;
         }
 
-        // TODO: change resampling type argument
-        let sample = || {
-            self.sample.1.get_interpolated(
-                self.sample_position,
-                false,
-                crate::instrument::XmResamplingType::LinearInterpolation,
-            )
-        };
+        let sample = |position| self.sample.1.get_interpolated(position, false, resampling);
 
-        let reversed_sample = || {
-            self.sample.1.get_interpolated(
-                self.sample_position,
-                true,
-                crate::instrument::XmResamplingType::LinearInterpolation,
-            )
-        };
+        let reversed_sample = |position| self.sample.1.get_interpolated(position, true, resampling);
 
-        let sample = match self.sample.0.kind.loop_type() {
+        let sample = match self.loop_type() {
             crate::instrument::XmSampleLoopType::NoLoop
-            | crate::instrument::XmSampleLoopType::ForwardLoop
             // TODO: do something different for unknown type
-            | crate::instrument::XmSampleLoopType::Unknown => sample(),
+            | crate::instrument::XmSampleLoopType::Unknown => sample(self.sample_position),
+            // the last sample of the loop blends into its first one instead of the one after it
+            crate::instrument::XmSampleLoopType::ForwardLoop => {
+                let (loop_start, loop_end) = self.loop_bounds();
+
+                self.sample.1.get_interpolated_looped(
+                    self.sample_position,
+                    loop_start as usize..loop_end as usize,
+                    resampling,
+                )
+            }
             crate::instrument::XmSampleLoopType::BidirectionalLoop => {
-                if self.ping {
-                    sample()
+                // neither the position nor its interpolation neighbour may leave the loop, the
+                // last sample of the loop is held instead of blending into the one after it
+                let (loop_start, loop_end) = self.loop_bounds();
+                let last = (loop_end - 1.0).max(loop_start);
+                let position = self.sample_position.clamp(loop_start, last);
+
+                if position.floor() >= last {
+                    self.sample.1.get(last as usize)
+                } else if self.ping {
+                    sample(position)
                 } else {
-                    reversed_sample()
+                    reversed_sample(position)
                 }
             }
         };
 
-        match sample {
-            Some(v) => v,
-            None => 0.0,
-        }
+        sample.unwrap_or(0.0)
     }
 }
 
 impl<'a> XmChannelContext<'a> {
     fn advance(&mut self) {
-        todo!();
+        let Some(ref mut instrument_state) = self.instrument_state else {
+            return;
+        };
+
+        if instrument_state.advance() {
+            self.instrument_state = None;
+        }
+    }
+
+    fn sample(&self, resampling: XmResamplingType) -> f32 {
+        match self.instrument_state {
+            Some(ref v) => v.sample(resampling),
+            None => 0.0,
+        }
+    }
+
+    /// Channel volume after applying tremor, the volume envelope and fadeout
+    pub(crate) fn output_volume(&self) -> f32 {
+        if self.tremor_muted {
+            return 0.0;
+        }
+
+        self.volume * self.envelope_volume * self.fadeout_volume
+    }
+
+    /// Channel panning after applying panbrello and the panning envelope, which swings less the
+    /// closer the channel panning is to either side
+    pub(crate) fn output_panning(&self) -> f32 {
+        let panning = (self.panning + self.panbrello_offset).clamp(0.0, 1.0);
+        let range = 0.5 - (panning - 0.5).abs();
+
+        (panning + (self.envelope_panning - 0.5) * range * 2.0).clamp(0.0, 1.0)
+    }
+
+    fn sample_opts(&self) -> Option<&'a XmInstrumentSampleOpts> {
+        self.instrument_state
+            .as_ref()?
+            .instrument
+            .sample_opts
+            .as_ref()
+    }
+
+    fn volume_envelope(&self) -> Option<&'a XmEnvelope> {
+        self.sample_opts()?.volume_envelope.as_ref()
+    }
+
+    fn panning_envelope(&self) -> Option<&'a XmEnvelope> {
+        self.sample_opts()?.panning_envelope.as_ref()
+    }
+
+    /// 1-based index of the last instrument referenced on this channel
+    pub fn instrument_index(&self) -> Option<u8> {
+        self.instrument_index
+    }
+
+    /// Last note triggered on this channel
+    pub fn note(&self) -> &XmNote {
+        &self.note
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.instrument_state.is_some()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn volume(&self) -> (f32, f32) {
+        (self.volume, self.panning)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn sound_control_flags(&self) -> [bool; 4] {
+        [
+            self.surround,
+            self.reverb,
+            self.highpass_filter,
+            self.play_backwards,
+        ]
+    }
+
+    #[cfg(test)]
+    pub(crate) fn filter(&self) -> (Option<u8>, Option<u8>) {
+        (self.filter_cutoff, self.filter_resonance)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn envelope_volume(&self) -> f32 {
+        self.envelope_volume
+    }
+
+    #[cfg(test)]
+    pub(crate) fn sample_position(&self) -> Option<f32> {
+        self.instrument_state.as_ref().map(|e| e.sample_position)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn period(&self) -> Option<f32> {
+        self.instrument_state.as_ref().map(|e| e.period)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn frequency(&self) -> Option<f32> {
+        self.instrument_state.as_ref().map(|e| e.frequency)
     }
 
-    fn sample(&self) -> f32 {
-        todo!();
+    /// Period `note` plays at on `sample`, shifted by the sample's relative note and tuned by
+    /// its finetune, or by the `E5x` override
+    fn note_period(&self, module: &XmModule, sample: &XmSample, note: &XmNote) -> Option<f32> {
+        let finetune = self.finetune_override.unwrap_or(sample.0.finetune);
+
+        module
+            .header
+            .frequency_table()
+            .note_period(note, finetune, sample.0.relative_note_num)
+    }
+
+    /// The channel's instrument along with the sample its keymap plays `note` with
+    fn keymap_sample(
+        &self,
+        module: &'a XmModule,
+        note: &XmNote,
+    ) -> Option<(&'a XmInstrumentHeader, &'a XmSample)> {
+        let (instrument, samples) = lookup_instrument(module, self.instrument_index?)?;
+        let sample = samples.get(instrument.sample_index(note)? as usize)?;
+
+        Some((instrument, sample))
+    }
+
+    fn trigger_note(
+        &mut self,
+        module: &'a XmModule,
+        instrument: &'a XmInstrumentHeader,
+        sample: &'a XmSample,
+    ) {
+        let Some(period) = self.note_period(module, sample, &self.note) else {
+            return;
+        };
+
+        self.instrument_state = Some(XmInstrumentState::new(instrument, sample, period));
+        self.restart_envelopes();
+    }
+
+    fn restart_envelopes(&mut self) {
+        self.key_held = true;
+        self.autovibrato_ticks = 0;
+        self.autovibrato_position = 0;
+        self.fadeout_volume = 1.0;
+        self.volume_envelope_frame = 0;
+        self.panning_envelope_frame = 0;
+    }
+
+    /// Restarts the playing sample from the beginning, keeping its pitch
+    fn retrigger(&mut self) {
+        let Some(ref mut instrument_state) = self.instrument_state else {
+            return;
+        };
+
+        instrument_state.sample_position = 0.0;
+        instrument_state.ping = true;
+        self.restart_envelopes();
     }
+
+    fn tremor(&mut self, parameter: &DoubleU4) {
+        if parameter.into_bits() != 0 {
+            self.tremor = *parameter;
+        }
+
+        // on for x + 1 ticks, then off for y + 1 ticks
+        let on = self.tremor.x() + 1;
+        let off = self.tremor.y() + 1;
+
+        self.tremor_muted = self.tremor_position >= on;
+        self.tremor_position = (self.tremor_position + 1) % (on + off);
+    }
+
+    fn multi_retrigger(&mut self) {
+        self.retrigger_counter += 1;
+        if self.retrigger_counter < self.retrigger_interval {
+            return;
+        }
+        self.retrigger_counter = 0;
+
+        let volume = self.volume * 64.0;
+        let volume = match self.retrigger_volume {
+            0x1..=0x5 => volume - (1 << (self.retrigger_volume - 1)) as f32,
+            0x6 => volume * 2.0 / 3.0,
+            0x7 => volume / 2.0,
+            0x9..=0xD => volume + (1 << (self.retrigger_volume - 9)) as f32,
+            0xE => volume * 3.0 / 2.0,
+            0xF => volume * 2.0,
+            _ => volume,
+        };
+        self.volume = (volume / 64.0).clamp(0.0, 1.0);
+
+        self.retrigger();
+    }
+
+    fn release_note(&mut self) {
+        self.key_held = false;
+
+        // without a volume envelope there's nothing to release, so the note is cut right away
+        if self.volume_envelope().is_none() {
+            self.instrument_state = None;
+        }
+    }
+
+    fn update_envelopes(&mut self) {
+        self.envelope_volume = match self.volume_envelope() {
+            Some(envelope) => {
+                let value = envelope.evaluate(self.volume_envelope_frame);
+                self.volume_envelope_frame =
+                    envelope.advance(self.volume_envelope_frame, self.key_held);
+
+                // an envelope which can neither sustain nor loop releases the note once it ends
+                let last_frame = envelope.points.last().map_or(0, |e| e.frame);
+                if envelope.sustain_point.is_none()
+                    && envelope.loop_end_point.is_none()
+                    && self.volume_envelope_frame >= last_frame
+                {
+                    self.key_held = false;
+                }
+
+                value.min(64.0) / 64.0
+            }
+            None => 1.0,
+        };
+
+        self.envelope_panning = match self.panning_envelope() {
+            Some(envelope) => {
+                let value = envelope.evaluate(self.panning_envelope_frame);
+                self.panning_envelope_frame =
+                    envelope.advance(self.panning_envelope_frame, self.key_held);
+
+                value.min(64.0) / 64.0
+            }
+            None => 0.5,
+        };
+    }
+
+    fn update_autovibrato(&mut self) {
+        let Some(opts) = self.sample_opts() else {
+            self.autovibrato_offset = 0.0;
+            return;
+        };
+        let vibrato = &opts.vibrato;
+
+        // the depth ramps in linearly over `sweep` ticks
+        let sweep = if self.autovibrato_ticks < vibrato.sweep as u16 {
+            self.autovibrato_ticks as f32 / vibrato.sweep as f32
+        } else {
+            1.0
+        };
+
+        // `depth` is in period units, so the maximum of 15 is roughly a quarter of a semitone
+        self.autovibrato_offset =
+            waveform(&vibrato.kind, self.autovibrato_position) * vibrato.depth as f32 * sweep;
+        self.autovibrato_position = self.autovibrato_position.wrapping_add(vibrato.rate);
+        self.autovibrato_ticks = self.autovibrato_ticks.saturating_add(1);
+    }
+
+    fn update_fadeout(&mut self) {
+        if self.key_held {
+            return;
+        }
+
+        let Some(opts) = self.sample_opts() else {
+            return;
+        };
+
+        self.fadeout_volume = (self.fadeout_volume - opts.volume_fadeout as f32 / 65536.0).max(0.0);
+
+        if self.fadeout_volume == 0.0 {
+            self.instrument_state = None;
+        }
+    }
+
+    fn process_row(&mut self, module: &'a XmModule, slot: Option<&'a XmPatternSlot>) {
+        self.pattern_slot_state = slot;
+        self.arpeggio_offset = 0;
+        self.vibrato_offset = 0.0;
+        self.panbrello_offset = 0.0;
+        self.tremor_muted = false;
+
+        let Some(slot) = slot else { return };
+
+        if let Some(index) = slot.instrument_index {
+            self.instrument_index = Some(index);
+        }
+
+        // the sample the row's note plays, or the held note's if the row has none
+        let note = match slot.note {
+            XmNote::Note { .. } => &slot.note,
+            _ => &self.note,
+        };
+        let sample = self.keymap_sample(module, note);
+
+        if let (Some(_), Some((_, (header, _)))) = (slot.instrument_index, sample) {
+            self.set_volume(header.volume);
+            self.panning = header.panning as f32 / 255.0;
+        }
+
+        let portamento_speed = tone_portamento_speed(slot);
+        if let Some(speed) = portamento_speed.filter(|e| *e != 0) {
+            self.portamento_speed = speed;
+        }
+
+        // FT2 maps the nibble to the -128..=112 range of the sample finetune
+        self.finetune_override = match slot.effect {
+            Some(XmEffect::SetFinetune(a)) => Some((a as i8 - 8) * 16),
+            _ => None,
+        };
+
+        match slot.note {
+            // a tone portamento slides the playing note instead of triggering a new one
+            XmNote::Note { .. }
+                if portamento_speed.is_some() && self.instrument_state.is_some() =>
+            {
+                let sample = self.instrument_state.as_ref().map(|e| e.sample);
+                self.note = slot.note.clone();
+                self.portamento_target =
+                    sample.and_then(|sample| self.note_period(module, sample, &slot.note));
+            }
+            XmNote::Note { .. } => {
+                self.note = slot.note.clone();
+                self.portamento_target = None;
+                if let Some((instrument, sample)) = sample {
+                    self.trigger_note(module, instrument, sample);
+                }
+            }
+            XmNote::NoteOff => self.release_note(),
+            XmNote::NoNote => {}
+        }
+
+        match slot.effect {
+            Some(XmEffect::SampleOffset(a)) => {
+                if a != 0 {
+                    self.sample_offset = a;
+                }
+
+                if let XmNote::Note { .. } = slot.note {
+                    self.apply_sample_offset();
+                }
+            }
+            Some(XmEffect::HighOffset(a)) => self.high_offset = a,
+            // ModPlug's order, the random waveform is played as a sine
+            Some(XmEffect::SetPanbrelloWaveform(a)) => {
+                self.panbrello_waveform = match a & 0x3 {
+                    1 => XmVibratoType::RampDown,
+                    2 => XmVibratoType::Square,
+                    _ => XmVibratoType::Sine,
+                };
+            }
+            Some(XmEffect::SoundControl(a)) => self.sound_control(a),
+            Some(XmEffect::SetActiveMacro(a)) => self.active_macro = a,
+            Some(XmEffect::MidiMacro(a)) | Some(XmEffect::SmoothMidiMacro(a)) => {
+                self.send_macro(module, a);
+            }
+            Some(XmEffect::GlissandoControl(a)) => self.glissando = a != 0,
+            Some(XmEffect::SetPanningFine(a)) => self.panning = a as f32 / 255.0,
+            Some(XmEffect::SetPanning(a)) => self.panning = a as f32 / 15.0,
+            // fine slides move by 4 period units per step, extra fine ones by 1
+            Some(XmEffect::FinePortamentoUp(a)) => {
+                let a = remember(&mut self.fine_portamento_up, a);
+                self.slide_period(-(a as f32) * 4.0);
+            }
+            Some(XmEffect::FinePortamentoDown(a)) => {
+                let a = remember(&mut self.fine_portamento_down, a);
+                self.slide_period(a as f32 * 4.0);
+            }
+            Some(XmEffect::ExtraFinePortamentoUp(a)) => {
+                let a = remember(&mut self.extra_fine_portamento_up, a);
+                self.slide_period(-(a as f32));
+            }
+            Some(XmEffect::ExtraFinePortamentoDown(a)) => {
+                let a = remember(&mut self.extra_fine_portamento_down, a);
+                self.slide_period(a as f32);
+            }
+            Some(XmEffect::RetriggerWithVolume(ref a)) => {
+                if a.x() != 0 {
+                    self.retrigger_volume = a.x();
+                }
+                if a.y() != 0 {
+                    self.retrigger_interval = a.y();
+                }
+
+                // a note on this row has just been triggered, so counting starts over
+                if let XmNote::Note { .. } = slot.note {
+                    self.retrigger_counter = 0;
+                } else {
+                    self.multi_retrigger();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// `X9x`, the other sub-commands are left alone
+    fn sound_control(&mut self, command: u8) {
+        match command {
+            0x0 => self.surround = false,
+            0x1 => self.surround = true,
+            0x8 => self.reverb = false,
+            0x9 => self.reverb = true,
+            0xC => self.highpass_filter = false,
+            0xD => self.highpass_filter = true,
+            0xE => self.play_backwards = false,
+            0xF => self.play_backwards = true,
+            _ => {}
+        }
+    }
+
+    /// Sends the macro `Zxx` or `\xx` picks, the smooth one jumps to its value right away.
+    /// Without a `MIDI` chunk in the module there are no macros, so both effects do nothing.
+    fn send_macro(&mut self, module: &XmModule, parameter: u8) {
+        let command = module
+            .extensions
+            .midi_macros()
+            .and_then(|e| e.zxx_macro(self.active_macro, parameter))
+            .and_then(|e| XmMacroCommand::parse(e, parameter & 0x7F));
+
+        match command {
+            Some(XmMacroCommand::FilterCutoff(a)) => self.filter_cutoff = Some(a),
+            Some(XmMacroCommand::FilterResonance(a)) => self.filter_resonance = Some(a),
+            None => {}
+        }
+    }
+
+    fn apply_sample_offset(&mut self) {
+        let offset = self.high_offset as usize * 0x10000 + self.sample_offset as usize * 0x100;
+
+        let Some(ref mut instrument_state) = self.instrument_state else {
+            return;
+        };
+
+        if offset < instrument_state.sample.1.len() {
+            instrument_state.sample_position = offset as f32;
+        } else {
+            // FT2 doesn't play anything when the offset lands past the end of the sample
+            self.instrument_state = None;
+        }
+    }
+
+    /// Sets the volume from the 0..=64 range of the sample header, the volume column and `Cxx`
+    fn set_volume(&mut self, volume: u8) {
+        self.volume = volume.min(0x40) as f32 / 64.0;
+    }
+
+    fn slide_volume(&mut self, amount: f32) {
+        self.volume = (self.volume + amount / 64.0).clamp(0.0, 1.0);
+    }
+
+    fn slide_panning(&mut self, amount: f32) {
+        self.panning = (self.panning + amount / 255.0).clamp(0.0, 1.0);
+    }
+
+    fn vibrato(&mut self) {
+        // FT2 scales the depth by (sine table peak / 32) period units
+        self.vibrato_offset = waveform(&XmVibratoType::Sine, self.vibrato_position)
+            * self.vibrato_depth as f32
+            * 255.0
+            / 32.0;
+        self.vibrato_position = self
+            .vibrato_position
+            .wrapping_add(self.vibrato_speed.wrapping_mul(4));
+    }
+
+    /// Runs on every tick of the row, the first one included
+    fn panbrello(&mut self, parameter: &DoubleU4) {
+        if parameter.x() != 0 {
+            self.panbrello_speed = parameter.x();
+        }
+        if parameter.y() != 0 {
+            self.panbrello_depth = parameter.y();
+        }
+
+        // ModPlug swings the panning by up to depth * 8 of its 256 steps
+        self.panbrello_offset = waveform(&self.panbrello_waveform, self.panbrello_position)
+            * self.panbrello_depth as f32
+            * 8.0
+            / 256.0;
+        self.panbrello_position = self.panbrello_position.wrapping_add(self.panbrello_speed);
+    }
+
+    fn panning_slide(&mut self, parameter: &DoubleU4) {
+        if parameter.into_bits() != 0 {
+            self.panning_slide = *parameter;
+        }
+
+        // x slides right and takes precedence over y, which slides left
+        if self.panning_slide.x() != 0 {
+            self.slide_panning(self.panning_slide.x() as f32);
+        } else {
+            self.slide_panning(-(self.panning_slide.y() as f32));
+        }
+    }
+
+    fn slide_period(&mut self, amount: f32) {
+        let Some(ref mut instrument_state) = self.instrument_state else {
+            return;
+        };
+
+        instrument_state.period = (instrument_state.period + amount).max(1.0);
+    }
+
+    fn tone_portamento(&mut self) {
+        let (Some(target), Some(ref mut instrument_state)) =
+            (self.portamento_target, self.instrument_state.as_mut())
+        else {
+            return;
+        };
+
+        let speed = self.portamento_speed as f32 * 4.0;
+
+        instrument_state.period = if instrument_state.period < target {
+            (instrument_state.period + speed).min(target)
+        } else {
+            (instrument_state.period - speed).max(target)
+        };
+    }
+
+    fn apply_volume_column(&mut self, column: &XmVolumeColumn, tick: u32) {
+        // 0x00..=0x0F doesn't do anything
+        if column.0 < 0x10 {
+            return;
+        }
+
+        let argument = column.argument();
+
+        match (column.command(), tick) {
+            (XmVolumeColumnCommand::SetVolume, 0) => self.set_volume(argument),
+            (XmVolumeColumnCommand::VolumeSlideDown, 1..) => self.slide_volume(-(argument as f32)),
+            (XmVolumeColumnCommand::VolumeSlideUp, 1..) => self.slide_volume(argument as f32),
+            (XmVolumeColumnCommand::FineVolumeDown, 0) => self.slide_volume(-(argument as f32)),
+            (XmVolumeColumnCommand::FineVolumeUp, 0) => self.slide_volume(argument as f32),
+            (XmVolumeColumnCommand::VibratoSpeed, 0) if argument != 0 => {
+                self.vibrato_speed = argument;
+            }
+            (XmVolumeColumnCommand::VibratoDepth, 0) if argument != 0 => {
+                self.vibrato_depth = argument;
+            }
+            (XmVolumeColumnCommand::VibratoDepth, 1..) => self.vibrato(),
+            (XmVolumeColumnCommand::SetPanning, 0) => self.panning = argument as f32 / 255.0,
+            (XmVolumeColumnCommand::PanSlideLeft, 1..) => self.slide_panning(-(argument as f32)),
+            (XmVolumeColumnCommand::PanSlideRight, 1..) => self.slide_panning(argument as f32),
+            _ => {}
+        }
+    }
+
+    fn process_tick(&mut self, tick: u32) {
+        let Some(slot) = self.pattern_slot_state else {
+            return;
+        };
+
+        if let Some(ref column) = slot.volume_column {
+            self.apply_volume_column(column, tick);
+        }
+
+        if tick > 0 && tone_portamento_speed(slot).is_some() {
+            self.tone_portamento();
+        }
+
+        match slot.effect {
+            Some(XmEffect::Arpeggio(ref a)) => {
+                self.arpeggio_offset = match tick % 3 {
+                    0 => 0,
+                    1 => a.x(),
+                    _ => a.y(),
+                };
+            }
+            Some(XmEffect::Retrigger(a)) if tick > 0 && tick.is_multiple_of(a as u32) => {
+                self.retrigger();
+            }
+            Some(XmEffect::RetriggerWithVolume(_)) if tick > 0 => self.multi_retrigger(),
+            // applied after the volume column, so it wins over a volume set there
+            Some(XmEffect::SetVolume(a)) if tick == 0 => self.set_volume(a),
+            // shares its speed, depth and position with the volume column vibrato
+            Some(XmEffect::Vibrato(ref a)) if tick == 0 => {
+                if a.x() != 0 {
+                    self.vibrato_speed = a.x();
+                }
+                if a.y() != 0 {
+                    self.vibrato_depth = a.y();
+                }
+            }
+            Some(XmEffect::Vibrato(_)) | Some(XmEffect::VolumeSlideVibrato(_)) if tick > 0 => {
+                self.vibrato();
+            }
+            Some(XmEffect::Tremor(ref a)) => self.tremor(a),
+            Some(XmEffect::PanningSlide(ref a)) if tick > 0 => self.panning_slide(a),
+            Some(XmEffect::Panbrello(ref a)) => self.panbrello(a),
+            // works like a note off on the given tick, ticks past the end of the row never come
+            Some(XmEffect::KeyOff(a)) if tick == a as u32 => self.release_note(),
+            _ => {}
+        }
+    }
+
+    fn update_frequency(&mut self, frequency_table: FrequencyTable, sample_rate: u32) {
+        let Some(ref mut instrument_state) = self.instrument_state else {
+            return;
+        };
+
+        let arpeggio_delta = match (&self.note, self.note.transpose(self.arpeggio_offset as i16)) {
+            (
+                XmNote::Note { tone, octave },
+                Some(XmNote::Note {
+                    tone: arp_tone,
+                    octave: arp_octave,
+                }),
+            ) => {
+                frequency_table.period(&arp_tone, arp_octave)
+                    - frequency_table.period(tone, *octave)
+            }
+            _ => 0.0,
+        };
+
+        let period = if self.glissando && self.portamento_target.is_some() {
+            frequency_table.nearest_semitone(instrument_state.period)
+        } else {
+            instrument_state.period
+        };
+
+        let period = period + arpeggio_delta + self.vibrato_offset + self.autovibrato_offset;
+        if instrument_state.frequency_period == Some(period) {
+            return;
+        }
+
+        instrument_state.frequency = frequency_table.frequency(period);
+        instrument_state.step = instrument_state.frequency / sample_rate as f32;
+        instrument_state.frequency_period = Some(period);
+    }
+}
+
+/// How a channel's panning is turned into left/right gains
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum XmPanLaw {
+    /// `sqrt(1 - p)` / `sqrt(p)`, keeps the loudness constant across the stereo field, which
+    /// puts centered channels 3dB down on each side
+    #[default]
+    ConstantPower,
+    /// Centered channels play at full volume on both sides, panning only attenuates the
+    /// opposite side
+    Balance,
+    /// `1 - p` / `p`, the amplitude is split between the sides, which puts centered channels
+    /// 6dB down on each side
+    Linear,
+}
+
+impl XmPanLaw {
+    /// Returns the `(left, right)` gains for `pan_ratio` (0.0 is left, 1.0 is right)
+    pub fn gains(&self, pan_ratio: f32) -> (f32, f32) {
+        match self {
+            Self::ConstantPower => ((1.0 - pan_ratio).sqrt(), pan_ratio.sqrt()),
+            Self::Balance => (
+                ((1.0 - pan_ratio) * 2.0).min(1.0),
+                (pan_ratio * 2.0).min(1.0),
+            ),
+            Self::Linear => (1.0 - pan_ratio, pan_ratio),
+        }
+    }
+}
+
+/// What's done to the mixed output before it's returned, it may leave `-1.0..=1.0` once a few
+/// loud channels add up
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum XmOutputLimiter {
+    /// The mix is returned as is
+    #[default]
+    None,
+    /// Cuts the mix off at `-1.0` and `1.0`
+    HardClip,
+    /// `tanh` curve, which stays close to linear for quiet output and bends loud output into
+    /// `-1.0..1.0` instead of cutting it off
+    SoftClip,
+    /// Divides the mix by the square root of the channel count, then cuts it off like
+    /// `HardClip`
+    Normalize,
+}
+
+impl XmOutputLimiter {
+    /// Limits one side of the mix of `channels_num` channels
+    pub fn apply(&self, sample: f32, channels_num: usize) -> f32 {
+        match self {
+            Self::None => sample,
+            Self::HardClip => sample.clamp(-1.0, 1.0),
+            Self::SoftClip => sample.tanh(),
+            Self::Normalize => (sample / (channels_num.max(1) as f32).sqrt()).clamp(-1.0, 1.0),
+        }
+    }
+}
+
+/// Output options of an `XmPlaybackContext`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct XmPlaybackConfig {
+    pub resampling: XmResamplingType,
+    /// 100 keeps the panning as is, 0 plays every channel in the center
+    pub stereo_separation: u8,
+    /// Multiplies the mixed output
+    pub gain: f32,
+    pub pan_law: XmPanLaw,
+    /// Whether [`XmPlaybackContext::frames`] carries on from the restart position once the song
+    /// ends, `render` always does
+    pub looping: bool,
+    /// Applied to the mix after `gain`
+    pub limiter: XmOutputLimiter,
+}
+
+impl Default for XmPlaybackConfig {
+    fn default() -> Self {
+        Self {
+            resampling: XmResamplingType::default(),
+            stereo_separation: 100,
+            gain: 1.0,
+            pan_law: XmPanLaw::default(),
+            looping: true,
+            limiter: XmOutputLimiter::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum XmSeekError {
+    /// The order is past the end of the song, holds a marker rather than a pattern or isn't
+    /// reached when playing the song from the start
+    InvalidOrder(u32),
+}
+
+/// Reports where playback is in the song, see [`XmPlaybackContext::track_events`]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum XmPlaybackEvent {
+    /// A row starts playing, holds the order and row
+    RowChanged { order: u32, row: u32 },
+    /// Playback moved on to another order, sent before the `RowChanged` of its first row
+    OrderChanged(u32),
+    /// Playback ran past the last order of the song or into an end marker, or jumped back to a
+    /// row that was already played
+    SongEnded,
+    /// Sent right after `SongEnded` with [`XmPlaybackConfig::looping`], playback carries on from
+    /// the restart position or the row jumped to, holds the order it restarts at
+    SongLooped(u32),
+}
+
+/// What a channel is playing, see [`XmPlaybackContext::channel_info`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct XmChannelInfo<'a> {
+    /// 1-based index of the last instrument referenced on the channel
+    pub instrument_index: Option<u8>,
+    /// `None` if the instrument doesn't exist in the module
+    pub instrument_name: Option<&'a str>,
+    /// Last note triggered on the channel, it may have stopped playing since
+    pub note: XmNote,
+    /// Set while a sample is playing, even if it's muted
+    pub playing: bool,
+    pub muted: bool,
+    /// After tremor, the volume envelope and fadeout, in `0.0..=1.0`
+    pub volume: f32,
+    /// After the panning envelope, 0.0 is left and 1.0 is right
+    pub panning: f32,
 }
 
 pub struct XmPlaybackContext<'a> {
-    module: XmModule,
+    module: &'a XmModule,
     sample_rate: u32,
 
     tempo: u16,
     bpm: u16,
     volume: f32,
+    global_volume: f32,
+    pan_law: XmPanLaw,
+    resampling: XmResamplingType,
+    stereo_separation: u8,
+    looping: bool,
+    limiter: XmOutputLimiter,
 
-    current_order: u32,
-    current_row: u32,
+    // the row being played, or the next one once its last tick is processed
+    cursor: XmRowCursor,
+    // counts on through the rows repeated by a pattern delay
     current_tick: u32,
-    left_samples_in_tick: f32,
+    left_samples_in_tick: u32,
+    // fraction of a frame carried over to the next tick, in `1 / (BPM * 2)` frame units
+    tick_remainder: u32,
+    // the tick being played, `current_*` already point at the next one
+    position: (u32, u32, u32),
+    // set once playback wrapped around to the restart position
+    song_ended: bool,
 
-    jump_dest: Option<u8>,
-    jump_row: Option<u8>,
-
-    extra_ticks: u16,
+    // `None` unless events are tracked
+    events: Option<Vec<XmPlaybackEvent>>,
+    // the order the last `RowChanged` was sent for
+    event_order: Option<u32>,
 
     // if a channel is None, then it is muted
     channels: Vec<Option<XmChannelContext<'a>>>,
+    // muted channels are stashed here, and keep playing silently so unmuting resumes in sync
+    muted_channels: Vec<Option<XmChannelContext<'a>>>,
+}
+
+// the module is left out, it's the same one for the whole playback
+impl core::fmt::Debug for XmPlaybackContext<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("XmPlaybackContext")
+            .field("sample_rate", &self.sample_rate)
+            .field("tempo", &self.tempo)
+            .field("bpm", &self.bpm)
+            .field("volume", &self.volume)
+            .field("global_volume", &self.global_volume)
+            .field("pan_law", &self.pan_law)
+            .field("resampling", &self.resampling)
+            .field("stereo_separation", &self.stereo_separation)
+            .field("looping", &self.looping)
+            .field("limiter", &self.limiter)
+            .field("cursor", &self.cursor)
+            .field("current_tick", &self.current_tick)
+            .field("left_samples_in_tick", &self.left_samples_in_tick)
+            .field("tick_remainder", &self.tick_remainder)
+            .field("position", &self.position)
+            .field("channels", &self.channels)
+            .field("muted_channels", &self.muted_channels)
+            .field("events", &self.events)
+            .finish_non_exhaustive()
+    }
 }
 
 impl<'a> XmPlaybackContext<'a> {
-    pub fn new(module: XmModule, sample_rate: u32) -> Self {
+    pub fn new(module: &'a XmModule, sample_rate: u32) -> Self {
+        Self::new_with_config(module, sample_rate, XmPlaybackConfig::default())
+    }
+
+    pub fn new_with_config(
+        module: &'a XmModule,
+        sample_rate: u32,
+        config: XmPlaybackConfig,
+    ) -> Self {
+        let mut cursor = XmRowCursor::new(module);
+        cursor.settle(module);
+        let position = (cursor.order() as u32, cursor.row() as u32, 0);
+
         Self {
             sample_rate,
 
             tempo: module.header.default_tempo,
             bpm: module.header.default_bpm,
-            volume: 1.0,
+            volume: config.gain,
+            global_volume: 1.0,
+            pan_law: config.pan_law,
+            resampling: config.resampling,
+            stereo_separation: config.stereo_separation.min(100),
+            looping: config.looping,
+            limiter: config.limiter,
 
-            current_order: 0,
-            current_row: 0,
+            cursor,
             current_tick: 0,
-            left_samples_in_tick: Self::samples_in_tick(sample_rate, module.header.default_bpm),
-
-            jump_dest: None,
-            jump_row: None,
+            // the first call to `advance` processes the first row right away
+            left_samples_in_tick: 0,
+            tick_remainder: 0,
+            position,
+            song_ended: false,
 
-            extra_ticks: 0,
+            events: None,
+            event_order: None,
 
             channels: vec![Some(XmChannelContext::default()); module.header.channels_num.into()],
+            muted_channels: vec![None; module.header.channels_num.into()],
 
             module,
         }
     }
 
-    fn samples_in_tick(sample_rate: u32, bpm: u16) -> f32 {
-        sample_rate as f32 / bpm as f32 * 0.4
+    /// Moves playback to the start of `row` in `order`, the row is clamped to the pattern
+    /// length. Playback is replayed from the start of the song without rendering, so every
+    /// effect with a lasting impact (tempo, volume, instruments...) is where it would've been.
+    pub fn seek_to(&mut self, order: u32, row: u32) -> Result<(), XmSeekError> {
+        let module = self.module;
+
+        let rows_num = module
+            .pattern_order_table
+            .get(order as usize)
+            .filter(|e| (order as usize) < module.song_length() && !is_order_marker(**e))
+            .map(|e| module.pattern_rows_num(*e) as u32)
+            .ok_or(XmSeekError::InvalidOrder(order))?;
+        let row = row.min(rows_num.saturating_sub(1));
+
+        // the walk goes through the song the same way playback does, so it also tells how many
+        // ticks it takes to get to the target
+        let mut elapsed_ticks = 0;
+        let ticks = module
+            .walk_rows(|current_order, current_row, ticks, _| {
+                if (current_order, current_row) == (order, row) {
+                    return Some(elapsed_ticks);
+                }
+
+                elapsed_ticks += ticks as usize;
+                None
+            })
+            .ok_or(XmSeekError::InvalidOrder(order))?;
+
+        self.tempo = module.header.default_tempo;
+        self.bpm = module.header.default_bpm;
+        self.global_volume = 1.0;
+        self.cursor = XmRowCursor::new(module);
+        self.cursor.settle(module);
+        self.current_tick = 0;
+        self.event_order = None;
+        for (channel, muted) in self.channels.iter_mut().zip(self.muted_channels.iter_mut()) {
+            if channel.is_some() {
+                *channel = Some(XmChannelContext::default());
+            } else {
+                *muted = Some(XmChannelContext::default());
+            }
+        }
+
+        // the replayed rows aren't reported
+        let events = self.events.take();
+        for _ in 0..ticks {
+            self.tick();
+        }
+        self.events = events;
+        self.event_order = None;
+
+        let position = (self.cursor.order() as u32, self.cursor.row() as u32);
+        if (position, self.current_tick) != ((order, row), 0) {
+            return Err(XmSeekError::InvalidOrder(order));
+        }
+
+        // the row at the new position gets processed by the next call to `advance`
+        self.left_samples_in_tick = 0;
+        self.tick_remainder = 0;
+        self.position = (order, row, 0);
+        self.song_ended = false;
+
+        Ok(())
+    }
+
+    /// Renders interleaved stereo frames into `out`, filling `out.len() / 2` frames. Modules
+    /// without any channels render silence.
+    pub fn render(&mut self, out: &mut [f32]) {
+        for frame in out.chunks_exact_mut(2) {
+            self.advance();
+
+            let (left, right) = self.sample();
+            frame[0] = left;
+            frame[1] = right;
+        }
+    }
+
+    /// Same as [`Self::render`], but every frame is downmixed to the average of its left and
+    /// right sides, filling `out.len()` frames. Without [`XmPlaybackConfig::looping`], frames
+    /// past the end of the song are silent.
+    pub fn render_mono_into(&mut self, out: &mut [f32]) {
+        let mut frames = self.frames().map(|(left, right)| (left + right) / 2.0);
+
+        for frame in out.iter_mut() {
+            *frame = frames.next().unwrap_or(0.0);
+        }
+    }
+
+    /// Iterates over `(left, right)` frames, same as [`Self::render`]. Without
+    /// [`XmPlaybackConfig::looping`] the iterator ends with the last tick of the song.
+    pub fn frames(&mut self) -> XmFrames<'_, 'a> {
+        XmFrames { context: self }
+    }
+
+    /// Same as [`Self::render`], but only the given channels are mixed into `out`, the rest
+    /// of the channels keep playing in the background so the engine state stays the same
+    pub fn render_channels(&mut self, channels: &[usize], out: &mut [f32]) {
+        for frame in out.chunks_exact_mut(2) {
+            self.advance();
+
+            let (left, right) = self.mix(|i| channels.contains(&i));
+            frame[0] = left;
+            frame[1] = right;
+        }
+    }
+
+    /// Returns the absolute output of every channel at the current frame, after the channel
+    /// volume but before panning and mixing. Muted channels report 0.0.
+    pub fn channel_levels(&self) -> Vec<f32> {
+        self.channels
+            .iter()
+            .map(|channel| match channel {
+                Some(channel) => {
+                    Self::volume(channel.sample(self.resampling), channel.output_volume()).abs()
+                }
+                None => 0.0,
+            })
+            .collect()
+    }
+
+    /// Returns what every channel is currently playing, muted channels included
+    pub fn channel_info(&self) -> Vec<XmChannelInfo<'a>> {
+        let module = self.module;
+
+        self.channels
+            .iter()
+            .zip(&self.muted_channels)
+            .filter_map(|(channel, muted)| {
+                let channel = channel.as_ref().or(muted.as_ref())?;
+                let instrument = channel
+                    .instrument_index()
+                    .and_then(|e| lookup_instrument(module, e));
+
+                Some(XmChannelInfo {
+                    instrument_index: channel.instrument_index(),
+                    instrument_name: instrument.map(|e| e.0.name.as_str()),
+                    note: channel.note().clone(),
+                    playing: channel.is_playing(),
+                    muted: muted.is_some(),
+                    volume: channel.output_volume(),
+                    panning: channel.output_panning(),
+                })
+            })
+            .collect()
+    }
+
+    /// Silences a channel, it keeps playing in the background until it's unmuted
+    pub fn mute_channel(&mut self, index: usize) {
+        if let (Some(channel), Some(muted)) = (
+            self.channels.get_mut(index),
+            self.muted_channels.get_mut(index),
+        ) {
+            if channel.is_some() {
+                *muted = channel.take();
+            }
+        }
+    }
+
+    pub fn unmute_channel(&mut self, index: usize) {
+        if let (Some(channel), Some(muted)) = (
+            self.channels.get_mut(index),
+            self.muted_channels.get_mut(index),
+        ) {
+            if muted.is_some() {
+                *channel = muted.take();
+            }
+        }
+    }
+
+    /// Mutes every channel but `index`, which gets unmuted
+    pub fn solo_channel(&mut self, index: usize) {
+        for i in 0..self.channels.len() {
+            if i == index {
+                self.unmute_channel(i);
+            } else {
+                self.mute_channel(i);
+            }
+        }
+    }
+
+    pub fn set_pan_law(&mut self, pan_law: XmPanLaw) {
+        self.pan_law = pan_law;
+    }
+
+    /// Starts or stops queueing up [`XmPlaybackEvent`]s as the song is rendered, they're
+    /// collected with [`Self::drain_events`]. Rows skipped over by [`Self::seek_to`] aren't
+    /// reported. Stopping throws away the events which weren't drained yet.
+    pub fn track_events(&mut self, enabled: bool) {
+        self.events = enabled.then(|| self.events.take().unwrap_or_default());
+    }
+
+    /// Takes the queued up events, oldest first
+    pub fn drain_events(&mut self) -> impl Iterator<Item = XmPlaybackEvent> + '_ {
+        self.events.iter_mut().flat_map(|e| e.drain(..))
+    }
+
+    fn emit(&mut self, event: XmPlaybackEvent) {
+        if let Some(events) = self.events.as_mut() {
+            events.push(event);
+        }
+    }
+
+    /// Whole frames the next tick lasts, the leftover fraction is carried over in
+    /// `tick_remainder` so that tick `n` always starts at frame `n * frames_per_tick` rounded
+    /// down, whatever the sample rate
+    fn samples_in_tick(&mut self) -> u32 {
+        // FT2 manual says number of ticks / second = BPM * 0.4, so a tick lasts
+        // sample_rate * 5 / (BPM * 2) frames
+        let denominator = self.bpm.max(1) as u64 * 2;
+        let total = self.sample_rate as u64 * 5 + (self.tick_remainder as u64).min(denominator - 1);
+
+        self.tick_remainder = (total % denominator) as u32;
+        (total / denominator) as u32
     }
 
     fn volume(sample: f32, volume: f32) -> f32 {
         sample * volume
     }
 
-    fn pan(sample: f32, pan_ratio: f32) -> (f32, f32) {
-        let left_vol = (1.0 - pan_ratio).sqrt();
-        let right_vol = pan_ratio.sqrt();
+    fn pan(&self, sample: f32, pan_ratio: f32) -> (f32, f32) {
+        let pan_ratio = 0.5 + (pan_ratio - 0.5) * self.stereo_separation as f32 / 100.0;
+        let (left_vol, right_vol) = self.pan_law.gains(pan_ratio);
 
         (sample * left_vol, sample * right_vol)
     }
 
-    fn tick(&mut self) {
-        // FT2 manual says number of ticks / second = BPM * 0.4
-        self.left_samples_in_tick += Self::samples_in_tick(self.sample_rate, self.bpm);
+    /// The `(order, row, tick)` of the last rendered frame, or the one playback starts at if
+    /// nothing was rendered since creating the context or seeking
+    pub fn position(&self) -> (u32, u32, u32) {
+        self.position
+    }
+
+    pub fn current_order(&self) -> u32 {
+        self.position.0
+    }
+
+    pub fn current_row(&self) -> u32 {
+        self.position.1
+    }
+
+    pub fn current_tick(&self) -> u32 {
+        self.position.2
+    }
+
+    /// Ticks per row
+    pub fn tempo(&self) -> u16 {
+        self.tempo
+    }
+
+    pub fn bpm(&self) -> u16 {
+        self.bpm
+    }
+
+    #[cfg(test)]
+    pub(crate) fn global_volume(&self) -> f32 {
+        self.global_volume
+    }
+
+    #[cfg(test)]
+    pub(crate) fn channel(&self, index: usize) -> Option<&XmChannelContext<'a>> {
+        self.channels.get(index)?.as_ref()
+    }
+
+    fn current_pattern_row(&self) -> Option<&'a XmPatternRow> {
+        let module = self.module;
+        let pattern_index = *module.pattern_order_table.get(self.cursor.order())?;
+
+        module
+            .patterns
+            .get(pattern_index as usize)?
+            .1
+             .0
+            .get(self.cursor.row())
+    }
+
+    /// Moves on from `row` once its last tick is processed, following jumps, breaks and loops
+    fn next_row(&mut self, row: Option<&XmPatternRow>) {
+        self.cursor.leave_row(row.map_or(&[][..], |e| &e.0[..]));
+
+        if self.cursor.settle(self.module) {
+            self.song_ended = true;
+            self.emit(XmPlaybackEvent::SongEnded);
+            if self.looping {
+                self.emit(XmPlaybackEvent::SongLooped(self.cursor.order() as u32));
+            }
+        }
+    }
+
+    /// Applies the effects of a row which affect the whole module rather than a single channel
+    fn process_row(&mut self, row: &XmPatternRow) {
+        for slot in row.0.iter() {
+            match slot.effect {
+                // values below 0x20 set the ticks per row, the rest set the BPM
+                Some(XmEffect::SetTempo(0)) => {}
+                Some(XmEffect::SetTempo(a)) if a < 0x20 => self.tempo = a as u16,
+                Some(XmEffect::SetTempo(a)) => self.bpm = a as u16,
+                Some(XmEffect::SetGlobalVolume(a)) => {
+                    self.global_volume = a.min(0x40) as f32 / 64.0;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Applies the module-wide effects that keep going on every tick after the first one
+    fn process_tick(&mut self, row: &XmPatternRow) {
+        for slot in row.0.iter() {
+            if let Some(XmEffect::GlobalVolumeSlide(ref a)) = slot.effect {
+                // sliding up takes precedence when both nibbles are set
+                let delta = if a.x() != 0 {
+                    a.x() as f32
+                } else {
+                    -(a.y() as f32)
+                };
+
+                self.global_volume = (self.global_volume + delta / 64.0).clamp(0.0, 1.0);
+            }
+        }
+    }
+
+    pub(crate) fn tick(&mut self) {
+        let module = self.module;
+        let row = self.current_pattern_row();
+        let (order, row_index) = (self.cursor.order() as u32, self.cursor.row() as u32);
+        self.position = (order, row_index, self.current_tick);
+
+        if self.current_tick == 0 {
+            if self.event_order != Some(order) {
+                self.emit(XmPlaybackEvent::OrderChanged(order));
+            }
+            self.emit(XmPlaybackEvent::RowChanged {
+                order,
+                row: row_index,
+            });
+            self.event_order = Some(order);
+        }
+
+        let channels = self.channels.iter_mut().zip(self.muted_channels.iter_mut());
+        for (i, (channel, muted)) in channels.enumerate() {
+            let Some(channel) = channel.as_mut().or(muted.as_mut()) else {
+                continue;
+            };
+
+            if self.current_tick == 0 {
+                channel.process_row(module, row.and_then(|e| e.0.get(i)));
+            }
+
+            channel.process_tick(self.current_tick);
+            channel.update_envelopes();
+            channel.update_fadeout();
+            channel.update_autovibrato();
+            channel.update_frequency(self.module.header.frequency_table(), self.sample_rate);
+        }
+
+        if let Some(row) = row {
+            if self.current_tick == 0 {
+                self.process_row(row);
+            } else {
+                self.process_tick(row);
+            }
+        }
+
+        // `EEx` plays the row that many more times without triggering its notes again
+        let delay = row.map_or(0, |e| pattern_delay(&e.0)) as u32;
+        self.current_tick += 1;
+        if self.current_tick >= self.tempo as u32 * (1 + delay) {
+            self.current_tick = 0;
+            self.next_row(row);
+        }
 
-        todo!();
+        self.left_samples_in_tick += self.samples_in_tick();
     }
 
     fn advance(&mut self) {
-        if self.left_samples_in_tick <= 0.0 {
+        let channels = self.channels.iter_mut().zip(self.muted_channels.iter_mut());
+        for (channel, muted) in channels {
+            let Some(channel) = channel.as_mut().or(muted.as_mut()) else {
+                continue;
+            };
+
+            channel.advance();
+        }
+
+        // ticks shorter than a frame at very low sample rates are processed without being heard
+        while self.left_samples_in_tick == 0 && self.sample_rate > 0 {
             self.tick();
         }
 
-        self.left_samples_in_tick -= 1.0;
+        self.left_samples_in_tick = self.left_samples_in_tick.saturating_sub(1);
     }
 
     fn sample(&self) -> (f32, f32) {
+        self.mix(|_| true)
+    }
+
+    /// Mixes the current output of every channel for which `filter` returns `true`
+    fn mix(&self, filter: impl Fn(usize) -> bool) -> (f32, f32) {
         let mut out_left = 0.0f32;
         let mut out_right = 0.0f32;
 
         for (i, channel) in self.channels.iter().enumerate() {
             let Some(channel) = channel else { continue };
+            if !filter(i) {
+                continue;
+            }
+
+            let ch_sample = Self::volume(channel.sample(self.resampling), channel.output_volume());
+            let (left, mut right) = self.pan(ch_sample, channel.output_panning());
+            if channel.surround {
+                right = -right;
+            }
+
+            out_left += left;
+            out_right += right;
+        }
+
+        let volume = self.volume * self.global_volume;
+        let channels_num = self.channels.len();
+        (
+            self.limiter
+                .apply(Self::volume(out_left, volume), channels_num),
+            self.limiter
+                .apply(Self::volume(out_right, volume), channels_num),
+        )
+    }
+}
+
+/// Iterator over the frames of an `XmPlaybackContext`, see [`XmPlaybackContext::frames`]
+#[derive(Debug)]
+pub struct XmFrames<'c, 'a> {
+    context: &'c mut XmPlaybackContext<'a>,
+}
+
+impl Iterator for XmFrames<'_, '_> {
+    type Item = (f32, f32);
 
-            let ch_sample = channel.sample();
+    fn next(&mut self) -> Option<Self::Item> {
+        let context = &mut *self.context;
 
+        // the wrap is noticed at the end of the last tick, its frames still have to be played
+        if !context.looping && context.song_ended && context.left_samples_in_tick == 0 {
+            return None;
         }
 
-        todo!();
+        context.advance();
+        Some(context.sample())
     }
 }
@@ -1,7 +1,8 @@
 use bitfield_struct::bitfield;
 use nom::{combinator::cond, error::ParseError, sequence::tuple, IResult};
 
-#[bitfield(u8, order = Lsb)]
+#[bitfield(u8, order = Msb)]
+#[derive(Eq, PartialEq)]
 pub struct DoubleU4 {
     #[bits(4)]
     pub x: u8,
@@ -10,61 +11,68 @@ pub struct DoubleU4 {
     pub y: u8,
 }
 
-#[derive(Clone)]
+#[cfg(feature = "serde")]
+bitfield_serde!(DoubleU4, u8);
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum XmEffect {
-    Arpeggio(DoubleU4),                  // 0 0x00(xy)
-    PortamentoUp(u8),                    // 1 0x01(xx)
-    PortamentoDown(u8),                  // 2 0x02(xx)
-    TonePortamento(u8),                  // 3 0x03(xx)
-    Vibrato(DoubleU4),                   // 4 0x04(xy)
-    VolumeSlideTonePortamento(DoubleU4), // 5 0x05(xy)
-    VolumeSlideVibrato(DoubleU4),        // 6 0x06(xy)
-    Tremolo(DoubleU4),                   // 7 0x07(xy)
-    SetPanningFine(u8),                  // 8 0x08(xx)
-    SampleOffset(u8),                    // 9 0x09(xx)
-    VolumeSlide(DoubleU4),               // A 0x0A(xy)
-    PositionJump(u8),                    // B 0x0B(xx)
-    SetVolume(u8),                       // C 0x0C(xx)
-    PatternBreak(u8),                    // D 0x0D(xx)
-    FinePortamentoUp(u8),                // E 0x0E(1x)
-    FinePortamentoDown(u8),              // E 0x0E(2x)
-    GlissandoControl(u8),                // E 0x0E(3x)
-    SetVibratoWaveform(u8),              // E 0x0E(4x)
-    SetFinetune(u8),                     // E 0x0E(5x)
-    PatternLoopStart,                    // E 0x0E(60)
-    PatternLoop(u8),                     // E 0x0E(6x)
-    SetTremoloWaveform(u8),              // E 0x0E(7x)
-    SetPanning(u8),                      // E 0x0E(8x)
-    Retrigger(u8),                       // E 0x0E(9x)
-    FineVolumeSlideUp(u8),               // E 0x0E(Ax)
-    FineVolumeSlideDown(u8),             // E 0x0E(Bx)
-    NoteCut(u8),                         // E 0x0E(Cx)
-    NoteDelay(u8),                       // E 0x0E(Dx)
-    PatternDelay(u8),                    // E 0x0E(Ex)
-    SetActiveMacro(u8),                  // E 0x0E(Fx) NOTE: ModPlug hack
-    SetTempo(u8),                        // F 0x0F(xx)
-    SetGlobalVolume(u8),                 // G 0x10(xx)
-    GlobalVolumeSlide(DoubleU4),         // H 0x11(xy)
-    KeyOff(u8),                          // K 0x14(xx)
-    SetEnvelopePosition(u8),             // L 0x15(xx)
-    PanningSlide(DoubleU4),              // P 0x19(xy)
-    RetriggerWithVolume(DoubleU4),       // R 0x1B(xy)
-    Tremor(DoubleU4),                    // T 0x1D(xy)
-    ExtraFinePortamentoUp(u8),           // X 0x21(1x) NOTE: ModPlug hack
-    ExtraFinePortamentoDown(u8),         // X 0x21(2x) NOTE: ModPlug hack
-    SetPanbrelloWaveform(u8),            // X 0x21(5x) NOTE: ModPlug hack
-    FinePatternDelay(u8),                // X 0x21(6x) NOTE: ModPlug hack
-    SoundControl(u8),                    // X 0x21(9x) NOTE: ModPlug hack
-    HighOffset(u8),                      // X 0x21(Ax) NOTE: ModPlug hack
-    Panbrello(DoubleU4),                 // Y 0x22(xy) NOTE: ModPlug hack
-    MidiMacro(u8),                       // Z 0x23(xx) NOTE: ModPlug hack
-    SmoothMidiMacro(u8),                 // \ 0x24(xx) NOTE: ModPlug hack
+    Arpeggio(DoubleU4),                     // 0 0x00(xy)
+    PortamentoUp(u8),                       // 1 0x01(xx)
+    PortamentoDown(u8),                     // 2 0x02(xx)
+    TonePortamento(u8),                     // 3 0x03(xx)
+    Vibrato(DoubleU4),                      // 4 0x04(xy)
+    VolumeSlideTonePortamento(DoubleU4),    // 5 0x05(xy)
+    VolumeSlideVibrato(DoubleU4),           // 6 0x06(xy)
+    Tremolo(DoubleU4),                      // 7 0x07(xy)
+    SetPanningFine(u8),                     // 8 0x08(xx)
+    SampleOffset(u8),                       // 9 0x09(xx)
+    VolumeSlide(DoubleU4),                  // A 0x0A(xy)
+    PositionJump(u8),                       // B 0x0B(xx)
+    SetVolume(u8),                          // C 0x0C(xx)
+    PatternBreak(u8),                       // D 0x0D(xx)
+    FinePortamentoUp(u8),                   // E 0x0E(1x)
+    FinePortamentoDown(u8),                 // E 0x0E(2x)
+    GlissandoControl(u8),                   // E 0x0E(3x)
+    SetVibratoWaveform(u8),                 // E 0x0E(4x)
+    SetFinetune(u8),                        // E 0x0E(5x)
+    PatternLoopStart,                       // E 0x0E(60)
+    PatternLoop(u8),                        // E 0x0E(6x)
+    SetTremoloWaveform(u8),                 // E 0x0E(7x)
+    SetPanning(u8),                         // E 0x0E(8x)
+    Retrigger(u8),                          // E 0x0E(9x)
+    FineVolumeSlideUp(u8),                  // E 0x0E(Ax)
+    FineVolumeSlideDown(u8),                // E 0x0E(Bx)
+    NoteCut(u8),                            // E 0x0E(Cx)
+    NoteDelay(u8),                          // E 0x0E(Dx)
+    PatternDelay(u8),                       // E 0x0E(Ex)
+    SetActiveMacro(u8),                     // E 0x0E(Fx) NOTE: ModPlug hack
+    SetTempo(u8),                           // F 0x0F(xx)
+    SetGlobalVolume(u8),                    // G 0x10(xx)
+    GlobalVolumeSlide(DoubleU4),            // H 0x11(xy)
+    KeyOff(u8),                             // K 0x14(xx)
+    SetEnvelopePosition(u8),                // L 0x15(xx)
+    PanningSlide(DoubleU4),                 // P 0x19(xy)
+    RetriggerWithVolume(DoubleU4),          // R 0x1B(xy)
+    Tremor(DoubleU4),                       // T 0x1D(xy)
+    ExtraFinePortamentoUp(u8),              // X 0x21(1x) NOTE: ModPlug hack
+    ExtraFinePortamentoDown(u8),            // X 0x21(2x) NOTE: ModPlug hack
+    SetPanbrelloWaveform(u8),               // X 0x21(5x) NOTE: ModPlug hack
+    FinePatternDelay(u8),                   // X 0x21(6x) NOTE: ModPlug hack
+    SoundControl(u8),                       // X 0x21(9x) NOTE: ModPlug hack
+    HighOffset(u8),                         // X 0x21(Ax) NOTE: ModPlug hack
+    Panbrello(DoubleU4),                    // Y 0x22(xy) NOTE: ModPlug hack
+    MidiMacro(u8),                          // Z 0x23(xx) NOTE: ModPlug hack
+    SmoothMidiMacro(u8),                    // \ 0x24(xx) NOTE: ModPlug hack
+    Unknown { command: u8, parameter: u8 }, // anything else, kept as is
 }
 
-#[derive(Clone)]
-pub struct XmVolumeColumn(u8);
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct XmVolumeColumn(pub(crate) u8);
 
 #[repr(u8)]
+#[derive(Debug)]
 pub enum XmVolumeColumnCommand {
     SetVolume,       // 0x00..0x50 axx
     VolumeSlideUp,   // 0x60..0x6F bxx
@@ -80,17 +88,23 @@ pub enum XmVolumeColumnCommand {
     Unknown,
 }
 
-pub(crate) fn parse_volume_column(data: &[u8]) -> IResult<&[u8], XmVolumeColumn> {
-    let (input, byte) = nom::number::complete::u8(data)?;
-    let byte = XmVolumeColumn::from(byte);
+/// The raw byte is kept around, so in lenient mode unknown commands are stored as
+/// `XmVolumeColumnCommand::Unknown`
+pub(crate) fn parse_volume_column(
+    strict: bool,
+) -> impl FnMut(&[u8]) -> IResult<&[u8], XmVolumeColumn> {
+    move |data| {
+        let (input, byte) = nom::number::complete::u8(data)?;
+        let byte = XmVolumeColumn::from(byte);
 
-    if let XmVolumeColumnCommand::Unknown = byte.command() {
-        Err(nom::Err::Error(nom::error::Error::from_error_kind(
-            data,
-            nom::error::ErrorKind::Verify,
-        )))
-    } else {
-        Ok((input, byte))
+        if strict && matches!(byte.command(), XmVolumeColumnCommand::Unknown) {
+            Err(nom::Err::Error(nom::error::Error::from_error_kind(
+                data,
+                nom::error::ErrorKind::Verify,
+            )))
+        } else {
+            Ok((input, byte))
+        }
     }
 }
 
@@ -133,6 +147,9 @@ pub(crate) fn parse_effect(
             (0x0B, a, _) => Ok((input, Some(XmEffect::PositionJump(a)))),      // B 0x0B(xx)
             (0x0C, a, _) => Ok((input, Some(XmEffect::SetVolume(a)))),         // C 0x0C(xx)
             (0x0D, a, _) => Ok((input, Some(XmEffect::PatternBreak(a)))),      // D 0x0D(xx)
+            // E0x is the Amiga filter toggle, which FT2 ignores, so it ends up as `Unknown`.
+            // E60 marks the loop start, so `PatternLoop` never holds a zero count. EFx is unused
+            // by FT2 and doesn't collide with any of its sub-effects.
             (0x0E, a, 0x1) => Ok((input, Some(XmEffect::FinePortamentoUp(a & 0b1111)))), // E 0x0E(1x)
             (0x0E, a, 0x2) => Ok((input, Some(XmEffect::FinePortamentoDown(a & 0b1111)))), // E 0x0E(2x)
             (0x0E, a, 0x3) => Ok((input, Some(XmEffect::GlissandoControl(a & 0b1111)))), // E 0x0E(3x)
@@ -166,16 +183,13 @@ pub(crate) fn parse_effect(
             (0x22, a, _) => Ok((input, Some(XmEffect::Panbrello(DoubleU4(a))))), // Y 0x22(xy) NOTE: ModPlug hack
             (0x23, a, _) => Ok((input, Some(XmEffect::MidiMacro(a)))), // Z 0x23(xx) NOTE: ModPlug hack
             (0x24, a, _) => Ok((input, Some(XmEffect::SmoothMidiMacro(a)))), // \ 0x24(xx) NOTE: ModPlug hack
-            (_, _a, _) => Err(nom::Err::Error(nom::error::Error::from_error_kind(
-                data,
-                nom::error::ErrorKind::Verify,
-            ))),
+            (command, parameter, _) => Ok((input, Some(XmEffect::Unknown { command, parameter }))),
         }
     }
 }
 
-impl std::fmt::Display for XmEffect {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Display for XmEffect {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             XmEffect::Arpeggio(a) => write!(f, "0{:02X}", a.into_bits()),
             XmEffect::PortamentoUp(a) => write!(f, "1{:02X}", a),
@@ -224,6 +238,11 @@ impl std::fmt::Display for XmEffect {
             XmEffect::Panbrello(a) => write!(f, "Y{:02X}", a.into_bits()),
             XmEffect::MidiMacro(a) => write!(f, "Z{:02X}", a),
             XmEffect::SmoothMidiMacro(a) => write!(f, "\\{:02X}", a),
+            // one digit like the other commands, commands past `Z` have none of their own
+            XmEffect::Unknown { command, parameter } => {
+                let digit = char::from_digit(*command as u32, 36).unwrap_or('?');
+                write!(f, "{}{:02X}", digit.to_ascii_uppercase(), parameter)
+            }
         }
     }
 }
@@ -262,14 +281,15 @@ impl XmVolumeColumn {
         let value = self.0 & 0b0000_1111;
 
         match self.command() {
-            XmVolumeColumnCommand::SetVolume => (value) | ((self.command_raw() - 1) << 4),
+            XmVolumeColumnCommand::SetVolume => self.0.saturating_sub(0x10),
             XmVolumeColumnCommand::VolumeSlideDown => value,
             XmVolumeColumnCommand::VolumeSlideUp => value,
             XmVolumeColumnCommand::FineVolumeDown => value,
             XmVolumeColumnCommand::FineVolumeUp => value,
             XmVolumeColumnCommand::VibratoSpeed => value,
             XmVolumeColumnCommand::VibratoDepth => value,
-            XmVolumeColumnCommand::SetPanning => 2 + value * 4,
+            // FT2 spreads the nibble over the full panning range, 0xCF ends up at 0xF0
+            XmVolumeColumnCommand::SetPanning => value << 4,
             XmVolumeColumnCommand::PanSlideLeft => value,
             XmVolumeColumnCommand::PanSlideRight => value,
             XmVolumeColumnCommand::TonePortamento => value,
@@ -278,7 +298,11 @@ impl XmVolumeColumn {
     }
 
     pub fn command(&self) -> XmVolumeColumnCommand {
-        XmVolumeColumnCommand::from_bits(self.command_raw())
+        match self.0 {
+            // set volume stops at 0x50, FT2 does nothing with the bytes past it
+            0x51..=0x5F => XmVolumeColumnCommand::Unknown,
+            _ => XmVolumeColumnCommand::from_bits(self.command_raw()),
+        }
     }
 
     fn command_raw(&self) -> u8 {
@@ -286,8 +310,8 @@ impl XmVolumeColumn {
     }
 }
 
-impl std::fmt::Display for XmVolumeColumnCommand {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Display for XmVolumeColumnCommand {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             XmVolumeColumnCommand::SetVolume => write!(f, "v"),
             XmVolumeColumnCommand::VolumeSlideDown => write!(f, "d"),
@@ -305,8 +329,12 @@ impl std::fmt::Display for XmVolumeColumnCommand {
     }
 }
 
-impl std::fmt::Display for XmVolumeColumn {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}{:0>2}", self.command(), self.argument())
+impl core::fmt::Display for XmVolumeColumn {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.command() {
+            // panning is shown the same way as with `8xx`
+            XmVolumeColumnCommand::SetPanning => write!(f, "p{:02X}", self.argument()),
+            command => write!(f, "{}{:0>2}", command, self.argument()),
+        }
     }
 }
@@ -0,0 +1,153 @@
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    effect::{XmEffect, XmVolumeColumnCommand},
+    note::XmNote,
+    XmModule,
+};
+
+/// MIDI ticks per quarter note. Every XM tick becomes a MIDI tick, so at the default 6 ticks
+/// per row a quarter note lasts 4 rows, and the XM BPM carries over as is.
+const TICKS_PER_QUARTER: u16 = 24;
+
+/// XM samples play at full volume unless the volume column says otherwise
+const DEFAULT_VELOCITY: u8 = 127;
+
+/// The MIDI channels XM channels are put on in order, leaving out channel 10 (index 9) since
+/// General MIDI plays it as percussion
+const MIDI_CHANNELS: [u8; 15] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 10, 11, 12, 13, 14, 15];
+
+/// A track event at an absolute time in ticks
+type Event = (u32, Vec<u8>);
+
+/// Converts the note data of the song, played once in order table sequence, into a format 1
+/// Standard MIDI File. The first track holds the tempo changes, followed by a track for every
+/// XM channel, each on a MIDI channel of its own. There are only 15 of those besides the
+/// percussion channel, so channels past the 15th are dropped with a warning. Notes are mapped
+/// through `XmNote::to_midi` and the volume column sets the velocity, every other effect is
+/// dropped.
+pub fn to_midi(module: &XmModule) -> Vec<u8> {
+    let channels_num = module.header.channels_num as usize;
+    if channels_num > MIDI_CHANNELS.len() {
+        log::warn!(
+            "only the first {} of {} channels fit into MIDI channels, the rest are dropped",
+            MIDI_CHANNELS.len(),
+            channels_num
+        );
+    }
+    let channels_num = channels_num.min(MIDI_CHANNELS.len());
+
+    let mut tempo_track: Vec<Event> = vec![(0, tempo_event(module.header.default_bpm))];
+    let mut tracks: Vec<Vec<Event>> = vec![vec![]; channels_num];
+    let mut playing: Vec<Option<u8>> = vec![None; channels_num];
+
+    let mut tempo = module.header.default_tempo;
+    let mut bpm = module.header.default_bpm;
+    let mut time = 0u32;
+
+    for (_, rows) in module.ordered_patterns() {
+        for row in rows {
+            for slot in row {
+                match slot.effect {
+                    Some(XmEffect::SetTempo(0)) => {}
+                    Some(XmEffect::SetTempo(a)) if a < 0x20 => tempo = a as u16,
+                    Some(XmEffect::SetTempo(a)) if a as u16 != bpm => {
+                        bpm = a as u16;
+                        tempo_track.push((time, tempo_event(bpm)));
+                    }
+                    _ => {}
+                }
+            }
+
+            for (i, slot) in row.iter().enumerate().take(channels_num) {
+                let channel = MIDI_CHANNELS[i];
+                let stops = matches!(slot.note, XmNote::Note { .. } | XmNote::NoteOff)
+                    || matches!(slot.effect, Some(XmEffect::KeyOff(_)));
+
+                if stops {
+                    if let Some(note) = playing[i].take() {
+                        tracks[i].push((time, vec![0x80 | channel, note, 0]));
+                    }
+                }
+
+                if let Some(note) = slot.note.to_midi() {
+                    let velocity = match slot.volume_column {
+                        Some(ref column)
+                            if column.0 >= 0x10
+                                && matches!(column.command(), XmVolumeColumnCommand::SetVolume) =>
+                        {
+                            (column.argument().min(0x40) * 2).min(127)
+                        }
+                        _ => DEFAULT_VELOCITY,
+                    };
+
+                    // a note-on with a velocity of zero counts as a note-off
+                    tracks[i].push((time, vec![0x90 | channel, note, velocity.max(1)]));
+                    playing[i] = Some(note);
+                }
+            }
+
+            time += tempo as u32;
+        }
+    }
+
+    for (i, note) in playing.into_iter().enumerate() {
+        if let Some(note) = note {
+            tracks[i].push((time, vec![0x80 | MIDI_CHANNELS[i], note, 0]));
+        }
+    }
+
+    let mut out = vec![];
+    out.extend_from_slice(b"MThd");
+    out.extend_from_slice(&6u32.to_be_bytes());
+    // format 1, every track plays at once
+    out.extend_from_slice(&1u16.to_be_bytes());
+    out.extend_from_slice(&(channels_num as u16 + 1).to_be_bytes());
+    out.extend_from_slice(&TICKS_PER_QUARTER.to_be_bytes());
+
+    write_track(&mut out, &tempo_track);
+    for track in tracks.iter() {
+        write_track(&mut out, track);
+    }
+
+    out
+}
+
+/// Set tempo meta event, in microseconds per quarter note
+fn tempo_event(bpm: u16) -> Vec<u8> {
+    let tempo = 60_000_000 / bpm.max(1) as u32;
+    let [_, a, b, c] = tempo.to_be_bytes();
+
+    vec![0xFF, 0x51, 0x03, a, b, c]
+}
+
+fn write_track(out: &mut Vec<u8>, events: &[Event]) {
+    let mut data = vec![];
+    let mut previous = 0;
+
+    for (time, event) in events {
+        write_variable_length(&mut data, time - previous);
+        data.extend_from_slice(event);
+        previous = *time;
+    }
+
+    // end of track
+    data.extend_from_slice(&[0x00, 0xFF, 0x2F, 0x00]);
+
+    out.extend_from_slice(b"MTrk");
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(&data);
+}
+
+/// Big endian base 128, with the top bit set on every byte but the last
+pub(crate) fn write_variable_length(out: &mut Vec<u8>, value: u32) {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    let mut value = value >> 7;
+
+    while value > 0 {
+        bytes.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+
+    out.extend(bytes.iter().rev());
+}
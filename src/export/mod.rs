@@ -0,0 +1,4 @@
+#[cfg(feature = "wav")]
+pub mod wav;
+
+pub mod midi;
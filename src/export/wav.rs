@@ -0,0 +1,191 @@
+use std::io::Write;
+
+use crate::{
+    context::{XmPlaybackConfig, XmPlaybackContext},
+    instrument::XmSamplePcmData,
+    XmModule, XmSample,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WavSampleFormat {
+    Pcm16,
+    Float32,
+}
+
+impl WavSampleFormat {
+    const fn format_tag(self) -> u16 {
+        match self {
+            WavSampleFormat::Pcm16 => 1,
+            WavSampleFormat::Float32 => 3,
+        }
+    }
+
+    const fn bytes_per_sample(self) -> u16 {
+        match self {
+            WavSampleFormat::Pcm16 => 2,
+            WavSampleFormat::Float32 => 4,
+        }
+    }
+}
+
+/// Renders the song once from the first order, without looping, and writes it out as a stereo
+/// WAV file. Samples are clipped to `-1.0..=1.0`. The song is rendered to memory first, since
+/// the header holds the size of the data, and is cut off at [`XmModule::duration`] in case
+/// pattern loops never settle. Fails with `InvalidInput` if `sample_rate` is 0, and with
+/// `InvalidData` if the song is too long to fit in a WAV file.
+pub fn render_to_wav<W: Write>(
+    module: &XmModule,
+    sample_rate: u32,
+    format: WavSampleFormat,
+    mut out: W,
+) -> std::io::Result<()> {
+    if sample_rate == 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "the sample rate must be above 0",
+        ));
+    }
+
+    let duration = module.duration(sample_rate).as_secs_f64();
+    let frames = (duration * sample_rate as f64).round() as usize;
+
+    let config = XmPlaybackConfig {
+        looping: false,
+        ..Default::default()
+    };
+    let mut context = XmPlaybackContext::new_with_config(module, sample_rate, config);
+
+    let mut bytes = vec![];
+    for (left, right) in context.frames().take(frames) {
+        for sample in [left, right].map(|e| e.clamp(-1.0, 1.0)) {
+            match format {
+                WavSampleFormat::Pcm16 => {
+                    bytes.extend_from_slice(&((sample * i16::MAX as f32) as i16).to_le_bytes())
+                }
+                WavSampleFormat::Float32 => bytes.extend_from_slice(&sample.to_le_bytes()),
+            }
+        }
+    }
+
+    let too_long = || {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "the song is too long to fit in a WAV file",
+        )
+    };
+    let data_size = u32::try_from(bytes.len()).map_err(|_| too_long())?;
+    let riff_size = data_size.checked_add(4 + 8 + 16 + 8).ok_or_else(too_long)?;
+
+    out.write_all(b"RIFF")?;
+    out.write_all(&riff_size.to_le_bytes())?;
+    out.write_all(b"WAVE")?;
+
+    write_fmt_chunk(
+        &mut out,
+        format.format_tag(),
+        2,
+        sample_rate,
+        format.bytes_per_sample(),
+    )?;
+
+    out.write_all(b"data")?;
+    out.write_all(&data_size.to_le_bytes())?;
+    out.write_all(&bytes)
+}
+
+/// Rips samples out of a module, implemented for `XmSample`
+pub trait XmSampleWavExt {
+    /// Writes the sample as a mono PCM WAV file playing at the rate C-4 would play it at, with
+    /// its loop stored in a `smpl` chunk
+    fn to_wav<W: Write>(&self, out: W) -> std::io::Result<()>;
+}
+
+impl XmSampleWavExt for XmSample {
+    fn to_wav<W: Write>(&self, mut out: W) -> std::io::Result<()> {
+        let (header, data) = self;
+
+        // 8 bit WAV data is unsigned
+        let (bytes, bytes_per_sample) = match data {
+            XmSamplePcmData::Bit8Data(v) => (v.iter().map(|e| (*e as u8) ^ 0x80).collect(), 1),
+            XmSamplePcmData::Bit16Data(v) => (
+                v.iter().flat_map(|e| e.to_le_bytes()).collect::<Vec<_>>(),
+                2,
+            ),
+        };
+        let sample_rate = sample_rate(header.finetune, header.relative_note_num);
+
+        // the loop is stored in bytes, `smpl` wants inclusive sample frames
+        let frames = data.len() as u32;
+        let loop_start = (header.loop_start / bytes_per_sample as u32).min(frames);
+        let loop_end = (header.loop_start.saturating_add(header.loop_length)
+            / bytes_per_sample as u32)
+            .min(frames);
+        let has_loop = header.is_looping() && loop_end > loop_start;
+
+        let smpl_size = if has_loop { 8 + 36 + 24 } else { 0 };
+        let data_size = bytes.len() as u32;
+        // chunks are padded to an even size
+        let padding = data_size % 2;
+
+        out.write_all(b"RIFF")?;
+        out.write_all(&(4 + 8 + 16 + 8 + data_size + padding + smpl_size).to_le_bytes())?;
+        out.write_all(b"WAVE")?;
+
+        write_fmt_chunk(&mut out, 1, 1, sample_rate, bytes_per_sample)?;
+
+        out.write_all(b"data")?;
+        out.write_all(&data_size.to_le_bytes())?;
+        out.write_all(&bytes)?;
+        if padding != 0 {
+            out.write_all(&[0])?;
+        }
+
+        if has_loop {
+            out.write_all(b"smpl")?;
+            out.write_all(&(36u32 + 24).to_le_bytes())?;
+            out.write_all(&0u32.to_le_bytes())?; // Manufacturer
+            out.write_all(&0u32.to_le_bytes())?; // Product
+            out.write_all(&(1_000_000_000 / sample_rate).to_le_bytes())?; // Sample period in ns
+            out.write_all(&60u32.to_le_bytes())?; // MIDI unity note, C-4
+            out.write_all(&0u32.to_le_bytes())?; // MIDI pitch fraction
+            out.write_all(&0u32.to_le_bytes())?; // SMPTE format
+            out.write_all(&0u32.to_le_bytes())?; // SMPTE offset
+            out.write_all(&1u32.to_le_bytes())?; // Number of loops
+            out.write_all(&0u32.to_le_bytes())?; // Sampler data
+
+            out.write_all(&0u32.to_le_bytes())?; // Cue point ID
+            out.write_all(&(header.is_bidirectional() as u32).to_le_bytes())?; // Type
+            out.write_all(&loop_start.to_le_bytes())?;
+            out.write_all(&(loop_end - 1).to_le_bytes())?;
+            out.write_all(&0u32.to_le_bytes())?; // Fraction
+            out.write_all(&0u32.to_le_bytes())?; // Play count, 0 loops forever
+        }
+
+        Ok(())
+    }
+}
+
+/// 8363 Hz shifted by `finetune` 1/128ths of a semitone and `relative_note` semitones
+fn sample_rate(finetune: i8, relative_note: i8) -> u32 {
+    let semitones = relative_note as f32 + finetune as f32 / 128.0;
+    (8363.0 * 2.0_f32.powf(semitones / 12.0)).round().max(1.0) as u32
+}
+
+fn write_fmt_chunk<W: Write>(
+    out: &mut W,
+    format_tag: u16,
+    channels: u16,
+    sample_rate: u32,
+    bytes_per_sample: u16,
+) -> std::io::Result<()> {
+    let block_align = channels * bytes_per_sample;
+
+    out.write_all(b"fmt ")?;
+    out.write_all(&16u32.to_le_bytes())?;
+    out.write_all(&format_tag.to_le_bytes())?;
+    out.write_all(&channels.to_le_bytes())?;
+    out.write_all(&sample_rate.to_le_bytes())?;
+    out.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+    out.write_all(&block_align.to_le_bytes())?;
+    out.write_all(&(8 * bytes_per_sample).to_le_bytes())
+}
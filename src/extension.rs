@@ -0,0 +1,281 @@
+//! Extension data OpenMPT appends after the standard XM body.
+//!
+//! A few chunks made of an ID and a 32 bit size come first, like the song message, the pattern
+//! and channel names or the MIDI macros. They're followed by the `XTPM` block of instrument
+//! properties and the `STPM` block of song properties, both made of a 4 byte code and a 16 bit
+//! size per property.
+
+use alloc::{string::String, vec, vec::Vec};
+
+use nom::IResult;
+
+pub(crate) const XM_INSTRUMENT_PROPERTIES_ID: &[u8; 4] = b"XTPM";
+
+pub(crate) const XM_SONG_PROPERTIES_ID: &[u8; 4] = b"STPM";
+
+pub(crate) const XM_PATTERN_NAME_LENGTH: usize = 32;
+
+pub(crate) const XM_CHANNEL_NAME_LENGTH: usize = 20;
+
+pub(crate) const XM_MIDI_MACRO_LENGTH: usize = 32;
+
+pub(crate) const XM_GLOBAL_MACROS_NUM: usize = 9;
+
+pub(crate) const XM_PARAMETERED_MACROS_NUM: usize = 16;
+
+pub(crate) const XM_FIXED_MACROS_NUM: usize = 128;
+
+pub(crate) const XM_MIDI_MACROS_SIZE: usize =
+    (XM_GLOBAL_MACROS_NUM + XM_PARAMETERED_MACROS_NUM + XM_FIXED_MACROS_NUM) * XM_MIDI_MACRO_LENGTH;
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct XmExtensions {
+    /// Chunks stored before the property blocks, in file order
+    pub chunks: Vec<XmExtensionChunk>,
+    /// `None` if there's no `XTPM` block
+    pub instrument_properties: Option<Vec<XmInstrumentProperty>>,
+    /// `None` if there's no `STPM` block
+    pub song_properties: Option<Vec<XmSongProperty>>,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum XmExtensionChunk {
+    /// `text`
+    SongMessage(String),
+    /// `PNAM`
+    PatternNames(Vec<String>),
+    /// `CNAM`
+    ChannelNames(Vec<String>),
+    /// `MIDI`, chunks of another size are kept as `Unknown`
+    MidiMacros(XmMidiMacros),
+    /// Any other chunk, like plugin data or a `MIDI` chunk of an unexpected size, kept as is
+    Unknown { id: [u8; 4], data: Vec<u8> },
+}
+
+/// Macros sent by `Zxx` and `\xx`, each one a string of hex bytes where `z` stands for the
+/// effect parameter
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct XmMidiMacros {
+    /// Start, stop, tick, note on, note off, volume, panning, bank and program change
+    pub global: Vec<String>,
+    /// `SF0` to `SFF`, `EFx` picks the one `Z00` to `Z7F` send
+    pub parametered: Vec<String>,
+    /// Sent by `Z80` to `ZFF`
+    pub fixed: Vec<String>,
+}
+
+/// Filter changes OpenMPT handles itself rather than sending them to a device or plugin
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum XmMacroCommand {
+    /// `F0F000xx`
+    FilterCutoff(u8),
+    /// `F0F001xx`
+    FilterResonance(u8),
+}
+
+/// The codes are kept as stored, OpenMPT stores the older ones reversed, like `.BPR` for the
+/// rows per beat
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct XmInstrumentProperty {
+    pub code: [u8; 4],
+    /// One value per instrument, all of the same length
+    pub values: Vec<Vec<u8>>,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct XmSongProperty {
+    pub code: [u8; 4],
+    pub value: Vec<u8>,
+}
+
+impl XmExtensions {
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+            && self.instrument_properties.is_none()
+            && self.song_properties.is_none()
+    }
+
+    pub fn song_message(&self) -> Option<&str> {
+        self.chunks.iter().find_map(|e| match e {
+            XmExtensionChunk::SongMessage(message) => Some(message.as_str()),
+            _ => None,
+        })
+    }
+
+    pub fn pattern_names(&self) -> &[String] {
+        self.chunks
+            .iter()
+            .find_map(|e| match e {
+                XmExtensionChunk::PatternNames(names) => Some(&names[..]),
+                _ => None,
+            })
+            .unwrap_or(&[])
+    }
+
+    pub fn midi_macros(&self) -> Option<&XmMidiMacros> {
+        self.chunks.iter().find_map(|e| match e {
+            XmExtensionChunk::MidiMacros(macros) => Some(macros),
+            _ => None,
+        })
+    }
+
+    pub fn channel_names(&self) -> &[String] {
+        self.chunks
+            .iter()
+            .find_map(|e| match e {
+                XmExtensionChunk::ChannelNames(names) => Some(&names[..]),
+                _ => None,
+            })
+            .unwrap_or(&[])
+    }
+
+    /// Value of the song property with `code`, as stored in the file
+    pub fn song_property(&self, code: &[u8; 4]) -> Option<&[u8]> {
+        self.song_properties
+            .as_ref()?
+            .iter()
+            .find(|e| &e.code == code)
+            .map(|e| &e.value[..])
+    }
+}
+
+impl XmMidiMacros {
+    /// Macro `Zxx` or `\xx` sends, `active_macro` is the `EFx` parameter
+    pub fn zxx_macro(&self, active_macro: u8, parameter: u8) -> Option<&str> {
+        let macro_ = match parameter {
+            0x00..=0x7F => self.parametered.get(active_macro as usize),
+            _ => self.fixed.get(parameter as usize - 0x80),
+        };
+
+        macro_.map(String::as_str)
+    }
+}
+
+impl XmMacroCommand {
+    /// Reads one of the internal macros with `z` replaced by `parameter`, values are capped at
+    /// 127. `None` for any other macro.
+    pub fn parse(macro_: &str, parameter: u8) -> Option<Self> {
+        let macro_ = macro_
+            .chars()
+            .filter(|e| !e.is_whitespace())
+            .collect::<String>()
+            .to_ascii_lowercase();
+
+        let value = match macro_.get(6..)? {
+            "z" => parameter,
+            value if value.len() == 2 => u8::from_str_radix(value, 16).ok()?,
+            _ => return None,
+        }
+        .min(0x7F);
+
+        match macro_.get(..6)? {
+            "f0f000" => Some(Self::FilterCutoff(value)),
+            "f0f001" => Some(Self::FilterResonance(value)),
+            _ => None,
+        }
+    }
+}
+
+fn chunk_id(data: &[u8]) -> IResult<&[u8], [u8; 4]> {
+    nom::combinator::map(nom::bytes::complete::take(4usize), |e: &[u8]| {
+        [e[0], e[1], e[2], e[3]]
+    })(data)
+}
+
+fn parse_names(data: &[u8], length: usize) -> Vec<String> {
+    data.chunks(length).map(crate::decode_name).collect()
+}
+
+fn parse_midi_macros(data: &[u8]) -> XmMidiMacros {
+    let mut macros = parse_names(data, XM_MIDI_MACRO_LENGTH).into_iter();
+
+    XmMidiMacros {
+        global: macros.by_ref().take(XM_GLOBAL_MACROS_NUM).collect(),
+        parametered: macros.by_ref().take(XM_PARAMETERED_MACROS_NUM).collect(),
+        fixed: macros.collect(),
+    }
+}
+
+fn parse_chunk(data: &[u8]) -> IResult<&[u8], XmExtensionChunk> {
+    let (input, id) = nom::combinator::verify(chunk_id, |e: &[u8; 4]| {
+        e.iter().all(u8::is_ascii_alphanumeric) && e != XM_INSTRUMENT_PROPERTIES_ID
+    })(data)?;
+    let (input, length) = nom::number::complete::le_u32(input)?;
+    let (input, data) = nom::bytes::complete::take(length)(input)?;
+
+    let chunk = match &id {
+        b"text" => XmExtensionChunk::SongMessage(crate::decode_name(data)),
+        b"PNAM" => XmExtensionChunk::PatternNames(parse_names(data, XM_PATTERN_NAME_LENGTH)),
+        b"CNAM" => XmExtensionChunk::ChannelNames(parse_names(data, XM_CHANNEL_NAME_LENGTH)),
+        b"MIDI" if data.len() == XM_MIDI_MACROS_SIZE => {
+            XmExtensionChunk::MidiMacros(parse_midi_macros(data))
+        }
+        _ => XmExtensionChunk::Unknown {
+            id,
+            data: data.to_vec(),
+        },
+    };
+
+    Ok((input, chunk))
+}
+
+fn parse_instrument_property(
+    instruments_num: usize,
+) -> impl FnMut(&[u8]) -> IResult<&[u8], XmInstrumentProperty> {
+    move |data| {
+        let (input, code) =
+            nom::combinator::verify(chunk_id, |e: &[u8; 4]| e != XM_SONG_PROPERTIES_ID)(data)?;
+        let (input, size) = nom::number::complete::le_u16(input)?;
+        let (input, _) = crate::verify_remaining(input, instruments_num, size as usize)?;
+        let (input, values) = nom::multi::count(
+            nom::combinator::map(nom::bytes::complete::take(size), <[u8]>::to_vec),
+            instruments_num,
+        )(input)?;
+
+        Ok((input, XmInstrumentProperty { code, values }))
+    }
+}
+
+fn parse_song_property(data: &[u8]) -> IResult<&[u8], XmSongProperty> {
+    let (input, (code, size)) =
+        nom::sequence::tuple((chunk_id, nom::number::complete::le_u16))(data)?;
+    let (input, value) = nom::bytes::complete::take(size)(input)?;
+
+    Ok((
+        input,
+        XmSongProperty {
+            code,
+            value: value.to_vec(),
+        },
+    ))
+}
+
+/// Reads whatever extension data follows the instruments. Never fails, the returned input
+/// starts at the first bytes which couldn't be read as extension data.
+pub(crate) fn parse(data: &[u8], instruments_num: usize) -> (&[u8], XmExtensions) {
+    let mut extensions = XmExtensions::default();
+
+    let (mut input, chunks) = nom::multi::many0(parse_chunk)(data).unwrap_or((data, vec![]));
+    extensions.chunks = chunks;
+
+    let Some(rest) = input.strip_prefix(XM_INSTRUMENT_PROPERTIES_ID) else {
+        return (input, extensions);
+    };
+    let (rest, properties) = nom::multi::many0(parse_instrument_property(instruments_num))(rest)
+        .unwrap_or((rest, vec![]));
+    extensions.instrument_properties = Some(properties);
+    input = rest;
+
+    let Some(rest) = input.strip_prefix(XM_SONG_PROPERTIES_ID) else {
+        return (input, extensions);
+    };
+    let (rest, properties) = nom::multi::many0(parse_song_property)(rest).unwrap_or((rest, vec![]));
+    extensions.song_properties = Some(properties);
+
+    (rest, extensions)
+}
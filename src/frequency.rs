@@ -1,22 +1,9 @@
-use crate::note::{XmNote, XmTone, XM_MAX_OCTAVE, XM_TONE_COUNT};
-
-fn tone_to_raw(tone: &XmTone, octave: u8) -> u8 {
-    let tone_raw: u8 = match tone {
-        XmTone::C => 1,
-        XmTone::CS => 2,
-        XmTone::D => 3,
-        XmTone::DS => 4,
-        XmTone::E => 5,
-        XmTone::F => 6,
-        XmTone::FS => 7,
-        XmTone::G => 8,
-        XmTone::GS => 9,
-        XmTone::A => 10,
-        XmTone::AS => 11,
-        XmTone::B => 12,
-    };
+#[cfg(feature = "std")]
+use crate::note::XmNote;
+use crate::note::{XmTone, XM_MAX_OCTAVE, XM_TONE_COUNT};
 
-    let note = tone_raw + (octave.clamp(1, XM_MAX_OCTAVE) - 1) * XM_TONE_COUNT;
+pub(crate) fn tone_to_raw(tone: &XmTone, octave: u8) -> u8 {
+    let note = tone.index() + octave.min(XM_MAX_OCTAVE) * XM_TONE_COUNT;
 
     // Add one to bring back the previously discarded NoNote scenario
     note + 1
@@ -25,31 +12,141 @@ fn tone_to_raw(tone: &XmTone, octave: u8) -> u8 {
 pub trait FrequencyCalculator {
     fn period(tone: &XmTone, octave: u8) -> f32;
     fn frequency(period: f32) -> f32;
+    /// Inverse of `period`, returns the note closest to `period`. Periods past either end of
+    /// the note range are clamped to `C-0` and `B-7`.
+    fn period_to_note(period: f32) -> (XmTone, u8);
+}
+
+/// Turns the 0-based index of a note, as a float since it comes from a period, into a note
+#[cfg(feature = "std")]
+fn note_from_index(index: f32) -> (XmTone, u8) {
+    let max_index = (XM_MAX_OCTAVE + 1) * XM_TONE_COUNT - 1;
+    let index = index.round().clamp(0.0, max_index as f32) as u8;
+
+    (
+        XmTone::from_index(index % XM_TONE_COUNT)
+            .expect("the remainder should always be a valid tone index"),
+        index / XM_TONE_COUNT,
+    )
+}
+
+/// Returns the period `note` plays at on a sample with the given `finetune` and `relative_note`
+/// (see `XmSampleHeader`). `None` if `note` isn't an actual note or if the relative note shifts
+/// it outside of the playable range.
+#[cfg(feature = "std")]
+pub fn note_period<F: FrequencyCalculator>(
+    note: &XmNote,
+    finetune: i8,
+    relative_note: i8,
+) -> Option<f32> {
+    let XmNote::Note { tone, octave } = note.transpose(relative_note as i16)? else {
+        return None;
+    };
+
+    // finetune is in 1/128ths of a semitone towards the next one, the highest note bends
+    // by the distance to the one below instead
+    let period = F::period(&tone, octave);
+    let semitone = match note.transpose(relative_note as i16 + 1) {
+        Some(XmNote::Note { tone, octave }) => F::period(&tone, octave) - period,
+        _ => {
+            let Some(XmNote::Note { tone, octave }) = note.transpose(relative_note as i16 - 1)
+            else {
+                return Some(period);
+            };
+
+            period - F::period(&tone, octave)
+        }
+    };
+
+    Some(period + semitone * finetune as f32 / 128.0)
 }
 
 pub struct Amiga;
 
 pub struct Linear;
 
+/// Which `FrequencyCalculator` a module's periods are meant for, see
+/// `XmHeader::frequency_table`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrequencyTable {
+    Linear,
+    Amiga,
+}
+
+#[cfg(feature = "std")]
+impl FrequencyTable {
+    pub fn period(&self, tone: &XmTone, octave: u8) -> f32 {
+        match self {
+            Self::Linear => Linear::period(tone, octave),
+            Self::Amiga => Amiga::period(tone, octave),
+        }
+    }
+
+    pub fn frequency(&self, period: f32) -> f32 {
+        match self {
+            Self::Linear => Linear::frequency(period),
+            Self::Amiga => Amiga::frequency(period),
+        }
+    }
+
+    /// `note_period` with this table's `FrequencyCalculator`
+    pub fn note_period(&self, note: &XmNote, finetune: i8, relative_note: i8) -> Option<f32> {
+        match self {
+            Self::Linear => note_period::<Linear>(note, finetune, relative_note),
+            Self::Amiga => note_period::<Amiga>(note, finetune, relative_note),
+        }
+    }
+
+    pub fn period_to_note(&self, period: f32) -> (XmTone, u8) {
+        match self {
+            Self::Linear => Linear::period_to_note(period),
+            Self::Amiga => Amiga::period_to_note(period),
+        }
+    }
+
+    /// Rounds `period` to the period of the closest semitone
+    pub fn nearest_semitone(&self, period: f32) -> f32 {
+        match self {
+            Self::Linear => (period / 64.0).round() * 64.0,
+            Self::Amiga => {
+                let semitones = (12.0 * (1712.0 / period).log2()).round();
+                1712.0 * 2.0_f32.powf(-semitones / 12.0)
+            }
+        }
+    }
+}
+
 // don't ask me about these numbers, look at libxm
+#[cfg(feature = "std")]
 impl FrequencyCalculator for Linear {
     fn period(tone: &XmTone, octave: u8) -> f32 {
-        let tone = tone_to_raw(tone, octave) as f32;
+        // the raw value is 1-based, C-4 (raw value 49) lands on period 4608 (8363 Hz)
+        let tone = (tone_to_raw(tone, octave) - 1) as f32;
         7680.0 - tone * 64.0
     }
 
     fn frequency(period: f32) -> f32 {
-        8363.0 * 2.0_f32.powf(4608.0 - period) / 768.0
+        8363.0 * 2.0_f32.powf((4608.0 - period) / 768.0)
+    }
+
+    fn period_to_note(period: f32) -> (XmTone, u8) {
+        note_from_index((7680.0 - period) / 64.0)
     }
 }
 
-// how do i even begin implementing amiga's
+// FT2 keeps the Amiga periods 4 times finer than ProTracker, so C-4 lands on 1712 (428 * 4)
+#[cfg(feature = "std")]
 impl FrequencyCalculator for Amiga {
     fn period(tone: &XmTone, octave: u8) -> f32 {
-        todo!();
+        let tone = (tone_to_raw(tone, octave) - 1) as f32;
+        1712.0 * 2.0_f32.powf((48.0 - tone) / 12.0)
     }
 
     fn frequency(period: f32) -> f32 {
-        todo!();
+        8363.0 * 1712.0 / period
+    }
+
+    fn period_to_note(period: f32) -> (XmTone, u8) {
+        note_from_index(48.0 + 12.0 * (1712.0 / period).log2())
     }
 }
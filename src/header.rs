@@ -1,7 +1,19 @@
+use alloc::{borrow::ToOwned, string::String, vec, vec::Vec};
+
 use nom::{sequence::tuple, IResult};
 
-#[derive(Clone, Debug)]
+/// The current version, which is also the one the writer produces
+pub const XM_SUPPORTED_VERSION: u16 = 0x0104;
+
+/// Versions before 1.04 store the instruments before the patterns and the sample data after
+/// them, the parser understands them back to 1.02
+pub const XM_OLDEST_SUPPORTED_VERSION: u16 = 0x0102;
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct XmHeader {
+    // normally "Extended Module: "
+    pub id_text: String,
     pub module_name: String,
     pub tracker_name: String,
     pub version: u16,
@@ -14,9 +26,62 @@ pub struct XmHeader {
     pub is_amiga: bool,
     pub default_tempo: u16,
     pub default_bpm: u16,
+    /// Bytes the header size covers past the order table
+    pub extra: Vec<u8>,
+}
+
+/// Suspicious, but not invalid, header values which players may want to know about
+#[derive(Clone, Debug, PartialEq)]
+pub enum XmHeaderWarning {
+    /// The Amiga frequency table is traditionally only used by 4 channel modules, so the flag
+    /// may have been set by mistake. Holds the number of channels.
+    AmigaWithManyChannels(u16),
+    /// A default tempo of 0 leaves no ticks for effects, playback moves on a row every tick
+    ZeroTempo,
+    /// A default BPM of 0 would make every tick last forever, playback treats it like 1
+    ZeroBpm,
 }
 
-pub(crate) fn parse(data: &[u8]) -> IResult<&[u8], (XmHeader, String, u8, u32)> {
+impl XmHeader {
+    pub fn frequency_table(&self) -> crate::frequency::FrequencyTable {
+        if self.is_amiga {
+            crate::frequency::FrequencyTable::Amiga
+        } else {
+            crate::frequency::FrequencyTable::Linear
+        }
+    }
+
+    /// `1` for version 1.04
+    pub fn version_major(&self) -> u8 {
+        (self.version >> 8) as u8
+    }
+
+    /// `4` for version 1.04
+    pub fn version_minor(&self) -> u8 {
+        (self.version & 0xFF) as u8
+    }
+
+    pub fn validate_ranges(&self) -> Vec<XmHeaderWarning> {
+        let mut warnings = vec![];
+
+        if self.default_tempo == 0 {
+            warnings.push(XmHeaderWarning::ZeroTempo);
+        }
+
+        if self.default_bpm == 0 {
+            warnings.push(XmHeaderWarning::ZeroBpm);
+        }
+
+        if self.is_amiga && self.channels_num > 4 {
+            warnings.push(XmHeaderWarning::AmigaWithManyChannels(self.channels_num));
+        }
+
+        warnings
+    }
+}
+
+/// Lenient mode accepts any number of channels
+pub(crate) fn parse(data: &[u8], strict: bool) -> IResult<&[u8], (XmHeader, u8, u32)> {
     let (
         input,
         (
@@ -42,11 +107,15 @@ pub(crate) fn parse(data: &[u8]) -> IResult<&[u8], (XmHeader, String, u8, u32)>
         crate::fixed_length_string(20), // Module name
         nom::combinator::verify(nom::number::complete::u8, |e| e == &0x1A), // 0x1A
         crate::fixed_length_string(20), // Tracker name
-        nom::combinator::verify(nom::number::complete::le_u16, |e| e == &0x0104), // Version number
-        nom::number::complete::le_u32,  // Header size
+        nom::combinator::verify(nom::number::complete::le_u16, |e| {
+            (XM_OLDEST_SUPPORTED_VERSION..=XM_SUPPORTED_VERSION).contains(e)
+        }), // Version number
+        nom::combinator::verify(nom::number::complete::le_u32, |e| *e >= 20), // Header size, counted from here on
         nom::combinator::verify(nom::number::complete::le_u16, |e| (1..=256u16).contains(e)), // Song length
         nom::number::complete::le_u16, // Restart position
-        nom::combinator::verify(nom::number::complete::le_u16, |e| (0..128).contains(e)), // Number of channels (OpenMPT allows a max of 127)
+        nom::combinator::verify(nom::number::complete::le_u16, |e| {
+            !strict || (0..128).contains(e)
+        }), // Number of channels (OpenMPT allows a max of 127)
         nom::combinator::verify(nom::number::complete::le_u16, |e| (1..=256).contains(e)), // Number of patterns
         nom::combinator::verify(nom::number::complete::le_u16, |e| (0..=128).contains(e)), // Number of instruments
         nom::number::complete::le_u16,                                                     // Flags
@@ -60,6 +129,7 @@ pub(crate) fn parse(data: &[u8]) -> IResult<&[u8], (XmHeader, String, u8, u32)>
         input,
         (
             XmHeader {
+                id_text,
                 module_name,
                 tracker_name,
                 version,
@@ -71,8 +141,8 @@ pub(crate) fn parse(data: &[u8]) -> IResult<&[u8], (XmHeader, String, u8, u32)>
                 is_amiga,
                 default_tempo,
                 default_bpm,
+                extra: vec![],
             },
-            id_text,
             ox1a,
             header_size - 20,
         ),
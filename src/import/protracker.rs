@@ -0,0 +1,286 @@
+//! Imports ProTracker MOD files as an `XmModule`.
+//!
+//! Every MOD sample becomes an instrument holding a single 8-bit sample. Patterns keep their 64
+//! rows, and notes are converted from their Amiga period, with C-2 (period 428) ending up as
+//! C-4. The module uses the Amiga frequency table, which XM inherited from ProTracker.
+//!
+//! | MOD   | XM                                                        |
+//! |-------|-----------------------------------------------------------|
+//! | `0xy` | `Arpeggio`, `000` is no effect                            |
+//! | `1xx` | `PortamentoUp`                                            |
+//! | `2xx` | `PortamentoDown`                                          |
+//! | `3xx` | `TonePortamento`                                          |
+//! | `4xy` | `Vibrato`                                                 |
+//! | `5xy` | `VolumeSlideTonePortamento`                               |
+//! | `6xy` | `VolumeSlideVibrato`                                      |
+//! | `7xy` | `Tremolo`                                                 |
+//! | `8xx` | `SetPanningFine`                                          |
+//! | `9xx` | `SampleOffset`                                            |
+//! | `Axy` | `VolumeSlide`                                             |
+//! | `Bxx` | `PositionJump`                                            |
+//! | `Cxx` | `SetVolume`                                               |
+//! | `Dxx` | `PatternBreak`                                            |
+//! | `Exy` | The matching `E` sub-effect, `E0x` (filter) is `Unknown`  |
+//! | `Fxx` | `SetTempo`                                                |
+//!
+//! The sample finetune is stored in eighths of a semitone, and is scaled up to XM's 128ths.
+//! Lengths and loop points are stored in words, and are doubled to get bytes.
+
+use alloc::{borrow::ToOwned, string::String, vec, vec::Vec};
+
+use nom::{error::ParseError, sequence::tuple, IResult};
+
+use crate::{
+    effect,
+    header::{XmHeader, XM_SUPPORTED_VERSION},
+    instrument::{
+        XmInstrumentHeader, XmSampleBitDepth, XmSampleHeader, XmSampleLoopType, XmSamplePcmData,
+        XmSampleType,
+    },
+    note::{XmNote, XM_MIDI_NOTE_OFFSET},
+    pattern::{
+        XmPatternHeader, XmPatternRow, XmPatternRows, XmPatternSlot, XM_PATTERN_HEADER_SIZE,
+    },
+    write, XmModule, XmSample,
+};
+
+const MOD_SAMPLES_NUM: usize = 31;
+const MOD_ORDER_TABLE_SIZE: usize = 128;
+const MOD_ROWS_NUM: usize = 64;
+
+/// ProTracker periods of the octave starting at C-2, which plays samples at their base rate like
+/// C-4 does in XM
+const MOD_BASE_PERIODS: [u64; 12] = [428, 404, 381, 360, 339, 320, 302, 285, 269, 254, 240, 226];
+const MOD_BASE_NOTE: i32 = 48;
+
+struct ModSampleHeader {
+    name: String,
+    length: u32,
+    finetune: i8,
+    volume: u8,
+    loop_start: u32,
+    loop_length: u32,
+}
+
+/// Number of channels for the format tag after the order table, only 31 sample modules with
+/// 4, 6 or 8 channels are supported
+fn channels_num(tag: &[u8]) -> Option<u16> {
+    match tag {
+        b"M.K." | b"M!K!" | b"FLT4" | b"4CHN" => Some(4),
+        b"6CHN" => Some(6),
+        b"8CHN" | b"CD81" | b"OKTA" | b"FLT8" => Some(8),
+        _ => None,
+    }
+}
+
+fn parse_sample_header(data: &[u8]) -> IResult<&[u8], ModSampleHeader> {
+    let (input, (name, length, finetune, volume, loop_start, loop_length)) = tuple((
+        crate::fixed_length_string(22), // Sample name
+        nom::number::complete::be_u16,  // Length in words
+        nom::number::complete::u8,      // Finetune, signed in the low nibble
+        nom::number::complete::u8,      // Volume
+        nom::number::complete::be_u16,  // Loop start in words
+        nom::number::complete::be_u16,  // Loop length in words
+    ))(data)?;
+
+    Ok((
+        input,
+        ModSampleHeader {
+            name,
+            length: length as u32 * 2,
+            // sign extend the nibble
+            finetune: ((finetune << 4) as i8) >> 4,
+            volume: volume.min(0x40),
+            loop_start: loop_start as u32 * 2,
+            loop_length: loop_length as u32 * 2,
+        },
+    ))
+}
+
+fn period_to_note(period: u16) -> XmNote {
+    if period == 0 {
+        return XmNote::NoNote;
+    }
+
+    // picks the closest note on a logarithmic scale, comparing the ratios between the periods by
+    // cross multiplying. Periods are scaled up so the higher octaves can halve them exactly.
+    let period = (period as u64) << 8;
+    let ratio = |candidate: u64| (period.max(candidate), period.min(candidate));
+    let (octave, semitone) = (-4..=3i32)
+        .flat_map(|octave| (0..12).map(move |semitone| (octave, semitone)))
+        .min_by(|a, b| {
+            let (a_max, a_min) = ratio(MOD_BASE_PERIODS[a.1] << (8 - a.0));
+            let (b_max, b_min) = ratio(MOD_BASE_PERIODS[b.1] << (8 - b.0));
+            (a_max * b_min).cmp(&(b_max * a_min))
+        })
+        .unwrap_or((0, 0));
+
+    let index = MOD_BASE_NOTE + octave * 12 + semitone as i32;
+
+    XmNote::from_midi((index + XM_MIDI_NOTE_OFFSET as i32).clamp(0, u8::MAX as i32) as u8)
+}
+
+fn parse_slot(data: &[u8]) -> IResult<&[u8], XmPatternSlot> {
+    let (input, (a, b, c, d)) = tuple((
+        nom::number::complete::u8,
+        nom::number::complete::u8,
+        nom::number::complete::u8,
+        nom::number::complete::u8,
+    ))(data)?;
+
+    let instrument = (a & 0xF0) | (c >> 4);
+    let period = ((a as u16 & 0x0F) << 8) | b as u16;
+    let command = c & 0x0F;
+
+    // the effects are the same as the first 16 of XM, so the bytes go through its parser
+    let effect = match (command, d) {
+        (0, 0) => None,
+        _ => effect::parse_effect(true, true)(&[command, d])
+            .ok()
+            .and_then(|e| e.1),
+    };
+
+    Ok((
+        input,
+        XmPatternSlot::new(
+            period_to_note(period),
+            (instrument != 0).then_some(instrument),
+            None,
+            effect,
+        ),
+    ))
+}
+
+fn parse_pattern(channels_num: u16) -> impl FnMut(&[u8]) -> IResult<&[u8], XmPatternRows> {
+    move |input| {
+        nom::combinator::map(
+            nom::multi::count(
+                nom::combinator::map(
+                    nom::multi::count(parse_slot, channels_num as usize),
+                    XmPatternRow,
+                ),
+                MOD_ROWS_NUM,
+            ),
+            XmPatternRows,
+        )(input)
+    }
+}
+
+fn to_xm_sample(header: ModSampleHeader, data: Vec<i8>) -> XmSample {
+    // loops of a single word are how ProTracker marks samples which don't loop
+    let loop_type = if header.loop_length > 2 {
+        XmSampleLoopType::ForwardLoop
+    } else {
+        XmSampleLoopType::NoLoop
+    };
+
+    (
+        XmSampleHeader {
+            length: data.len() as u32,
+            loop_start: header.loop_start,
+            loop_length: header.loop_length,
+            volume: header.volume,
+            finetune: header.finetune * 16,
+            kind: XmSampleType::new()
+                .with_loop_type(loop_type)
+                .with_depth(XmSampleBitDepth::Bit8),
+            panning: 0x80,
+            relative_note_num: 0,
+            name: header.name,
+        },
+        XmSamplePcmData::Bit8Data(data),
+    )
+}
+
+/// Parses a ProTracker MOD file with 31 samples and 4, 6 or 8 channels
+pub fn from_mod(data: &[u8]) -> IResult<&[u8], XmModule> {
+    let (input, (module_name, sample_headers, song_length, restart_pos, order_table, tag)) =
+        tuple((
+            crate::fixed_length_string(20),                          // Module name
+            nom::multi::count(parse_sample_header, MOD_SAMPLES_NUM), // Sample headers
+            // Song length
+            nom::combinator::verify(nom::number::complete::u8, |e| (1..=128).contains(e)),
+            nom::number::complete::u8, // Restart position, 127 in most files
+            nom::bytes::complete::take(MOD_ORDER_TABLE_SIZE), // Pattern order table
+            nom::bytes::complete::take(4usize), // Format tag
+        ))(data)?;
+
+    let Some(channels_num) = channels_num(tag) else {
+        return Err(nom::Err::Error(nom::error::Error::from_error_kind(
+            input,
+            nom::error::ErrorKind::Tag,
+        )));
+    };
+
+    // every pattern in the order table is stored, even the ones past the song length
+    let patterns_num = order_table.iter().max().map_or(0, |e| *e as usize + 1);
+    let (mut input, patterns) =
+        nom::multi::count(parse_pattern(channels_num), patterns_num)(input)?;
+
+    let mut instruments = vec![];
+    for header in sample_headers {
+        if header.length == 0 {
+            instruments.push((XmInstrumentHeader::new(header.name, 0), vec![]));
+            continue;
+        }
+
+        // the last sample is often cut short, keep whatever is there
+        let length = (header.length as usize).min(input.len());
+        let (input_, sample_data) = nom::multi::count(nom::number::complete::i8, length)(input)?;
+        input = input_;
+
+        let sample = to_xm_sample(header, sample_data);
+        instruments.push((
+            XmInstrumentHeader::new(sample.0.name.clone(), 1),
+            vec![sample],
+        ));
+    }
+
+    let song_length = song_length as u16;
+    let patterns = patterns
+        .into_iter()
+        .map(|rows| {
+            let packed_data_size = write::pack_rows(&rows).len() as u16;
+
+            (
+                XmPatternHeader {
+                    header_length: XM_PATTERN_HEADER_SIZE as u32,
+                    packing_type: 0,
+                    rows_num: MOD_ROWS_NUM as u16,
+                    packed_data_size,
+                    extra: vec![],
+                },
+                rows,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    Ok((
+        input,
+        XmModule {
+            header: XmHeader {
+                id_text: "Extended Module: ".to_owned(),
+                module_name,
+                tracker_name: "ProTracker".to_owned(),
+                version: XM_SUPPORTED_VERSION,
+                song_length,
+                restart_pos: if (restart_pos as u16) < song_length {
+                    restart_pos as u16
+                } else {
+                    0
+                },
+                channels_num,
+                patterns_num: patterns.len() as u16,
+                instruments_num: instruments.len() as u16,
+                is_amiga: true,
+                default_tempo: 6,
+                default_bpm: 125,
+                extra: vec![],
+            },
+            patterns,
+            instruments,
+            pattern_order_table: order_table[..song_length as usize].to_vec(),
+            extensions: Default::default(),
+        },
+    ))
+}
@@ -1,11 +1,16 @@
+use alloc::{string::String, vec, vec::Vec};
+
 use bitfield_struct::bitfield;
-use either::Either;
 use nom::{error::ParseError, sequence::tuple, IResult};
 
+use crate::note::XmNote;
+
+#[cfg(feature = "std")]
 use crate::interpolation::{self, Interpolation};
 
-const XM_INSTRUMENT_HEADER_SIZE: usize = 29;
-const XM_INSTRUMENT_HEADER_SIZE_W_OPTS: usize = 263;
+pub(crate) const XM_INSTRUMENT_HEADER_SIZE: usize = 29;
+pub(crate) const XM_INSTRUMENT_HEADER_SIZE_W_OPTS: usize = 263;
+pub(crate) const XM_SAMPLE_HEADER_SIZE: usize = 40;
 
 #[bitfield(u8)]
 pub struct XmEnvelopeType {
@@ -17,21 +22,26 @@ pub struct XmEnvelopeType {
     __: u8,
 }
 
-#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(u8)]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
 pub enum XmVibratoType {
-    Sine,
-    Square,
-    RampUp,
-    RampDown,
+    #[default]
+    Sine = 0,
+    Square = 1,
+    RampDown = 2,
+    RampUp = 3,
 }
 
-#[derive(Clone, Default, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Default, Debug, Eq, PartialEq)]
 pub struct XmEnvelopePoint {
     pub frame: u16,
     pub value: u16,
 }
 
-#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
 pub struct XmEnvelope {
     pub points: Vec<XmEnvelopePoint>,
     pub sustain_point: Option<u8>,
@@ -39,7 +49,60 @@ pub struct XmEnvelope {
     pub loop_end_point: Option<u8>,
 }
 
-#[derive(Clone, Debug)]
+impl XmEnvelope {
+    /// Envelope through `(frame, value)` points, without sustain or loop
+    pub fn new(points: &[(u16, u16)]) -> Self {
+        Self {
+            points: points
+                .iter()
+                .map(|&(frame, value)| XmEnvelopePoint { frame, value })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn point_frame(&self, point: Option<u8>) -> Option<u16> {
+        self.points.get(point? as usize).map(|e| e.frame)
+    }
+
+    /// Linearly interpolates the envelope value at `frame`, holding the first and last point
+    /// values outside of the envelope
+    pub fn evaluate(&self, frame: u16) -> f32 {
+        let Some(next) = self.points.iter().position(|e| e.frame > frame) else {
+            return self.points.last().map_or(0.0, |e| e.value as f32);
+        };
+
+        if next == 0 {
+            return self.points[0].value as f32;
+        }
+
+        let (a, b) = (&self.points[next - 1], &self.points[next]);
+        let t = (frame - a.frame) as f32 / (b.frame - a.frame) as f32;
+
+        a.value as f32 + t * (b.value as f32 - a.value as f32)
+    }
+
+    /// Moves `frame` forward by one tick. The frame stays on the sustain point while the note is
+    /// `held`, and jumps back to the loop start once it reaches the loop end.
+    pub fn advance(&self, frame: u16, held: bool) -> u16 {
+        if held && self.point_frame(self.sustain_point) == Some(frame) {
+            return frame;
+        }
+
+        let next = frame.saturating_add(1);
+
+        match (
+            self.point_frame(self.loop_start_point),
+            self.point_frame(self.loop_end_point),
+        ) {
+            (Some(start), Some(end)) if frame < end && next >= end => start,
+            _ => next,
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
 pub struct XmVibratoOpts {
     pub kind: XmVibratoType,
     pub sweep: u8,
@@ -47,9 +110,31 @@ pub struct XmVibratoOpts {
     pub rate: u8,
 }
 
-#[derive(Clone, Debug)]
+/// serde only implements its traits for arrays of up to 32 elements
+#[cfg(feature = "serde")]
+mod keymap_serde {
+    use alloc::vec::Vec;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(keymap: &[u8; 96], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(keymap)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 96], D::Error> {
+        let keymap = Vec::<u8>::deserialize(deserializer)?;
+        let len = keymap.len();
+
+        keymap
+            .try_into()
+            .map_err(|_| D::Error::invalid_length(len, &"96 keymap entries"))
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct XmInstrumentSampleOpts {
     pub sample_header_size: u32,
+    #[cfg_attr(feature = "serde", serde(with = "keymap_serde"))]
     pub sample_keymap_assignments: [u8; 96],
     pub volume_envelope: Option<XmEnvelope>,
     pub panning_envelope: Option<XmEnvelope>,
@@ -57,13 +142,79 @@ pub struct XmInstrumentSampleOpts {
     pub volume_fadeout: u16,
 }
 
-#[derive(Clone, Debug)]
+/// Every sample on every key, no envelopes, vibrato or fadeout
+impl Default for XmInstrumentSampleOpts {
+    fn default() -> Self {
+        Self {
+            sample_header_size: XM_SAMPLE_HEADER_SIZE as u32,
+            sample_keymap_assignments: [0; 96],
+            volume_envelope: None,
+            panning_envelope: None,
+            vibrato: XmVibratoOpts::default(),
+            volume_fadeout: 0,
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct XmInstrumentHeader {
     pub header_size: u32,
     pub name: String,
     pub kind: u8,
     pub samples_num: u16,
     pub sample_opts: Option<XmInstrumentSampleOpts>,
+    /// Bytes the header size covers past the standard fields
+    pub extra: Vec<u8>,
+}
+
+/// An unnamed instrument without any samples
+impl Default for XmInstrumentHeader {
+    fn default() -> Self {
+        Self {
+            header_size: XM_INSTRUMENT_HEADER_SIZE as u32,
+            name: String::new(),
+            kind: 0,
+            samples_num: 0,
+            sample_opts: None,
+            extra: vec![],
+        }
+    }
+}
+
+impl XmInstrumentHeader {
+    /// Instrument with `samples_num` samples, which get the default sample options so that
+    /// the writer stores them
+    pub fn new(name: String, samples_num: u16) -> Self {
+        let sample_opts = (samples_num > 0).then(XmInstrumentSampleOpts::default);
+
+        Self {
+            header_size: match sample_opts {
+                Some(_) => XM_INSTRUMENT_HEADER_SIZE_W_OPTS,
+                None => XM_INSTRUMENT_HEADER_SIZE,
+            } as u32,
+            name,
+            samples_num,
+            sample_opts,
+            ..Default::default()
+        }
+    }
+
+    /// Index of the sample `note` plays through the keymap, instruments without sample options
+    /// always play their first sample. `None` if `note` isn't an actual note.
+    pub fn sample_index(&self, note: &XmNote) -> Option<u8> {
+        let XmNote::Note { tone, octave } = note else {
+            return None;
+        };
+
+        match self.sample_opts {
+            Some(ref opts) => {
+                let key = crate::frequency::tone_to_raw(tone, *octave) as usize - 1;
+                opts.sample_keymap_assignments.get(key).copied()
+            }
+            None => Some(0),
+        }
+    }
 }
 
 #[repr(u8)]
@@ -84,6 +235,7 @@ pub enum XmSampleBitDepth {
 }
 
 #[bitfield(u8)]
+#[derive(Eq, PartialEq)]
 pub struct XmSampleType {
     #[bits(2)]
     pub loop_type: XmSampleLoopType,
@@ -98,7 +250,11 @@ pub struct XmSampleType {
     __: u8,
 }
 
-#[derive(Clone, Debug)]
+#[cfg(feature = "serde")]
+bitfield_serde!(XmSampleType, u8);
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct XmSampleHeader {
     pub length: u32,
     pub loop_start: u32,
@@ -111,15 +267,50 @@ pub struct XmSampleHeader {
     pub name: String,
 }
 
-#[derive(Clone)]
+/// An empty unnamed 8 bit sample without a loop, at full volume and centered
+impl Default for XmSampleHeader {
+    fn default() -> Self {
+        Self {
+            length: 0,
+            loop_start: 0,
+            loop_length: 0,
+            volume: 64,
+            finetune: 0,
+            kind: XmSampleType::new(),
+            panning: 0x80,
+            relative_note_num: 0,
+            name: String::new(),
+        }
+    }
+}
+
+impl XmSampleHeader {
+    /// Returns `true` for both forward and bidirectional loops
+    pub fn is_looping(&self) -> bool {
+        matches!(
+            self.kind.loop_type(),
+            XmSampleLoopType::ForwardLoop | XmSampleLoopType::BidirectionalLoop
+        )
+    }
+
+    pub fn is_bidirectional(&self) -> bool {
+        matches!(self.kind.loop_type(), XmSampleLoopType::BidirectionalLoop)
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum XmSamplePcmData {
     Bit8Data(Vec<i8>),
     Bit16Data(Vec<i16>),
 }
 
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
 pub enum XmResamplingType {
     NoInterpolation,
+    #[default]
     LinearInterpolation,
+    CubicInterpolation,
 }
 
 impl XmSamplePcmData {
@@ -130,43 +321,138 @@ impl XmSamplePcmData {
         }
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The samples as stored, `None` for 16 bit data
+    pub fn as_i8_slice(&self) -> Option<&[i8]> {
+        match self {
+            Self::Bit8Data(v) => Some(v),
+            Self::Bit16Data(_) => None,
+        }
+    }
+
+    /// The samples as stored, `None` for 8 bit data
+    pub fn as_i16_slice(&self) -> Option<&[i16]> {
+        match self {
+            Self::Bit8Data(_) => None,
+            Self::Bit16Data(v) => Some(v),
+        }
+    }
+
+    /// Converts the samples to floats, dividing by 128 or 32768 so the full range of either bit
+    /// depth maps into `-1.0..1.0`
+    pub fn to_f32(&self) -> Vec<f32> {
+        (0..self.len()).filter_map(|e| self.get(e)).collect()
+    }
+
+    /// Finds the shortest period the loop region of `header` repeats with, in the same units as
+    /// `XmSampleHeader::loop_length` (bytes). Returns `None` if the sample doesn't loop or the
+    /// loop lies outside of the sample data.
+    pub fn minimal_loop(&self, header: &XmSampleHeader) -> Option<usize> {
+        if !header.is_looping() {
+            return None;
+        }
+
+        let bytes_per_sample = match self {
+            Self::Bit8Data(_) => 1,
+            Self::Bit16Data(_) => 2,
+        };
+
+        let start = header.loop_start as usize / bytes_per_sample;
+        let length = header.loop_length as usize / bytes_per_sample;
+
+        let period = match self {
+            Self::Bit8Data(v) => minimal_period(v.get(start..start + length)?),
+            Self::Bit16Data(v) => minimal_period(v.get(start..start + length)?),
+        }?;
+
+        Some(period * bytes_per_sample)
+    }
+
+    /// The sample at `pos` as a float, scaled the same way as `to_f32`
     pub fn get(&self, pos: usize) -> Option<f32> {
         match self {
-            Self::Bit8Data(v) => v.get(pos).map(|e| (*e as f32) / i8::MAX as f32),
-            Self::Bit16Data(v) => v.get(pos).map(|e| (*e as f32) / i16::MAX as f32),
+            Self::Bit8Data(v) => v.get(pos).map(|e| *e as f32 / 128.0),
+            Self::Bit16Data(v) => v.get(pos).map(|e| *e as f32 / 32768.0),
         }
     }
 
+    #[cfg(feature = "std")]
     pub fn get_interpolated(
         &self,
         pos: f32,
         reverse: bool,
         resampling: XmResamplingType,
     ) -> Option<f32> {
-        if pos.ceil() as usize >= self.len() || pos < 0.0 {
+        self.interpolate(pos, reverse, resampling, None)
+    }
+
+    /// Same as `get_interpolated` played forwards, except that the neighbours past the end of
+    /// the forward loop `loop_range` wrap around to its start, and the one before its start
+    /// wraps around to its end
+    #[cfg(feature = "std")]
+    pub(crate) fn get_interpolated_looped(
+        &self,
+        pos: f32,
+        loop_range: core::ops::Range<usize>,
+        resampling: XmResamplingType,
+    ) -> Option<f32> {
+        self.interpolate(pos, false, resampling, Some(loop_range))
+    }
+
+    #[cfg(feature = "std")]
+    fn interpolate(
+        &self,
+        pos: f32,
+        reverse: bool,
+        resampling: XmResamplingType,
+        loop_range: Option<core::ops::Range<usize>>,
+    ) -> Option<f32> {
+        // neighbours past either end are clamped to the edge samples below, so only the position
+        // itself has to land inside of the buffer
+        if pos < 0.0 || pos.floor() as usize >= self.len() {
             return None;
         }
 
         let sample_index = pos.floor() as usize;
-        let previous_sample_index = || {
-            if sample_index != 0 {
-                sample_index - 1
-            } else {
-                sample_index
+        let neighbour = |offset: usize| {
+            let index = sample_index + offset;
+
+            match loop_range {
+                Some(ref range) if index >= range.end && range.contains(&sample_index) => {
+                    range.start + (index - range.start) % range.len()
+                }
+                _ => index.min(self.len() - 1),
             }
         };
-        let next_sample_index = || {
-            if sample_index < self.len() - 1 {
-                sample_index + 1
+        let next_sample_index = || neighbour(1);
+
+        // playing backwards blends between the same two samples, just starting from the upper one
+        let step = || {
+            if reverse {
+                1.0 - (pos - pos.floor())
             } else {
-                sample_index
+                pos - pos.floor()
             }
         };
 
-        let step = || pos - pos.floor();
+        // positions without interpolation and whole positions played forwards, which are all of
+        // them at the sample's own rate, read a single sample
+        if let XmResamplingType::NoInterpolation = resampling {
+            return self.get(if reverse {
+                next_sample_index()
+            } else {
+                sample_index
+            });
+        }
+        if !reverse && pos == pos.floor() {
+            return self.get(sample_index);
+        }
 
         let (Some(first), Some(second)) = (if reverse {
-            (self.get(previous_sample_index()), self.get(sample_index))
+            (self.get(next_sample_index()), self.get(sample_index))
         } else {
             (self.get(sample_index), self.get(next_sample_index()))
         }) else {
@@ -174,17 +460,47 @@ impl XmSamplePcmData {
         };
 
         match resampling {
-            // second and third argument are ignored for NoInterpolation
-            XmResamplingType::NoInterpolation => {
-                Some(interpolation::NoInterpolation::interpolate(first, 0.0, 0.0))
-            }
+            XmResamplingType::NoInterpolation => Some(first),
             XmResamplingType::LinearInterpolation => Some(
                 interpolation::LinearInterpolation::interpolate(first, second, step()),
             ),
+            // the outer neighbours are clamped to the edges the same way, and the one before the
+            // start of the loop wraps around to its end
+            XmResamplingType::CubicInterpolation => {
+                let previous_index = match loop_range {
+                    Some(ref range) if sample_index == range.start && !range.is_empty() => {
+                        range.end - 1
+                    }
+                    _ => sample_index.saturating_sub(1),
+                };
+                let previous = self.get(previous_index)?;
+                let after_next = self.get(neighbour(2))?;
+
+                let points = if reverse {
+                    [after_next, first, second, previous]
+                } else {
+                    [previous, first, second, after_next]
+                };
+
+                Some(interpolation::CubicInterpolation::interpolate(
+                    points,
+                    step(),
+                ))
+            }
         }
     }
 }
 
+/// Only divisors of the length are considered, so the shortened loop still lines up with the
+/// original loop end
+fn minimal_period<T: PartialEq>(data: &[T]) -> Option<usize> {
+    let length = data.len();
+
+    (1..=length)
+        .filter(|period| length.is_multiple_of(*period))
+        .find(|&period| data.iter().zip(&data[period..]).all(|(a, b)| a == b))
+}
+
 fn parse_envelope_point(data: &[u8]) -> IResult<&[u8], XmEnvelopePoint> {
     let (input, (x, y)) =
         tuple((nom::number::complete::le_u16, nom::number::complete::le_u16))(data)?;
@@ -207,12 +523,9 @@ fn parse_envelope_type(data: &[u8]) -> IResult<&[u8], XmEnvelopeType> {
 fn parse_vibrato_type(data: &[u8]) -> IResult<&[u8], XmVibratoType> {
     let (input, byte) = nom::number::complete::u8(data)?;
 
-    match byte {
-        0 => Ok((input, XmVibratoType::Sine)),
-        1 => Ok((input, XmVibratoType::Square)),
-        2 => Ok((input, XmVibratoType::RampDown)),
-        3 => Ok((input, XmVibratoType::RampUp)),
-        _ => Err(nom::Err::Error(nom::error::Error::from_error_kind(
+    match XmVibratoType::from_bits(byte) {
+        Some(kind) => Ok((input, kind)),
+        None => Err(nom::Err::Error(nom::error::Error::from_error_kind(
             input,
             nom::error::ErrorKind::Verify,
         ))),
@@ -359,16 +672,15 @@ pub(crate) fn parse_instrument_header(data: &[u8]) -> IResult<&[u8], XmInstrumen
     let (input, sample_opts) =
         nom::combinator::cond(samples_num > 0, parse_instrument_sample_opts)(input)?;
 
-    let (input, _excess_data) = match sample_opts {
-        Some(_) => nom::combinator::cond(
-            header_size as usize > XM_INSTRUMENT_HEADER_SIZE_W_OPTS,
-            nom::bytes::complete::take(header_size as usize - XM_INSTRUMENT_HEADER_SIZE_W_OPTS),
-        )(input)?,
-        None => nom::combinator::cond(
-            header_size as usize > XM_INSTRUMENT_HEADER_SIZE,
-            nom::bytes::complete::take(header_size as usize - XM_INSTRUMENT_HEADER_SIZE),
-        )(input)?,
+    let parsed_size = match sample_opts {
+        Some(_) => XM_INSTRUMENT_HEADER_SIZE_W_OPTS,
+        None => XM_INSTRUMENT_HEADER_SIZE,
     };
+    // the parser of `cond` is built even if the condition doesn't hold, so don't underflow
+    let (input, extra) = nom::combinator::cond(
+        header_size as usize > parsed_size,
+        nom::bytes::complete::take((header_size as usize).saturating_sub(parsed_size)),
+    )(input)?;
 
     Ok((
         input,
@@ -378,6 +690,7 @@ pub(crate) fn parse_instrument_header(data: &[u8]) -> IResult<&[u8], XmInstrumen
             kind,
             samples_num,
             sample_opts,
+            extra: extra.map_or(vec![], |e| e.to_vec()),
         },
     ))
 }
@@ -410,6 +723,16 @@ pub(crate) fn parse_sample_header(data: &[u8]) -> IResult<&[u8], XmSampleHeader>
         crate::fixed_length_string(22), // Sample name
     ))(data)?;
 
+    // loops running past the end of the sample are cut short like FT2 does, empty ones disable
+    // looping altogether
+    let loop_start = loop_start.min(length);
+    let loop_length = loop_length.min(length - loop_start);
+    let kind = if loop_length == 0 {
+        kind.with_loop_type(XmSampleLoopType::NoLoop)
+    } else {
+        kind
+    };
+
     Ok((
         input,
         XmSampleHeader {
@@ -426,71 +749,128 @@ pub(crate) fn parse_sample_header(data: &[u8]) -> IResult<&[u8], XmSampleHeader>
     ))
 }
 
-fn decode_dpcm_data(
+pub(crate) fn decode_dpcm_data(
     length: usize,
     depth: XmSampleBitDepth,
 ) -> impl FnMut(&[u8]) -> IResult<&[u8], XmSamplePcmData> {
     move |data| {
-        let mut previous = match depth {
-            XmSampleBitDepth::Bit8 => Either::Left(0i8),
-            XmSampleBitDepth::Bit16 => Either::Right(0i16),
-            XmSampleBitDepth::Unknown => {
-                return Err(nom::Err::Error(nom::error::Error::from_error_kind(
-                    data,
-                    nom::error::ErrorKind::Verify,
-                )))
-            }
-        };
+        if matches!(depth, XmSampleBitDepth::Unknown) {
+            return Err(nom::Err::Error(nom::error::Error::from_error_kind(
+                data,
+                nom::error::ErrorKind::Verify,
+            )));
+        }
 
-        match previous {
-            Either::Left(ref mut previous) => {
-                let mut out = vec![];
-                let (input, samples) = nom::multi::count(nom::number::complete::i8, length)(data)?;
+        let (data, _) = crate::verify_remaining(data, length, 1)?;
 
-                for sample in samples {
-                    *previous = sample.wrapping_add(*previous);
-                    out.push(*previous);
-                }
+        // the deltas are summed straight into the output, a trailing odd byte of 16 bit data is
+        // left unread
+        match depth {
+            XmSampleBitDepth::Bit16 => {
+                let (input, deltas) = nom::bytes::complete::take(length & !1)(data)?;
+                let mut out = Vec::with_capacity(length / 2);
+                let mut previous = 0i16;
+
+                out.extend(deltas.chunks_exact(2).map(|e| {
+                    previous = previous.wrapping_add(i16::from_le_bytes([e[0], e[1]]));
+                    previous
+                }));
+
+                Ok((input, XmSamplePcmData::Bit16Data(out)))
+            }
+            _ => {
+                let (input, deltas) = nom::bytes::complete::take(length)(data)?;
+                let mut out = Vec::with_capacity(length);
+                let mut previous = 0i8;
+
+                out.extend(deltas.iter().map(|&e| {
+                    previous = previous.wrapping_add(e as i8);
+                    previous
+                }));
 
                 Ok((input, XmSamplePcmData::Bit8Data(out)))
             }
-            Either::Right(ref mut previous) => {
-                let mut out = vec![];
-                let (input, samples) =
-                    nom::multi::count(nom::number::complete::le_i16, length / 2)(data)?;
-
-                for sample in samples {
-                    *previous = sample.wrapping_add(*previous);
-                    out.push(*previous);
-                }
+        }
+    }
+}
 
-                Ok((input, XmSamplePcmData::Bit16Data(out)))
+/// Inverse of `decode_dpcm_data`, stores every sample as the wrapping difference to the
+/// previous one
+pub(crate) fn encode_dpcm_data(data: &XmSamplePcmData) -> Vec<u8> {
+    let mut out = vec![];
+
+    match data {
+        XmSamplePcmData::Bit8Data(v) => {
+            let mut previous = 0i8;
+
+            for sample in v {
+                out.push(sample.wrapping_sub(previous) as u8);
+                previous = *sample;
+            }
+        }
+        XmSamplePcmData::Bit16Data(v) => {
+            let mut previous = 0i16;
+
+            for sample in v {
+                out.extend_from_slice(&sample.wrapping_sub(previous).to_le_bytes());
+                previous = *sample;
             }
         }
     }
+
+    out
 }
 
-pub(crate) fn parse(
+/// Parses an instrument header along with its sample headers, but not the sample data
+pub(crate) fn parse_headers(
     data: &[u8],
-) -> IResult<&[u8], (XmInstrumentHeader, Vec<(XmSampleHeader, XmSamplePcmData)>)> {
+) -> IResult<&[u8], (XmInstrumentHeader, Vec<XmSampleHeader>)> {
     let (input, instr_header) = parse_instrument_header(data)?;
     if instr_header.samples_num == 0 {
         return Ok((input, (instr_header, vec![])));
     }
 
-    let (mut input, sample_headers) =
+    let (input, _) = crate::verify_remaining(
+        input,
+        instr_header.samples_num as usize,
+        XM_SAMPLE_HEADER_SIZE,
+    )?;
+    let (input, sample_headers) =
         nom::multi::count(parse_sample_header, instr_header.samples_num as usize)(input)?;
 
+    Ok((input, (instr_header, sample_headers)))
+}
+
+/// Decodes the delta encoded data of a single sample, `data` starts where its data is stored,
+/// which is right after the previous sample's data
+pub fn parse_sample<'a>(
+    header: &XmSampleHeader,
+    data: &'a [u8],
+) -> IResult<&'a [u8], XmSamplePcmData> {
+    decode_dpcm_data(header.length as usize, header.kind.depth())(data)
+}
+
+/// Parses the sample data following the sample headers of an instrument
+pub(crate) fn parse_sample_data<'a>(
+    mut input: &'a [u8],
+    sample_headers: &[XmSampleHeader],
+) -> IResult<&'a [u8], Vec<XmSamplePcmData>> {
     let mut sample_data_entries = vec![];
-    for mut parser in sample_headers
-        .iter()
-        .map(|e| decode_dpcm_data(e.length as usize, e.kind.depth()))
-    {
-        let (input_, sample_data_entry) = parser(input)?;
+    for header in sample_headers {
+        let (input_, sample_data_entry) = parse_sample(header, input)?;
         input = input_;
         sample_data_entries.push(sample_data_entry);
     }
 
+    Ok((input, sample_data_entries))
+}
+
+pub(crate) fn parse(
+    data: &[u8],
+) -> IResult<&[u8], (XmInstrumentHeader, Vec<(XmSampleHeader, XmSamplePcmData)>)> {
+    let (input, (instr_header, sample_headers)) = parse_headers(data)?;
+    let (input, sample_data_entries) = parse_sample_data(input, &sample_headers)?;
+
     Ok((
         input,
         (
@@ -517,6 +897,19 @@ impl XmSampleBitDepth {
     }
 }
 
+impl XmVibratoType {
+    // FT2's order, the discriminants match the stored bytes
+    pub(crate) const fn from_bits(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(XmVibratoType::Sine),
+            1 => Some(XmVibratoType::Square),
+            2 => Some(XmVibratoType::RampDown),
+            3 => Some(XmVibratoType::RampUp),
+            _ => None,
+        }
+    }
+}
+
 impl XmSampleLoopType {
     const fn from_bits(value: u8) -> Self {
         match value {
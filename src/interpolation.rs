@@ -4,15 +4,13 @@ pub trait Interpolation {
 
 pub struct LinearInterpolation;
 
-pub struct SincLinearInterpolation;
-
 pub struct CubicInterpolation;
 
 pub struct NoInterpolation;
 
 impl Interpolation for LinearInterpolation {
     fn interpolate(v0: f32, v1: f32, t: f32) -> f32 {
-        (v0 + t) * (v1 - v0)
+        v0 + t * (v1 - v0)
     }
 }
 
@@ -22,14 +20,14 @@ impl Interpolation for NoInterpolation {
     }
 }
 
-impl Interpolation for SincLinearInterpolation {
-    fn interpolate(v0: f32, v1: f32, t: f32) -> f32 {
-        todo!();
-    }
-}
+// needs the neighbours on both sides, so it doesn't fit `Interpolation`
+impl CubicInterpolation {
+    /// Catmull-Rom spline between `v[1]` and `v[2]`
+    pub fn interpolate(v: [f32; 4], t: f32) -> f32 {
+        let a = -0.5 * v[0] + 1.5 * v[1] - 1.5 * v[2] + 0.5 * v[3];
+        let b = v[0] - 2.5 * v[1] + 2.0 * v[2] - 0.5 * v[3];
+        let c = -0.5 * v[0] + 0.5 * v[2];
 
-impl Interpolation for CubicInterpolation {
-    fn interpolate(v0: f32, v1: f32, t: f32) -> f32 {
-        todo!();
+        ((a * t + b) * t + c) * t + v[1]
     }
 }
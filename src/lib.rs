@@ -1,6 +1,38 @@
-use nom::{bytes::complete::take, combinator::map_res, error::ParseError, IResult};
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use alloc::{
+    borrow::ToOwned,
+    collections::BTreeSet,
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+
+use nom::{bytes::complete::take, error::ParseError, IResult};
+
+/// Implements serde traits for a bitfield type through its raw bits
+#[cfg(feature = "serde")]
+macro_rules! bitfield_serde {
+    ($name:ty, $bits:ty) => {
+        impl serde::Serialize for $name {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serde::Serialize::serialize(&self.into_bits(), serializer)
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $name {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                <$bits as serde::Deserialize>::deserialize(deserializer).map(Self::from_bits)
+            }
+        }
+    };
+}
 
 pub mod effect;
+pub mod extension;
 pub mod header;
 pub mod instrument;
 pub mod note;
@@ -9,61 +41,535 @@ pub mod pattern;
 pub mod interpolation;
 pub mod frequency;
 
+// the playback engine needs the float math of `std`
+#[cfg(feature = "std")]
 pub mod context;
 
-#[cfg(test)]
+pub mod write;
+
+pub mod export;
+pub mod import;
+
+#[cfg(all(test, feature = "std"))]
 mod tests;
 
+/// Controls how closely modules have to follow the spec to be accepted
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ParseOptions {
+    /// Rejects unknown volume column commands (`0x51..=0x5F`), more than 127 channels and more
+    /// than 256 rows per pattern. Unknown effects are always kept as `XmEffect::Unknown`.
+    pub strict: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self { strict: true }
+    }
+}
+
+/// Errors from `parse_reader`
+#[derive(Debug)]
+pub enum XmError {
+    #[cfg(feature = "std")]
+    Io(std::io::Error),
+    /// The data isn't a module this parser understands. Holds the offset parsing stopped at.
+    Parse {
+        offset: usize,
+        kind: nom::error::ErrorKind,
+    },
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for XmError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl core::fmt::Display for XmError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            #[cfg(feature = "std")]
+            XmError::Io(e) => write!(f, "failed to read the module: {}", e),
+            XmError::Parse { offset, kind } => {
+                write!(
+                    f,
+                    "failed to parse the module at byte {}: {:?}",
+                    offset, kind
+                )
+            }
+        }
+    }
+}
+
+impl core::error::Error for XmError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            #[cfg(feature = "std")]
+            XmError::Io(e) => Some(e),
+            XmError::Parse { .. } => None,
+        }
+    }
+}
+
 pub type XmSample = (instrument::XmSampleHeader, instrument::XmSamplePcmData);
 
 pub type XmInstrumentCollection = Vec<(instrument::XmInstrumentHeader, Vec<XmSample>)>;
 
+/// Upper bound on the rows `XmModule::duration` and the time mapping walk through, which is
+/// over 16 plays of a song with 256 orders of 256 row patterns
+pub const XM_MAX_WALKED_ROWS: usize = 1 << 20;
+
 pub type XmPattern = (pattern::XmPatternHeader, pattern::XmPatternRows);
 
 pub type XmPatternCollection = Vec<XmPattern>;
 
-#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct XmModule {
     pub header: header::XmHeader,
     pub patterns: XmPatternCollection,
     pub instruments: XmInstrumentCollection,
     pub pattern_order_table: pattern::XmPatternOrderTable,
+    /// Data OpenMPT appends after the instruments
+    pub extensions: extension::XmExtensions,
+}
+
+impl XmModule {
+    /// Number of orders which are actually played
+    pub(crate) fn song_length(&self) -> usize {
+        (self.header.song_length as usize).min(self.pattern_order_table.len())
+    }
+
+    /// Iterates over the patterns in the order they're played in, skipping over the ModPlug
+    /// order markers and stopping at the end marker. Orders referring to missing patterns are
+    /// skipped as well.
+    ///
+    /// ```no_run
+    /// let data = std::fs::read("song.xm").unwrap();
+    /// let (_, module) = xmia::parse(&data).unwrap();
+    ///
+    /// for (header, rows) in module.ordered_patterns() {
+    ///     assert_eq!(header.rows_num as usize, rows.iter().count());
+    ///
+    ///     for row in rows {
+    ///         println!("{}", row);
+    ///     }
+    /// }
+    /// ```
+    pub fn ordered_patterns(&self) -> impl Iterator<Item = &XmPattern> {
+        self.order_entries()
+            .take_while(|e| *e != pattern::XmOrderEntry::End)
+            .filter_map(|e| match e {
+                pattern::XmOrderEntry::Pattern(index) => self.patterns.get(index as usize),
+                _ => None,
+            })
+    }
+
+    /// The played part of the order table, with the ModPlug markers told apart from pattern
+    /// indices
+    pub fn order_entries(&self) -> impl Iterator<Item = pattern::XmOrderEntry> + '_ {
+        self.pattern_order_table[..self.song_length()]
+            .iter()
+            .map(|&e| pattern::XmOrderEntry::from(e))
+    }
+
+    /// Names of the instruments, in the order they're stored in. Trackers pad names with
+    /// spaces, which are cut off.
+    pub fn instrument_names(&self) -> Vec<&str> {
+        self.instruments
+            .iter()
+            .map(|(header, _)| header.name.trim_end())
+            .collect()
+    }
+
+    /// Names of the samples of every instrument, flattened in the order they're stored in and
+    /// cut off like `instrument_names`
+    pub fn sample_names(&self) -> Vec<&str> {
+        self.instruments
+            .iter()
+            .flat_map(|(_, samples)| samples.iter().map(|(header, _)| header.name.trim_end()))
+            .collect()
+    }
+
+    /// 0-based indices into `instruments` of the instruments referenced anywhere in the pattern
+    /// data. References to instruments which don't exist are left out.
+    pub fn used_instruments(&self) -> BTreeSet<u8> {
+        self.patterns
+            .iter()
+            .flat_map(|(_, rows)| rows.iter())
+            .flat_map(|row| row.iter())
+            // patterns count instruments from 1
+            .filter_map(|slot| slot.instrument_index()?.checked_sub(1))
+            .filter(|&e| (e as usize) < self.instruments.len())
+            .collect()
+    }
+
+    /// 0-based indices into `instruments` of the instruments no pattern refers to
+    pub fn unused_instruments(&self) -> BTreeSet<u8> {
+        let used = self.used_instruments();
+
+        (0..self.instruments.len().min(u8::MAX as usize + 1))
+            .map(|e| e as u8)
+            .filter(|e| !used.contains(e))
+            .collect()
+    }
+
+    /// Indices of the samples each instrument plays, following the song order. Notes without an
+    /// instrument play the last one used on their channel, and pick the sample through its
+    /// keymap. The outer `Vec` lines up with `instruments`.
+    pub fn used_samples(&self) -> Vec<BTreeSet<u8>> {
+        let mut used = vec![BTreeSet::new(); self.instruments.len()];
+        let mut channel_instruments = vec![None; self.header.channels_num as usize];
+
+        for (_, rows) in self.ordered_patterns() {
+            for row in rows.iter() {
+                for (slot, channel_instrument) in row.iter().zip(channel_instruments.iter_mut()) {
+                    if let Some(index) = slot.instrument_index() {
+                        *channel_instrument = index.checked_sub(1).map(|e| e as usize);
+                    }
+
+                    let Some(index) = *channel_instrument else {
+                        continue;
+                    };
+                    let Some((header, samples)) = self.instruments.get(index) else {
+                        continue;
+                    };
+
+                    if let Some(sample) = header.sample_index(slot.note()) {
+                        if (sample as usize) < samples.len() {
+                            used[index].insert(sample);
+                        }
+                    }
+                }
+            }
+        }
+
+        used
+    }
+
+    /// Channels with at least one non-empty slot in any pattern
+    pub fn used_channels(&self) -> BTreeSet<u16> {
+        self.patterns
+            .iter()
+            .flat_map(|(_, rows)| rows.iter())
+            .flat_map(|row| row.iter().enumerate())
+            .filter(|(_, slot)| **slot != pattern::XmPatternSlot::default())
+            .map(|(channel, _)| channel as u16)
+            .collect()
+    }
+
+    /// Renders the patterns in the order they're played in as a tracker style grid, with a
+    /// header of channel labels and the row indices in hex
+    pub fn dump_patterns(&self) -> String {
+        let mut out = String::new();
+
+        for (order, entry) in self.order_entries().enumerate() {
+            let entry = match entry {
+                pattern::XmOrderEntry::Pattern(index) => index,
+                pattern::XmOrderEntry::Skip => continue,
+                pattern::XmOrderEntry::End => break,
+            };
+
+            let Some((_, rows)) = self.patterns.get(entry as usize) else {
+                continue;
+            };
+
+            out += &format!("Order {:02X}, pattern {:02X}\n", order, entry);
+
+            // lines up with the row indices and the slots of `XmPatternRow`'s `Display`
+            out += "   ";
+            for channel in 0..self.header.channels_num {
+                out += &format!("|{:<11}", format!("Ch {}", channel + 1));
+            }
+            out += "|\n";
+
+            for (index, row) in rows.iter().enumerate() {
+                out += &format!("{:02X} {}\n", index, row);
+            }
+
+            out += "\n";
+        }
+
+        out
+    }
+
+    /// Walks the song once from the first order, without looping, calling `f` with the order,
+    /// row, ticks and seconds per tick of every row until it returns `Some(_)`. Rows repeated by
+    /// `EEx` are walked once, with all of their ticks. Tempo changes through `Fxx`, position
+    /// jumps, pattern breaks and pattern loops are followed along the way, the same way playback
+    /// does. The walk ends when a jump lands on a row that was already played, since that's
+    /// where the song starts looping, and gives up after `XM_MAX_WALKED_ROWS` rows in case
+    /// pattern loops never settle.
+    fn walk_rows<T>(&self, mut f: impl FnMut(u32, u32, u16, f64) -> Option<T>) -> Option<T> {
+        let mut tempo = self.header.default_tempo;
+        let mut bpm = self.header.default_bpm;
+
+        let mut cursor = pattern::XmRowCursor::new(self);
+        if cursor.settle(self) {
+            return None;
+        }
+
+        for _ in 0..XM_MAX_WALKED_ROWS {
+            let (order, row_index) = (cursor.order(), cursor.row());
+            let slots = self
+                .pattern_order_table
+                .get(order)
+                .and_then(|e| self.patterns.get(*e as usize))
+                .and_then(|e| e.1 .0.get(row_index))
+                .map_or(&[][..], |e| &e.0[..]);
+
+            for slot in slots {
+                match slot.effect {
+                    Some(effect::XmEffect::SetTempo(0)) => {}
+                    Some(effect::XmEffect::SetTempo(a)) if a < 0x20 => tempo = a as u16,
+                    Some(effect::XmEffect::SetTempo(a)) => bpm = a as u16,
+                    _ => {}
+                }
+            }
+
+            // FT2 manual says number of ticks / second = BPM * 0.4, a BPM of 0 plays like 1 the
+            // same way it does in playback
+            let tick_duration = 1.0 / (bpm.max(1) as f64 * 0.4);
+            let ticks = tempo * (1 + pattern::pattern_delay(slots) as u16);
+
+            if let Some(v) = f(order as u32, row_index as u32, ticks, tick_duration) {
+                return Some(v);
+            }
+
+            cursor.leave_row(slots);
+            if cursor.settle(self) {
+                return None;
+            }
+        }
+
+        None
+    }
+
+    /// Length of the song when played once from the first order at `sample_rate`, without
+    /// looping. Jumps back to rows that were already played end the song, and pattern loops that
+    /// never settle are cut off after `XM_MAX_WALKED_ROWS` rows.
+    #[cfg(feature = "std")]
+    pub fn duration(&self, sample_rate: u32) -> core::time::Duration {
+        let mut frames = 0.0;
+
+        self.walk_rows(|_, _, ticks, tick_duration| {
+            frames += ticks as f64 * tick_duration * sample_rate as f64;
+            None::<()>
+        });
+
+        core::time::Duration::from_secs_f64(frames.ceil() / sample_rate.max(1) as f64)
+    }
+
+    /// Converts a playback time in seconds to an `(order, row, tick)` position. Returns `None`
+    /// if the song ends before `seconds`.
+    pub fn time_to_position(&self, seconds: f64) -> Option<(u32, u32, u32)> {
+        let mut row_start = 0.0;
+
+        self.walk_rows(|order, row, ticks, tick_duration| {
+            let row_end = row_start + ticks as f64 * tick_duration;
+
+            if seconds < row_end {
+                let tick = ((seconds - row_start).max(0.0) / tick_duration) as u32;
+                return Some((order, row, tick));
+            }
+
+            row_start = row_end;
+            None
+        })
+    }
+
+    /// Converts an `(order, row, tick)` position to the playback time in seconds it starts at.
+    /// Returns `None` if the position isn't part of the song.
+    pub fn position_to_time(&self, order: u32, row: u32, tick: u32) -> Option<f64> {
+        let mut row_start = 0.0;
+
+        self.walk_rows(|current_order, current_row, ticks, tick_duration| {
+            if (current_order, current_row) == (order, row) {
+                return (tick < ticks as u32).then_some(row_start + tick as f64 * tick_duration);
+            }
+
+            row_start += ticks as f64 * tick_duration;
+            None
+        })
+    }
+
+    /// Number of rows the pattern at `index` plays for. Like in FT2, patterns which don't exist
+    /// play as empty patterns of `XM_EMPTY_PATTERN_ROWS` rows.
+    pub(crate) fn pattern_rows_num(&self, index: u8) -> usize {
+        self.patterns
+            .get(index as usize)
+            .map_or(pattern::XM_EMPTY_PATTERN_ROWS, |e| e.1 .0.len())
+    }
+
+    /// Resolves `restart_pos` to the order playback loops back to, skipping over any markers.
+    /// Returns `None` if there's no pattern to play from `restart_pos` onwards.
+    pub fn restart_order(&self) -> Option<usize> {
+        (self.header.restart_pos as usize..self.song_length())
+            .find(|&e| !pattern::is_order_marker(self.pattern_order_table[e]))
+    }
+}
+
+/// Code page 437 characters of the bytes from 0x80 up, the character set of the DOS trackers
+const CP437_HIGH_CHARS: &str = concat!(
+    "ÇüéâäàåçêëèïîìÄÅ",
+    "ÉæÆôöòûùÿÖÜ¢£¥₧ƒ",
+    "áíóúñÑªº¿⌐¬½¼¡«»",
+    "░▒▓│┤╡╢╖╕╣║╗╝╜╛┐",
+    "└┴┬├─┼╞╟╚╔╩╦╠═╬╧",
+    "╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀",
+    "αßΓπΣσµτΦΘΩδ∞φε∩",
+    "≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{A0}",
+);
+
+/// Decodes a name, names which aren't valid UTF-8 were most likely written by a DOS tracker and
+/// are read as code page 437. Trailing `\0` padding is cut off.
+pub(crate) fn decode_name(bytes: &[u8]) -> String {
+    let name = match core::str::from_utf8(bytes) {
+        Ok(name) => name.to_string(),
+        Err(_) => bytes
+            .iter()
+            .map(|&e| match e {
+                0x00..=0x7F => e as char,
+                _ => CP437_HIGH_CHARS
+                    .chars()
+                    .nth((e - 0x80) as usize)
+                    .unwrap_or('?'),
+            })
+            .collect(),
+    };
+
+    name.trim_end_matches('\0').to_owned()
 }
 
 fn fixed_length_string<'a>(length: usize) -> impl FnMut(&'a [u8]) -> IResult<&'a [u8], String> {
-    move |input| {
-        map_res(take(length), |bytes: &[u8]| {
-            std::str::from_utf8(bytes)
-                .map(|s| s.trim_end_matches('\0').to_string())
-                .map_err(|_| {
-                    nom::Err::Error(nom::error::Error::from_error_kind(
-                        input,
-                        nom::error::ErrorKind::MapRes,
-                    ))
-                })
-        })(input)
-        .map(|e| (e.0, e.1.to_owned()))
+    nom::combinator::map(take(length), decode_name)
+}
+
+/// Fails right away if `input` is too short to hold `count` items of at least `min_size` bytes
+/// each, so bogus counts in a truncated file don't drive `nom::multi::count` or allocations
+pub(crate) fn verify_remaining(input: &[u8], count: usize, min_size: usize) -> IResult<&[u8], ()> {
+    if count.saturating_mul(min_size) > input.len() {
+        return Err(nom::Err::Error(nom::error::Error::from_error_kind(
+            input,
+            nom::error::ErrorKind::Eof,
+        )));
     }
+
+    Ok((input, ()))
 }
 
 pub fn parse(data: &[u8]) -> IResult<&[u8], XmModule> {
-    let (input, header) = header::parse(data)?;
-    let (input, pattern_order_table) =
-        pattern::parse_order_table_raw(input, header.0.song_length as usize, header.3 as usize)?;
+    parse_with_options(data, ParseOptions::default())
+}
+
+pub fn parse_with_options(data: &[u8], options: ParseOptions) -> IResult<&[u8], XmModule> {
+    let (input, mut header) = header::parse(data, options.strict)?;
+    let (input, (pattern_order_table, extra)) =
+        pattern::parse_order_table_raw(input, header.0.song_length as usize, header.2 as usize)?;
+    header.0.extra = extra.to_vec();
+
+    if header.0.version < header::XM_SUPPORTED_VERSION {
+        return parse_legacy(input, header.0, pattern_order_table, options);
+    }
+
+    let patterns_num = header.0.patterns_num as usize;
+    let instruments_num = header.0.instruments_num as usize;
+
+    let (input, _) = verify_remaining(input, patterns_num, pattern::XM_PATTERN_HEADER_SIZE)?;
     let (input, patterns) = nom::multi::count(
-        pattern::parse(header.0.channels_num),
-        header.0.patterns_num as usize,
+        pattern::parse(header.0.channels_num, header.0.version, options.strict),
+        patterns_num,
     )(input)?;
-    let (input, instruments) =
-        nom::multi::count(instrument::parse, header.0.instruments_num as usize)(input)?;
+    let (input, _) = verify_remaining(
+        input,
+        instruments_num,
+        instrument::XM_INSTRUMENT_HEADER_SIZE,
+    )?;
+    let (input, instruments) = nom::multi::count(instrument::parse, instruments_num)(input)?;
+    let (input, extensions) = extension::parse(input, instruments_num);
 
     Ok((
         input,
         XmModule {
             header: header.0,
-            patterns: patterns.into_iter().map(|e| (e.0, e.1)).collect::<Vec<_>>(),
+            patterns,
             instruments,
             pattern_order_table,
+            extensions,
         },
     ))
 }
+
+/// Versions before 1.04 store every instrument and sample header first, then the patterns, and
+/// the sample data of all instruments last
+fn parse_legacy(
+    input: &[u8],
+    header: header::XmHeader,
+    pattern_order_table: pattern::XmPatternOrderTable,
+    options: ParseOptions,
+) -> IResult<&[u8], XmModule> {
+    let patterns_num = header.patterns_num as usize;
+    let instruments_num = header.instruments_num as usize;
+
+    let (input, _) = verify_remaining(
+        input,
+        instruments_num,
+        instrument::XM_INSTRUMENT_HEADER_SIZE,
+    )?;
+    let (input, instrument_headers) =
+        nom::multi::count(instrument::parse_headers, instruments_num)(input)?;
+    // 1.02 pattern headers are a byte shorter
+    let (input, _) = verify_remaining(input, patterns_num, pattern::XM_PATTERN_HEADER_SIZE - 1)?;
+    let (mut input, patterns) = nom::multi::count(
+        pattern::parse(header.channels_num, header.version, options.strict),
+        patterns_num,
+    )(input)?;
+
+    let mut instruments = vec![];
+    for (instrument_header, sample_headers) in instrument_headers {
+        let (input_, sample_data_entries) = instrument::parse_sample_data(input, &sample_headers)?;
+        input = input_;
+
+        instruments.push((
+            instrument_header,
+            sample_headers
+                .into_iter()
+                .zip(sample_data_entries)
+                .collect::<Vec<_>>(),
+        ));
+    }
+
+    let (input, extensions) = extension::parse(input, instruments_num);
+
+    Ok((
+        input,
+        XmModule {
+            header,
+            patterns,
+            instruments,
+            pattern_order_table,
+            extensions,
+        },
+    ))
+}
+
+/// Reads a whole module out of `reader`, for example a `File` or a symphonia
+/// `MediaSourceStream`. The data is buffered internally, so the module doesn't borrow from it.
+#[cfg(feature = "std")]
+pub fn parse_reader<R: std::io::Read>(mut reader: R) -> Result<XmModule, XmError> {
+    let mut data = vec![];
+    reader.read_to_end(&mut data)?;
+
+    match parse(&data) {
+        Ok((_, module)) => Ok(module),
+        Err(nom::Err::Error(e) | nom::Err::Failure(e)) => Err(XmError::Parse {
+            offset: data.len() - e.input.len(),
+            kind: e.code,
+        }),
+        Err(nom::Err::Incomplete(_)) => Err(XmError::Parse {
+            offset: data.len(),
+            kind: nom::error::ErrorKind::Eof,
+        }),
+    }
+}
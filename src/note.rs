@@ -4,6 +4,19 @@ use nom::{error::ParseError, IResult};
 pub enum XmNoteRaw {
     NoNote = 0,
 
+    C0,
+    CS0,
+    D0,
+    DS0,
+    E0,
+    F0,
+    FS0,
+    G0,
+    GS0,
+    A0,
+    AS0,
+    B0,
+
     C1,
     CS1,
     D1,
@@ -95,28 +108,20 @@ pub enum XmNoteRaw {
     AS7,
     B7,
 
-    C8,
-    CS8,
-    D8,
-    DS8,
-    E8,
-    F8,
-    FS8,
-    G8,
-    GS8,
-    A8,
-    AS8,
-    B8,
-
     NoteOff,
 }
 
 pub const XM_TONE_COUNT: u8 = 12;
-pub const XM_MAX_OCTAVE: u8 = 8;
+pub const XM_MAX_OCTAVE: u8 = 7;
 pub const XM_NO_NOTE: u8 = XmNoteRaw::NoNote as u8;
 pub const XM_NOTE_OFF: u8 = XmNoteRaw::NoteOff as u8;
 
-#[derive(Clone)]
+/// MIDI note number of `C-0`. This puts `C-4`, the note samples are played at their base rate
+/// with, on MIDI note 60 (middle C).
+pub const XM_MIDI_NOTE_OFFSET: u8 = 12;
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum XmTone {
     C,
     CS,
@@ -132,8 +137,45 @@ pub enum XmTone {
     B,
 }
 
-impl std::fmt::Display for XmTone {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl XmTone {
+    pub(crate) const fn from_index(index: u8) -> Option<Self> {
+        match index {
+            0 => Some(XmTone::C),
+            1 => Some(XmTone::CS),
+            2 => Some(XmTone::D),
+            3 => Some(XmTone::DS),
+            4 => Some(XmTone::E),
+            5 => Some(XmTone::F),
+            6 => Some(XmTone::FS),
+            7 => Some(XmTone::G),
+            8 => Some(XmTone::GS),
+            9 => Some(XmTone::A),
+            10 => Some(XmTone::AS),
+            11 => Some(XmTone::B),
+            _ => None,
+        }
+    }
+
+    pub(crate) const fn index(&self) -> u8 {
+        match self {
+            XmTone::C => 0,
+            XmTone::CS => 1,
+            XmTone::D => 2,
+            XmTone::DS => 3,
+            XmTone::E => 4,
+            XmTone::F => 5,
+            XmTone::FS => 6,
+            XmTone::G => 7,
+            XmTone::GS => 8,
+            XmTone::A => 9,
+            XmTone::AS => 10,
+            XmTone::B => 11,
+        }
+    }
+}
+
+impl core::fmt::Display for XmTone {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             XmTone::C => write!(f, "C-"),
             XmTone::CS => write!(f, "C#"),
@@ -151,7 +193,8 @@ impl std::fmt::Display for XmTone {
     }
 }
 
-#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum XmNote {
     Note { tone: XmTone, octave: u8 },
     NoNote,
@@ -164,11 +207,57 @@ impl Default for XmNote {
     }
 }
 
-impl std::fmt::Display for XmNote {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl XmNote {
+    /// Shifts the note by the given amount of semitones, returns `None` if this isn't an
+    /// actual note or if the result falls outside of the playable range
+    pub fn transpose(&self, semitones: i16) -> Option<XmNote> {
+        let Self::Note { tone, octave } = self else {
+            return None;
+        };
+
+        let index = *octave as i16 * XM_TONE_COUNT as i16 + tone.index() as i16 + semitones;
+        if !(0..((XM_MAX_OCTAVE + 1) * XM_TONE_COUNT) as i16).contains(&index) {
+            return None;
+        }
+
+        let index = index as u8;
+        Some(Self::Note {
+            tone: XmTone::from_index(index % XM_TONE_COUNT)?,
+            octave: index / XM_TONE_COUNT,
+        })
+    }
+
+    /// Returns the MIDI note number, see `XM_MIDI_NOTE_OFFSET`. `None` if this isn't an actual
+    /// note.
+    pub fn to_midi(&self) -> Option<u8> {
+        let Self::Note { tone, octave } = self else {
+            return None;
+        };
+
+        Some(XM_MIDI_NOTE_OFFSET + octave * XM_TONE_COUNT + tone.index())
+    }
+
+    /// Converts a MIDI note number to a note, see `XM_MIDI_NOTE_OFFSET`. Numbers outside of the
+    /// playable range are clamped to `C-0` and `B-7`.
+    pub fn from_midi(midi: u8) -> Self {
+        let index = midi.clamp(
+            XM_MIDI_NOTE_OFFSET,
+            XM_MIDI_NOTE_OFFSET + (XM_MAX_OCTAVE + 1) * XM_TONE_COUNT - 1,
+        ) - XM_MIDI_NOTE_OFFSET;
+
+        Self::Note {
+            tone: XmTone::from_index(index % XM_TONE_COUNT)
+                .expect("the remainder should always be a valid tone index"),
+            octave: index / XM_TONE_COUNT,
+        }
+    }
+}
+
+impl core::fmt::Display for XmNote {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             Self::NoNote => write!(f, "..."),
-            Self::NoteOff => write!(f, "== "),
+            Self::NoteOff => write!(f, "^^^"),
             Self::Note { tone, octave } => write!(f, "{}{}", tone, octave),
         }
     }
@@ -194,33 +283,14 @@ pub fn parse_xm_note(input: &[u8]) -> IResult<&[u8], XmNote> {
         )));
     }
 
-    let tone_raw = value as u16 - (octave as u16 * XM_TONE_COUNT as u16);
-    let tone = match tone_raw {
-        0 => XmTone::C,
-        1 => XmTone::CS,
-        2 => XmTone::D,
-        3 => XmTone::DS,
-        4 => XmTone::E,
-        5 => XmTone::F,
-        6 => XmTone::FS,
-        7 => XmTone::G,
-        8 => XmTone::GS,
-        9 => XmTone::A,
-        10 => XmTone::AS,
-        11 => XmTone::B,
-        _ => {
-            return Err(nom::Err::Error(nom::error::Error::from_error_kind(
-                input,
-                nom::error::ErrorKind::Verify,
-            )))
-        }
+    let tone_raw = value - (octave * XM_TONE_COUNT);
+    let Some(tone) = XmTone::from_index(tone_raw) else {
+        return Err(nom::Err::Error(nom::error::Error::from_error_kind(
+            input,
+            nom::error::ErrorKind::Verify,
+        )));
     };
 
-    Ok((
-        input,
-        XmNote::Note {
-            tone,
-            octave: octave + 1,
-        },
-    ))
+    // octaves are zero-based like in FT2, so the raw value 1 is `C-0`
+    Ok((input, XmNote::Note { tone, octave }))
 }
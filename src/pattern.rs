@@ -1,24 +1,79 @@
-use crate::{effect, note};
+use alloc::{borrow::ToOwned, collections::BTreeSet, format, vec, vec::Vec};
+
+use crate::{effect, note, XmModule};
 
 use bitfield_struct::bitfield;
 use nom::{error::ParseError, sequence::tuple, IResult, Parser};
 
-const XM_PATTERN_HEADER_SIZE: usize = 9;
+pub(crate) const XM_PATTERN_HEADER_SIZE: usize = 9;
+
+// FT2 always stores the order table with its maximum size
+pub(crate) const XM_ORDER_TABLE_SIZE: usize = 256;
 
 pub type XmPatternOrderTable = Vec<u8>;
 
-#[derive(Clone, Debug)]
+/// Order table entry used by ModPlug trackers for orders which are skipped during playback ("+++")
+pub const XM_ORDER_SKIP_MARKER: u8 = 0xFE;
+
+/// Order table entry used by ModPlug trackers to end the song early ("---")
+pub const XM_ORDER_END_MARKER: u8 = 0xFF;
+
+/// Rows of the empty pattern played for orders referring to patterns which don't exist, also
+/// the length patterns stored with 0 rows load with
+pub const XM_EMPTY_PATTERN_ROWS: usize = 64;
+
+pub fn is_order_marker(entry: u8) -> bool {
+    entry == XM_ORDER_SKIP_MARKER || entry == XM_ORDER_END_MARKER
+}
+
+/// How playback treats an entry of the order table
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum XmOrderEntry {
+    /// Plays the pattern with this index
+    Pattern(u8),
+    /// `XM_ORDER_SKIP_MARKER`, playback moves on to the next order
+    Skip,
+    /// `XM_ORDER_END_MARKER`, the song ends here and loops back to the restart position
+    End,
+}
+
+impl From<u8> for XmOrderEntry {
+    fn from(value: u8) -> Self {
+        match value {
+            XM_ORDER_SKIP_MARKER => XmOrderEntry::Skip,
+            XM_ORDER_END_MARKER => XmOrderEntry::End,
+            _ => XmOrderEntry::Pattern(value),
+        }
+    }
+}
+
+impl From<XmOrderEntry> for u8 {
+    fn from(value: XmOrderEntry) -> Self {
+        match value {
+            XmOrderEntry::Pattern(index) => index,
+            XmOrderEntry::Skip => XM_ORDER_SKIP_MARKER,
+            XmOrderEntry::End => XM_ORDER_END_MARKER,
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct XmPatternHeader {
     pub header_length: u32,
     pub packing_type: u8,
     pub rows_num: u16,
     pub packed_data_size: u16,
+    /// Bytes the header length covers past the standard fields
+    pub extra: Vec<u8>,
 }
 
-#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct XmPatternRow(pub Vec<XmPatternSlot>);
 
-#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct XmPatternRows(pub Vec<XmPatternRow>);
 
 #[bitfield(u8)]
@@ -33,19 +88,251 @@ pub struct XmNoteFlags {
     __: u8,
 }
 
-#[derive(Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
 pub struct XmPatternSlot {
-    note: note::XmNote,
-    instrument_index: Option<u8>,
-    volume_column: Option<effect::XmVolumeColumn>,
-    effect: Option<effect::XmEffect>,
+    pub(crate) note: note::XmNote,
+    pub(crate) instrument_index: Option<u8>,
+    pub(crate) volume_column: Option<effect::XmVolumeColumn>,
+    pub(crate) effect: Option<effect::XmEffect>,
+}
+
+impl XmPatternSlot {
+    pub fn new(
+        note: note::XmNote,
+        instrument_index: Option<u8>,
+        volume_column: Option<effect::XmVolumeColumn>,
+        effect: Option<effect::XmEffect>,
+    ) -> Self {
+        Self {
+            note,
+            instrument_index,
+            volume_column,
+            effect,
+        }
+    }
+
+    pub fn note(&self) -> &note::XmNote {
+        &self.note
+    }
+
+    pub fn instrument_index(&self) -> Option<u8> {
+        self.instrument_index
+    }
+
+    pub fn volume_column(&self) -> Option<&effect::XmVolumeColumn> {
+        self.volume_column.as_ref()
+    }
+
+    pub fn effect(&self) -> Option<&effect::XmEffect> {
+        self.effect.as_ref()
+    }
+}
+
+impl XmPatternRow {
+    pub fn iter(&self) -> core::slice::Iter<'_, XmPatternSlot> {
+        self.0.iter()
+    }
+}
+
+impl IntoIterator for XmPatternRow {
+    type Item = XmPatternSlot;
+    type IntoIter = alloc::vec::IntoIter<XmPatternSlot>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a XmPatternRow {
+    type Item = &'a XmPatternSlot;
+    type IntoIter = core::slice::Iter<'a, XmPatternSlot>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+impl XmPatternRows {
+    pub fn iter(&self) -> core::slice::Iter<'_, XmPatternRow> {
+        self.0.iter()
+    }
+}
+
+impl IntoIterator for XmPatternRows {
+    type Item = XmPatternRow;
+    type IntoIter = alloc::vec::IntoIter<XmPatternRow>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a XmPatternRows {
+    type Item = &'a XmPatternRow;
+    type IntoIter = core::slice::Iter<'a, XmPatternRow>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+/// Extra times a row plays through `EEx`, the last pattern delay of the row wins
+pub(crate) fn pattern_delay(slots: &[XmPatternSlot]) -> u8 {
+    slots
+        .iter()
+        .rev()
+        .find_map(|slot| match slot.effect {
+            Some(effect::XmEffect::PatternDelay(a)) => Some(a),
+            _ => None,
+        })
+        .unwrap_or(0)
+}
+
+/// Moves through the song row by row the way FT2 does, following the order table along with
+/// position jumps, pattern breaks and pattern loops. Both the playback engine and
+/// `XmModule::walk_rows` go through the song with it, so they always agree on where it goes.
+#[derive(Clone, Debug)]
+pub(crate) struct XmRowCursor {
+    order: usize,
+    row: usize,
+    // set by a position jump or pattern break until the row it leads to is reached
+    jumped: bool,
+    visited: BTreeSet<(usize, usize)>,
+    // start row and remaining count of every channel's pattern loop
+    loops: Vec<(usize, u8)>,
+}
+
+impl XmRowCursor {
+    /// Points at the first row of the first order, [`Self::settle`] has to be called before
+    /// it's played
+    pub(crate) fn new(module: &XmModule) -> Self {
+        Self {
+            order: 0,
+            row: 0,
+            jumped: false,
+            visited: BTreeSet::new(),
+            loops: vec![(0, 0); module.header.channels_num as usize],
+        }
+    }
+
+    pub(crate) fn order(&self) -> usize {
+        self.order
+    }
+
+    pub(crate) fn row(&self) -> usize {
+        self.row
+    }
+
+    /// Moves on from a row once it's played, `slots` are the contents of the row
+    pub(crate) fn leave_row(&mut self, slots: &[XmPatternSlot]) {
+        let mut jump_order = None;
+        let mut break_row = None;
+        let mut loop_row = None;
+
+        for (channel, slot) in slots.iter().enumerate() {
+            match slot.effect {
+                Some(effect::XmEffect::PositionJump(a)) => jump_order = Some(a as usize),
+                // the row is stored as two decimal digits
+                Some(effect::XmEffect::PatternBreak(a)) => {
+                    break_row = Some((a >> 4) as usize * 10 + (a & 0xF) as usize)
+                }
+                Some(effect::XmEffect::PatternLoopStart) => {
+                    if let Some(state) = self.loops.get_mut(channel) {
+                        state.0 = self.row;
+                    }
+                }
+                Some(effect::XmEffect::PatternLoop(count)) => {
+                    if let Some(state) = self.loops.get_mut(channel) {
+                        if state.1 == 0 {
+                            state.1 = count;
+                            loop_row = Some(state.0);
+                        } else {
+                            state.1 -= 1;
+                            if state.1 > 0 {
+                                loop_row = Some(state.0);
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(loop_row) = loop_row {
+            self.row = loop_row;
+        } else if jump_order.is_some() || break_row.is_some() {
+            self.order = jump_order.unwrap_or(self.order + 1);
+            self.row = break_row.unwrap_or(0);
+            self.jumped = true;
+            self.loops.fill((0, 0));
+        } else {
+            self.row += 1;
+        }
+    }
+
+    /// Skips ahead to the row which gets played next, past pattern ends and skip markers.
+    /// Returns `true` if the song ended on the way, by running past its last order or into an
+    /// end marker, or by jumping to a row that was already played. The cursor then points at
+    /// where the song starts over: the restart position or the row jumped to.
+    pub(crate) fn settle(&mut self, module: &XmModule) -> bool {
+        let mut ended = false;
+
+        loop {
+            let entry = module.pattern_order_table[..module.song_length()].get(self.order);
+            let rows_num = match entry.map(|e| XmOrderEntry::from(*e)) {
+                Some(XmOrderEntry::Pattern(index)) => module.pattern_rows_num(index),
+                Some(XmOrderEntry::Skip) => {
+                    self.order += 1;
+                    continue;
+                }
+                // there's nothing to play from the restart position on either
+                None | Some(XmOrderEntry::End) if ended => {
+                    self.row = 0;
+                    return true;
+                }
+                None | Some(XmOrderEntry::End) => {
+                    ended = true;
+                    self.order = module.restart_order().unwrap_or(0);
+                    self.row = 0;
+                    self.jumped = false;
+                    self.visited.clear();
+                    self.loops.fill((0, 0));
+                    continue;
+                }
+            };
+
+            // FT2 starts over at the first row when breaking past the end of the next pattern
+            if self.row >= rows_num {
+                if !self.jumped || rows_num == 0 {
+                    self.order += 1;
+                    self.row = 0;
+                    self.loops.fill((0, 0));
+                    continue;
+                }
+                self.row = 0;
+            }
+
+            break;
+        }
+
+        let jumped = core::mem::take(&mut self.jumped);
+        if !self.visited.insert((self.order, self.row)) && jumped {
+            self.visited.clear();
+            self.visited.insert((self.order, self.row));
+            return true;
+        }
+
+        ended
+    }
 }
 
+/// Returns the order table along with the bytes `size` covers past the full size table
 pub(crate) fn parse_order_table_raw(
     data: &[u8],
     length: usize,
     size: usize,
-) -> IResult<&[u8], XmPatternOrderTable> {
+) -> IResult<&[u8], (XmPatternOrderTable, &[u8])> {
     if length > size {
         Err(nom::Err::Error(nom::error::Error::from_error_kind(
             data,
@@ -53,94 +340,122 @@ pub(crate) fn parse_order_table_raw(
         )))
     } else {
         let (input, out) = nom::bytes::complete::take(length)(data)?;
-        let (input, _) = nom::bytes::complete::take(size - length)(input)?;
+        let (input, _) = nom::bytes::complete::take(size.min(XM_ORDER_TABLE_SIZE) - length)(input)?;
+        let (input, extra) =
+            nom::bytes::complete::take(size.saturating_sub(XM_ORDER_TABLE_SIZE))(input)?;
 
-        Ok((input, out.to_vec()))
+        Ok((input, (out.to_vec(), extra)))
     }
 }
 
-fn parse_header(data: &[u8]) -> IResult<&[u8], (XmPatternHeader, &[u8])> {
-    let (input, (header_length, packing_type, rows_num, packed_data_size)) = tuple((
-        nom::number::complete::le_u32, // Pattern header length
-        nom::number::complete::u8,     // Packing type
-        nom::combinator::verify(nom::number::complete::le_u16, |e| (1..=256).contains(e)), // Number of rows in pattern
-        nom::number::complete::le_u16, // Packed pattern data size
-    ))(data)?;
-
-    let (input, excess_data) = if header_length as usize > XM_PATTERN_HEADER_SIZE {
-        nom::bytes::complete::take(header_length as usize - XM_PATTERN_HEADER_SIZE)(input)?
-    } else {
-        (input, &[] as &[u8])
-    };
-
-    Ok((
-        input,
-        (
-            XmPatternHeader {
-                header_length,
-                packing_type,
-                rows_num,
-                packed_data_size,
-            },
-            excess_data,
-        ),
-    ))
+/// Version 1.02 stores the number of rows minus one in a single byte, so its header is a byte
+/// shorter
+fn parse_rows_num(version: u16) -> impl FnMut(&[u8]) -> IResult<&[u8], u16> {
+    move |data| match version {
+        0x0102 => nom::combinator::map(nom::number::complete::u8, |e| e as u16 + 1)(data),
+        _ => nom::number::complete::le_u16(data),
+    }
 }
 
-fn parse_slot(data: &[u8]) -> IResult<&[u8], XmPatternSlot> {
-    let (input, note_or_flags) = nom::number::complete::u8(data)?;
-    let is_flags = ((note_or_flags & (0x1 << 7)) >> 7) == 1;
+/// Lenient mode accepts more than the 256 rows FT2 allows
+fn parse_header(
+    version: u16,
+    strict: bool,
+) -> impl FnMut(&[u8]) -> IResult<&[u8], XmPatternHeader> {
+    let header_size = match version {
+        0x0102 => XM_PATTERN_HEADER_SIZE - 1,
+        _ => XM_PATTERN_HEADER_SIZE,
+    };
 
-    if is_flags {
-        let flags = XmNoteFlags(note_or_flags);
+    move |data| {
+        let (input, (header_length, packing_type, rows_num, packed_data_size)) = tuple((
+            nom::number::complete::le_u32, // Pattern header length
+            nom::number::complete::u8,     // Packing type
+            nom::combinator::verify(parse_rows_num(version), |e| !strict || *e <= 256), // Number of rows in pattern
+            nom::number::complete::le_u16, // Packed pattern data size
+        ))(data)?;
 
-        let (input, (note, instrument_index, volume_column)) = tuple((
-            nom::combinator::cond(flags.note_follows(), note::parse_xm_note)
-                .map(|e| e.unwrap_or(note::XmNote::NoNote)),
-            nom::combinator::cond(flags.instrument_follows(), nom::number::complete::u8),
-            nom::combinator::cond(
-                flags.volume_column_byte_follows(),
-                effect::parse_volume_column,
-            ),
-        ))(input)?;
+        // some writers store 0 rows, which loads as the default pattern length
+        let rows_num = match rows_num {
+            0 => XM_EMPTY_PATTERN_ROWS as u16,
+            _ => rows_num,
+        };
 
-        let (input, effect) = effect::parse_effect(
-            flags.effect_type_follows(),
-            flags.effect_parameter_follows(),
-        )(input)?;
+        let (input, excess_data) = if header_length as usize > header_size {
+            nom::bytes::complete::take(header_length as usize - header_size)(input)?
+        } else {
+            (input, &[] as &[u8])
+        };
 
         Ok((
             input,
-            XmPatternSlot {
-                note,
-                instrument_index,
-                volume_column,
-                effect,
+            XmPatternHeader {
+                header_length,
+                packing_type,
+                rows_num,
+                packed_data_size,
+                extra: excess_data.to_vec(),
             },
         ))
-    } else {
-        let (input, (note, instrument_index, volume_column, effect)) = tuple((
-            note::parse_xm_note,
-            nom::number::complete::u8,
-            effect::parse_volume_column,
-            effect::parse_effect(true, true),
-        ))(data)?;
+    }
+}
 
-        Ok((
-            input,
-            XmPatternSlot {
-                note,
-                instrument_index: Some(instrument_index),
-                volume_column: Some(volume_column),
-                effect,
-            },
-        ))
+fn parse_slot(strict: bool) -> impl FnMut(&[u8]) -> IResult<&[u8], XmPatternSlot> {
+    move |data| {
+        let (input, note_or_flags) = nom::number::complete::u8(data)?;
+        let is_flags = ((note_or_flags & (0x1 << 7)) >> 7) == 1;
+
+        if is_flags {
+            let flags = XmNoteFlags(note_or_flags);
+
+            let (input, (note, instrument_index, volume_column)) = tuple((
+                nom::combinator::cond(flags.note_follows(), note::parse_xm_note)
+                    .map(|e| e.unwrap_or(note::XmNote::NoNote)),
+                nom::combinator::cond(flags.instrument_follows(), nom::number::complete::u8),
+                nom::combinator::cond(
+                    flags.volume_column_byte_follows(),
+                    effect::parse_volume_column(strict),
+                ),
+            ))(input)?;
+
+            let (input, effect) = effect::parse_effect(
+                flags.effect_type_follows(),
+                flags.effect_parameter_follows(),
+            )(input)?;
+
+            Ok((
+                input,
+                XmPatternSlot {
+                    note,
+                    instrument_index,
+                    volume_column,
+                    effect,
+                },
+            ))
+        } else {
+            let (input, (note, instrument_index, volume_column, effect)) = tuple((
+                note::parse_xm_note,
+                nom::number::complete::u8,
+                effect::parse_volume_column(strict),
+                effect::parse_effect(true, true),
+            ))(data)?;
+
+            Ok((
+                input,
+                XmPatternSlot {
+                    note,
+                    instrument_index: Some(instrument_index),
+                    volume_column: Some(volume_column),
+                    effect,
+                },
+            ))
+        }
     }
 }
 
-fn parse_row(channels_num: u16) -> impl FnMut(&[u8]) -> IResult<&[u8], XmPatternRow> {
+fn parse_row(channels_num: u16, strict: bool) -> impl FnMut(&[u8]) -> IResult<&[u8], XmPatternRow> {
     move |data| {
-        nom::multi::count(parse_slot, channels_num as usize)
+        nom::multi::count(parse_slot(strict), channels_num as usize)
             .map(|e| XmPatternRow(e))
             .parse(data)
     }
@@ -148,20 +463,34 @@ fn parse_row(channels_num: u16) -> impl FnMut(&[u8]) -> IResult<&[u8], XmPattern
 
 pub(crate) fn parse(
     channels_num: u16,
-) -> impl FnMut(&[u8]) -> IResult<&[u8], (XmPatternHeader, XmPatternRows, &[u8])> {
+    version: u16,
+    strict: bool,
+) -> impl FnMut(&[u8]) -> IResult<&[u8], (XmPatternHeader, XmPatternRows)> {
     move |data| {
-        let (input, (header, excess)) = parse_header(data)?;
+        let (input, header) = parse_header(version, strict)(data)?;
+
+        // empty patterns are stored without any data
+        if header.packed_data_size == 0 {
+            let row = XmPatternRow(vec![XmPatternSlot::default(); channels_num as usize]);
+            let rows = XmPatternRows(vec![row; header.rows_num as usize]);
+            return Ok((input, (header, rows)));
+        }
+
+        // every slot takes up at least a byte
+        let (input, _) =
+            crate::verify_remaining(input, header.rows_num as usize * channels_num as usize, 1)?;
 
-        let (input, notes) = nom::multi::count(parse_row(channels_num), header.rows_num as usize)
-            .map(|e| XmPatternRows(e))
-            .parse(input)?;
+        let (input, notes) =
+            nom::multi::count(parse_row(channels_num, strict), header.rows_num as usize)
+                .map(|e| XmPatternRows(e))
+                .parse(input)?;
 
-        Ok((input, (header, notes, excess)))
+        Ok((input, (header, notes)))
     }
 }
 
-impl std::fmt::Display for XmPatternSlot {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Display for XmPatternSlot {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         // TODO: add the rest
         let effect_fmt = match self.effect {
             Some(ref v) => format!("{}", v),
@@ -182,8 +511,8 @@ impl std::fmt::Display for XmPatternSlot {
     }
 }
 
-impl std::fmt::Display for XmPatternRow {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Display for XmPatternRow {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         for note in self.0.iter() {
             write!(f, "|")?;
             write!(f, "{}", note)?;
@@ -193,12 +522,12 @@ impl std::fmt::Display for XmPatternRow {
     }
 }
 
-impl std::fmt::Display for XmPatternRows {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Display for XmPatternRows {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         for row in self.0.iter() {
             writeln!(f, "{}", row)?;
         }
 
-        std::fmt::Result::Ok(())
+        core::fmt::Result::Ok(())
     }
 }
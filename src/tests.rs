@@ -2,9 +2,3840 @@ use super::*;
 
 #[test]
 fn test_parse_xm_header_first() {
-    let (_input, format) = parse(include_bytes!("test_xms/surfonasinewave.xm")).unwrap();
+    for data in [
+        &include_bytes!("test_xms/test_wo_mpt_ext.xm")[..],
+        &include_bytes!("test_xms/test_w_mpt_ext.xm")[..],
+    ] {
+        let (input, module) = parse(data).unwrap();
+        assert!(input.is_empty());
+        assert_eq!(module.header.id_text, "Extended Module: ");
+        assert_eq!(module.patterns.len(), module.header.patterns_num as usize);
+        assert_eq!(
+            module.instruments.len(),
+            module.header.instruments_num as usize
+        );
 
-    println!("{:?}", format.pattern_order_table);
-    println!("{:#?}", format.instruments.len());
-    println!("{}", format.patterns[0].1);
+        let written = write::write(&module);
+        let (input, parsed) = parse(&written).unwrap();
+        assert!(input.is_empty());
+        assert!(parsed == module);
+    }
+}
+
+fn test_sample(data: Vec<i8>) -> XmSample {
+    (
+        instrument::XmSampleHeader {
+            length: data.len() as u32,
+            loop_start: 0,
+            loop_length: data.len() as u32,
+            volume: 64,
+            finetune: 0,
+            kind: instrument::XmSampleType::new()
+                .with_loop_type(instrument::XmSampleLoopType::ForwardLoop)
+                .with_depth(instrument::XmSampleBitDepth::Bit8),
+            panning: 128,
+            relative_note_num: 0,
+            name: String::new(),
+        },
+        instrument::XmSamplePcmData::Bit8Data(data),
+    )
+}
+
+fn test_module(rows: Vec<Vec<pattern::XmPatternSlot>>) -> XmModule {
+    let channels_num = rows.first().map_or(1, |e| e.len()) as u16;
+
+    XmModule {
+        header: header::XmHeader {
+            id_text: "Extended Module: ".to_owned(),
+            module_name: String::new(),
+            tracker_name: String::new(),
+            version: 0x0104,
+            song_length: 1,
+            restart_pos: 0,
+            channels_num,
+            patterns_num: 1,
+            instruments_num: 1,
+            is_amiga: false,
+            default_tempo: 6,
+            default_bpm: 125,
+            extra: vec![],
+        },
+        patterns: vec![(
+            pattern::XmPatternHeader {
+                header_length: 9,
+                packing_type: 0,
+                rows_num: rows.len() as u16,
+                packed_data_size: 0,
+                extra: vec![],
+            },
+            pattern::XmPatternRows(rows.into_iter().map(pattern::XmPatternRow).collect()),
+        )],
+        instruments: vec![(
+            instrument::XmInstrumentHeader {
+                header_size: 29,
+                name: String::new(),
+                kind: 0,
+                samples_num: 1,
+                sample_opts: None,
+                extra: vec![],
+            },
+            vec![test_sample(vec![0, 64, 127, 64, 0, -64, -127, -64])],
+        )],
+        pattern_order_table: vec![0],
+        extensions: Default::default(),
+    }
+}
+
+fn c4() -> note::XmNote {
+    note::XmNote::Note {
+        tone: note::XmTone::C,
+        octave: 4,
+    }
+}
+
+#[test]
+fn test_arpeggio_cycles_frequencies() {
+    let module = test_module(vec![
+        vec![pattern::XmPatternSlot::new(
+            c4(),
+            Some(1),
+            None,
+            Some(effect::XmEffect::Arpeggio(effect::DoubleU4::from_bits(
+                0x47,
+            ))),
+        )],
+        vec![pattern::XmPatternSlot::default()],
+    ]);
+    let mut context = context::XmPlaybackContext::new(&module, 44100);
+
+    let mut frequencies = vec![];
+    for _ in 0..7 {
+        context.tick();
+        frequencies.push(context.channel(0).unwrap().frequency().unwrap());
+    }
+
+    let base = frequencies[0];
+    let expected = [
+        1.0,
+        2.0f32.powf(4.0 / 12.0),
+        2.0f32.powf(7.0 / 12.0),
+        1.0,
+        2.0f32.powf(4.0 / 12.0),
+        2.0f32.powf(7.0 / 12.0),
+        // the next row has no arpeggio, so the base note is restored
+        1.0,
+    ];
+
+    for (frequency, ratio) in frequencies.iter().zip(expected) {
+        assert!((frequency / base - ratio).abs() < 1e-4);
+    }
+}
+
+#[test]
+fn test_note_raw_round_trip() {
+    for raw in 1..=96u8 {
+        let (_, parsed) = note::parse_xm_note(&[raw]).unwrap();
+        let note::XmNote::Note { tone, octave } = parsed else {
+            panic!("{} should parse as a note", raw);
+        };
+
+        assert_eq!(frequency::tone_to_raw(&tone, octave), raw);
+    }
+
+    let (_, first) = note::parse_xm_note(&[1]).unwrap();
+    let (_, c4) = note::parse_xm_note(&[49]).unwrap();
+    let (_, last) = note::parse_xm_note(&[96]).unwrap();
+    assert_eq!(first.to_string(), "C-0");
+    assert_eq!(c4.to_string(), "C-4");
+    assert_eq!(last.to_string(), "B-7");
+
+    use frequency::FrequencyCalculator;
+    let c4_frequency = frequency::Linear::frequency(frequency::Linear::period(&note::XmTone::C, 4));
+    assert!((c4_frequency - 8363.0).abs() < 1e-2);
+}
+
+#[test]
+fn test_set_tempo_and_bpm() {
+    let set_tempo = |value| {
+        vec![pattern::XmPatternSlot::new(
+            note::XmNote::NoNote,
+            None,
+            None,
+            Some(effect::XmEffect::SetTempo(value)),
+        )]
+    };
+
+    let module = test_module(vec![set_tempo(0x06), set_tempo(0x7D)]);
+    let mut context = context::XmPlaybackContext::new(&module, 44100);
+
+    let mut module_fast = module.clone();
+    module_fast.header.default_tempo = 3;
+    module_fast.header.default_bpm = 150;
+    let mut context_fast = context::XmPlaybackContext::new(&module_fast, 44100);
+
+    context.tick();
+    context_fast.tick();
+    assert_eq!((context.tempo(), context.bpm()), (0x06, 125));
+    assert_eq!((context_fast.tempo(), context_fast.bpm()), (0x06, 150));
+
+    // the new tempo keeps the first row going for 6 ticks
+    for _ in 0..5 {
+        context_fast.tick();
+    }
+    assert_eq!((context_fast.tempo(), context_fast.bpm()), (0x06, 150));
+
+    context_fast.tick();
+    assert_eq!((context_fast.tempo(), context_fast.bpm()), (0x06, 0x7D));
+}
+
+#[test]
+fn test_sample_loop_type_queries() {
+    let (mut header, _) = test_sample(vec![0; 4]);
+
+    for (bits, looping, bidirectional) in [
+        (0, false, false), // NoLoop
+        (1, true, false),  // ForwardLoop
+        (2, true, true),   // BidirectionalLoop
+        (3, false, false), // Unknown
+    ] {
+        header.kind = instrument::XmSampleType::from_bits(bits);
+
+        assert_eq!(header.is_looping(), looping);
+        assert_eq!(header.is_bidirectional(), bidirectional);
+    }
+}
+
+fn render_energy(module: &XmModule, frames: usize) -> f32 {
+    let mut context = context::XmPlaybackContext::new(module, 44100);
+    let mut out = vec![0.0; frames * 2];
+    context.render(&mut out);
+
+    out.iter().map(|e| e.abs()).sum()
+}
+
+#[test]
+fn test_global_volume() {
+    let row = |effect| {
+        vec![pattern::XmPatternSlot::new(
+            c4(),
+            Some(1),
+            None,
+            Some(effect),
+        )]
+    };
+
+    let full = test_module(vec![row(effect::XmEffect::SetGlobalVolume(0x40))]);
+    let half = test_module(vec![row(effect::XmEffect::SetGlobalVolume(0x20))]);
+
+    let full_energy = render_energy(&full, 256);
+    assert!(full_energy > 0.0);
+    assert!((render_energy(&half, 256) / full_energy - 0.5).abs() < 1e-4);
+
+    let slide = test_module(vec![
+        row(effect::XmEffect::SetGlobalVolume(0x10)),
+        row(effect::XmEffect::GlobalVolumeSlide(
+            effect::DoubleU4::from_bits(0x20),
+        )),
+    ]);
+    let mut context = context::XmPlaybackContext::new(&slide, 44100);
+
+    for _ in 0..7 {
+        context.tick();
+    }
+    assert_eq!(context.global_volume(), 0x10 as f32 / 64.0);
+
+    for _ in 0..5 {
+        context.tick();
+    }
+    assert_eq!(context.global_volume(), (0x10 + 2 * 5) as f32 / 64.0);
+}
+
+#[test]
+fn test_render_channels_subsets_sum_up() {
+    let module = test_module(vec![
+        vec![
+            pattern::XmPatternSlot::new(c4(), Some(1), None, None),
+            pattern::XmPatternSlot::new(c4().transpose(7).unwrap(), Some(1), None, None),
+        ],
+        vec![pattern::XmPatternSlot::default(); 2],
+    ]);
+
+    let render = |channels: &[usize]| {
+        let mut context = context::XmPlaybackContext::new(&module, 44100);
+        let mut out = vec![0.0; 2048];
+        context.render_channels(channels, &mut out);
+        out
+    };
+
+    let first = render(&[0]);
+    let second = render(&[1]);
+    let both = render(&[0, 1]);
+
+    assert!(first.iter().any(|e| *e != 0.0));
+    assert!(second.iter().any(|e| *e != 0.0));
+    for ((a, b), sum) in first.iter().zip(second.iter()).zip(both.iter()) {
+        assert!((a + b - sum).abs() < 1e-5);
+    }
+}
+
+#[test]
+fn test_sample_offset() {
+    let first_frame = |effect| {
+        let mut module = test_module(vec![vec![pattern::XmPatternSlot::new(
+            c4(),
+            Some(1),
+            None,
+            effect,
+        )]]);
+
+        // the first half of the sample is silent, so only an offset one produces output
+        let mut data = vec![0i8; 512];
+        data.extend([100i8; 512]);
+        module.instruments[0].1[0] = test_sample(data);
+
+        let mut context = context::XmPlaybackContext::new(&module, 44100);
+        let mut out = [0.0; 2];
+        context.render(&mut out);
+        out[0]
+    };
+
+    assert_eq!(first_frame(None), 0.0);
+    assert!(first_frame(Some(effect::XmEffect::SampleOffset(0x02))) > 0.0);
+    // offsets past the end of the sample silence the note
+    assert_eq!(first_frame(Some(effect::XmEffect::SampleOffset(0x04))), 0.0);
+}
+
+#[test]
+fn test_amiga_with_many_channels_warning() {
+    let mut module = test_module(vec![vec![pattern::XmPatternSlot::default(); 8]]);
+    assert!(module.header.validate_ranges().is_empty());
+
+    module.header.is_amiga = true;
+    assert_eq!(
+        module.header.validate_ranges(),
+        vec![header::XmHeaderWarning::AmigaWithManyChannels(8)]
+    );
+
+    module.header.channels_num = 4;
+    assert!(module.header.validate_ranges().is_empty());
+}
+
+#[test]
+fn test_zero_tempo_and_bpm_warnings() {
+    let mut module = test_module(vec![vec![pattern::XmPatternSlot::default()]]);
+    module.header.default_tempo = 0;
+    assert_eq!(
+        module.header.validate_ranges(),
+        vec![header::XmHeaderWarning::ZeroTempo]
+    );
+
+    module.header.default_bpm = 0;
+    assert_eq!(
+        module.header.validate_ranges(),
+        vec![
+            header::XmHeaderWarning::ZeroTempo,
+            header::XmHeaderWarning::ZeroBpm
+        ]
+    );
+
+    module.header.default_tempo = 6;
+    module.header.default_bpm = 125;
+    assert!(module.header.validate_ranges().is_empty());
+}
+
+#[test]
+fn test_volume_column() {
+    let slot = |note, volume_column| {
+        let instrument_index = matches!(note, note::XmNote::Note { .. }).then_some(1);
+        vec![pattern::XmPatternSlot::new(
+            note,
+            instrument_index,
+            Some(effect::XmVolumeColumn::new(volume_column)),
+            None,
+        )]
+    };
+
+    let module = test_module(vec![
+        slot(c4(), 0x30),
+        slot(note::XmNote::NoNote, 0x64),
+        slot(note::XmNote::NoNote, 0xC8),
+        slot(note::XmNote::NoNote, 0xA4),
+        slot(note::XmNote::NoNote, 0xB8),
+    ]);
+    let mut context = context::XmPlaybackContext::new(&module, 44100);
+
+    context.tick();
+    assert_eq!(context.channel(0).unwrap().volume().0, 0.5);
+
+    // the slide runs on every tick but the first
+    for _ in 0..12 {
+        context.tick();
+    }
+    let (volume, _) = context.channel(0).unwrap().volume();
+    assert!((volume - (0.5 - 5.0 * 4.0 / 64.0)).abs() < 1e-6);
+
+    for _ in 0..6 {
+        context.tick();
+    }
+    let (_, panning) = context.channel(0).unwrap().volume();
+    let expected = effect::XmVolumeColumn::new(0xC8).argument() as f32 / 255.0;
+    assert!((panning - expected).abs() < 1e-6);
+
+    // the speed alone doesn't start the vibrato
+    for _ in 0..6 {
+        context.tick();
+    }
+
+    // vibrato leaves the first tick alone and then bends the pitch
+    let base = context.channel(0).unwrap().frequency().unwrap();
+    let mut bent = false;
+    for _ in 0..6 {
+        context.tick();
+        bent |= context.channel(0).unwrap().frequency().unwrap() != base;
+    }
+    assert!(bent);
+}
+
+#[test]
+fn test_vibrato_effect() {
+    let slot = |note, volume_column: Option<u8>, effect| {
+        let instrument_index = matches!(note, note::XmNote::Note { .. }).then_some(1);
+        vec![pattern::XmPatternSlot::new(
+            note,
+            instrument_index,
+            volume_column.map(effect::XmVolumeColumn::new),
+            Some(effect),
+        )]
+    };
+    let vibrato = |a| effect::XmEffect::Vibrato(effect::DoubleU4::from_bits(a));
+    let frequencies = |rows| {
+        let module = test_module(rows);
+        let mut context = context::XmPlaybackContext::new(&module, 44100);
+
+        (0..18)
+            .map(|_| {
+                context.tick();
+                context.channel(0).unwrap().frequency().unwrap()
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let expected = frequencies(vec![
+        slot(c4(), None, vibrato(0x48)),
+        slot(note::XmNote::NoNote, None, vibrato(0x48)),
+        slot(note::XmNote::NoNote, None, vibrato(0x48)),
+    ]);
+    // the first tick plays the note as is, the vibrato starts from the zero crossing of the
+    // sine on the next one and bends it from then on
+    assert_eq!(expected[1], expected[0]);
+    assert!(expected[2..6].iter().all(|e| *e != expected[0]));
+
+    // `400` and `600` keep going with the last speed and depth
+    let remembered = frequencies(vec![
+        slot(c4(), None, vibrato(0x48)),
+        slot(note::XmNote::NoNote, None, vibrato(0x00)),
+        slot(
+            note::XmNote::NoNote,
+            None,
+            effect::XmEffect::VolumeSlideVibrato(effect::DoubleU4::from_bits(0x00)),
+        ),
+    ]);
+    assert_eq!(remembered, expected);
+
+    // the speed set in the volume column is the one `4xy` remembers
+    let shared = frequencies(vec![
+        slot(c4(), Some(0xA4), vibrato(0x08)),
+        slot(note::XmNote::NoNote, None, vibrato(0x00)),
+        slot(note::XmNote::NoNote, None, vibrato(0x00)),
+    ]);
+    assert_eq!(shared, expected);
+}
+
+#[test]
+fn test_minimal_loop() {
+    let cycle = [0i8, 64, 127, 64, 0, -64, -127, -64];
+    let (header, data) = test_sample(cycle.repeat(2));
+    assert_eq!(data.minimal_loop(&header), Some(cycle.len()));
+
+    // a loop that doesn't repeat can't be shortened
+    let (header, data) = test_sample(cycle.to_vec());
+    assert_eq!(data.minimal_loop(&header), Some(cycle.len()));
+
+    let (mut header, data) = test_sample(cycle.repeat(2));
+    header.kind = header
+        .kind
+        .with_loop_type(instrument::XmSampleLoopType::NoLoop);
+    assert_eq!(data.minimal_loop(&header), None);
+}
+
+fn test_envelope(points: &[(u16, u16)]) -> instrument::XmEnvelope {
+    instrument::XmEnvelope {
+        points: points
+            .iter()
+            .map(|&(frame, value)| instrument::XmEnvelopePoint { frame, value })
+            .collect(),
+        sustain_point: None,
+        loop_start_point: None,
+        loop_end_point: None,
+    }
+}
+
+fn test_sample_opts(
+    volume_envelope: Option<instrument::XmEnvelope>,
+    panning_envelope: Option<instrument::XmEnvelope>,
+) -> instrument::XmInstrumentSampleOpts {
+    instrument::XmInstrumentSampleOpts {
+        sample_header_size: 40,
+        sample_keymap_assignments: [0; 96],
+        volume_envelope,
+        panning_envelope,
+        vibrato: instrument::XmVibratoOpts {
+            kind: instrument::XmVibratoType::Sine,
+            sweep: 0,
+            depth: 0,
+            rate: 0,
+        },
+        volume_fadeout: 0,
+    }
+}
+
+#[test]
+fn test_envelope_ramp() {
+    let envelope = test_envelope(&[(2, 0), (6, 64)]);
+
+    assert_eq!(envelope.evaluate(0), 0.0);
+    assert_eq!(envelope.evaluate(4), 32.0);
+    assert_eq!(envelope.evaluate(6), 64.0);
+    assert_eq!(envelope.evaluate(100), 64.0);
+    assert_eq!(envelope.advance(4, true), 5);
+}
+
+#[test]
+fn test_envelope_sustain() {
+    let mut envelope = test_envelope(&[(0, 64), (4, 32), (8, 0)]);
+    envelope.sustain_point = Some(1);
+
+    let mut frame = 0;
+    for _ in 0..10 {
+        frame = envelope.advance(frame, true);
+    }
+    assert_eq!(frame, 4);
+    assert_eq!(envelope.evaluate(frame), 32.0);
+
+    // releasing the note lets the envelope carry on
+    frame = envelope.advance(frame, false);
+    assert_eq!(frame, 5);
+}
+
+#[test]
+fn test_envelope_loop() {
+    let mut envelope = test_envelope(&[(0, 0), (2, 64), (6, 0)]);
+    envelope.loop_start_point = Some(1);
+    envelope.loop_end_point = Some(2);
+
+    let mut frames = vec![];
+    let mut frame = 0;
+    for _ in 0..10 {
+        frames.push(frame);
+        frame = envelope.advance(frame, false);
+    }
+
+    assert_eq!(frames, vec![0, 1, 2, 3, 4, 5, 2, 3, 4, 5]);
+}
+
+#[test]
+fn test_volume_envelope_playback() {
+    let mut module = test_module(vec![
+        vec![pattern::XmPatternSlot::new(c4(), Some(1), None, None)],
+        vec![pattern::XmPatternSlot::new(
+            note::XmNote::NoteOff,
+            None,
+            None,
+            None,
+        )],
+    ]);
+
+    let mut envelope = test_envelope(&[(0, 64), (2, 32), (4, 0)]);
+    envelope.sustain_point = Some(1);
+    module.instruments[0].0.sample_opts = Some(test_sample_opts(Some(envelope), None));
+
+    let mut context = context::XmPlaybackContext::new(&module, 44100);
+
+    let mut volumes = vec![];
+    for _ in 0..9 {
+        context.tick();
+        volumes.push(context.channel(0).unwrap().envelope_volume());
+    }
+
+    // held on the sustain point until the note off on tick 6
+    assert_eq!(volumes, vec![1.0, 0.75, 0.5, 0.5, 0.5, 0.5, 0.5, 0.25, 0.0]);
+}
+
+#[test]
+fn test_volume_column_tone_portamento() {
+    let frequencies = |volume_column: Option<u8>, effect| {
+        let module = test_module(vec![
+            vec![pattern::XmPatternSlot::new(c4(), Some(1), None, None)],
+            vec![pattern::XmPatternSlot::new(
+                note::XmNote::Note {
+                    tone: note::XmTone::C,
+                    octave: 5,
+                },
+                None,
+                volume_column.map(effect::XmVolumeColumn::new),
+                effect,
+            )],
+        ]);
+        let mut context = context::XmPlaybackContext::new(&module, 44100);
+
+        (0..12)
+            .map(|_| {
+                context.tick();
+                context.channel(0).unwrap().frequency().unwrap()
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // FT2 multiplies the volume column speed by 16, so `g2` matches `320`
+    let volume_column = frequencies(Some(0xF2), None);
+    let effect = frequencies(None, Some(effect::XmEffect::TonePortamento(0x20)));
+    assert_eq!(volume_column, effect);
+
+    // glides up from C-4 without reaching C-5 within the row
+    let c4_frequency = volume_column[0];
+    assert_eq!(volume_column[6], c4_frequency);
+    assert!(volume_column[7] > c4_frequency);
+    assert!(volume_column.windows(2).skip(6).all(|e| e[1] > e[0]));
+    assert!(volume_column[11] < c4_frequency * 2.0);
+}
+
+#[test]
+fn test_frequency_follows_period() {
+    use frequency::{FrequencyCalculator, Linear};
+
+    let row = |note, effect| vec![pattern::XmPatternSlot::new(note, Some(1), None, effect)];
+    let module = test_module(vec![
+        row(c4(), None),
+        row(note::XmNote::NoNote, None),
+        row(
+            note::XmNote::NoNote,
+            Some(effect::XmEffect::PortamentoUp(4)),
+        ),
+        row(c4(), None),
+        row(
+            note::XmNote::NoNote,
+            Some(effect::XmEffect::PortamentoDown(8)),
+        ),
+        row(note::XmNote::NoNote, None),
+    ]);
+    let mut context = context::XmPlaybackContext::new(&module, 44100);
+
+    // held notes keep their frequency from one tick to the next, slides recompute it
+    for _ in 0..6 * 6 {
+        context.tick();
+        let channel = context.channel(0).unwrap();
+        let expected = Linear::frequency(channel.period().unwrap());
+        assert!((channel.frequency().unwrap() - expected).abs() <= expected * 1e-6);
+    }
+
+    // arpeggio moves the frequency without touching the period
+    let module = test_module(vec![row(
+        c4(),
+        Some(effect::XmEffect::Arpeggio(effect::DoubleU4::from_bits(
+            0x47,
+        ))),
+    )]);
+    let mut context = context::XmPlaybackContext::new(&module, 44100);
+    let frequencies = (0..6)
+        .map(|_| {
+            context.tick();
+            context.channel(0).unwrap().frequency().unwrap()
+        })
+        .collect::<Vec<_>>();
+    assert!(frequencies[..3].windows(2).all(|e| e[1] > e[0]));
+    assert_eq!(frequencies[..3], frequencies[3..]);
+}
+
+#[test]
+fn test_glissando_control() {
+    let frequencies = |glissando| {
+        let module = test_module(vec![
+            vec![pattern::XmPatternSlot::new(
+                c4(),
+                Some(1),
+                None,
+                Some(effect::XmEffect::GlissandoControl(glissando)),
+            )],
+            vec![pattern::XmPatternSlot::new(
+                note::XmNote::Note {
+                    tone: note::XmTone::C,
+                    octave: 5,
+                },
+                None,
+                None,
+                Some(effect::XmEffect::TonePortamento(0x08)),
+            )],
+        ]);
+        let mut context = context::XmPlaybackContext::new(&module, 44100);
+
+        (0..12)
+            .map(|_| {
+                context.tick();
+                context.channel(0).unwrap().frequency().unwrap()
+            })
+            .collect::<Vec<_>>()
+    };
+    let semitones = |frequencies: Vec<f32>| {
+        let c4_frequency = frequencies[0];
+        frequencies
+            .into_iter()
+            .map(|e| 12.0 * (e / c4_frequency).log2())
+            .collect::<Vec<_>>()
+    };
+
+    // the slide moves by half a semitone per tick, so without glissando it passes in between
+    let smooth = semitones(frequencies(0));
+    assert!(smooth.iter().any(|e| (e - e.round()).abs() > 0.25));
+    assert!(smooth.windows(2).skip(6).all(|e| e[1] > e[0]));
+
+    // with it every tick lands on a semitone, and each semitone is held for 2 ticks
+    let stepped = semitones(frequencies(1));
+    assert!(stepped.iter().all(|e| (e - e.round()).abs() < 1e-3));
+    assert!(stepped[11] > 1.5);
+    assert_eq!(stepped[8], stepped[9]);
+    assert_eq!(stepped[10], stepped[11]);
+}
+
+#[test]
+fn test_relative_note() {
+    let frequency = |relative_note_num| {
+        let mut module = test_module(vec![vec![pattern::XmPatternSlot::new(
+            c4(),
+            Some(1),
+            None,
+            None,
+        )]]);
+        module.instruments[0].1[0].0.relative_note_num = relative_note_num;
+
+        let mut context = context::XmPlaybackContext::new(&module, 44100);
+        context.tick();
+        let channel = context.channel(0).unwrap();
+        (channel.period().unwrap(), channel.frequency().unwrap())
+    };
+
+    // a sample recorded an octave up is brought back down by a relative note of -12
+    let (base_period, base_frequency) = frequency(0);
+    let (period, lower) = frequency(-12);
+    assert_eq!(period, base_period + 12.0 * 64.0);
+    assert!((lower * 2.0 - base_frequency).abs() < 1e-2);
+
+    let (_, higher) = frequency(7);
+    assert!((higher / base_frequency - 2.0f32.powf(7.0 / 12.0)).abs() < 1e-4);
+}
+
+#[test]
+fn test_set_finetune() {
+    let slot = |effect| pattern::XmPatternSlot::new(c4(), Some(1), None, effect);
+    let mut module = test_module(vec![
+        vec![slot(Some(effect::XmEffect::SetFinetune(0xC)))],
+        vec![slot(Some(effect::XmEffect::SetFinetune(0x8)))],
+        vec![slot(None)],
+    ]);
+    module.instruments[0].1[0].0.finetune = -16;
+    module.header.default_tempo = 1;
+
+    let mut context = context::XmPlaybackContext::new(&module, 44100);
+    let mut periods = vec![];
+    let mut frequencies = vec![];
+    for _ in 0..3 {
+        context.tick();
+        periods.push(context.channel(0).unwrap().period().unwrap());
+        frequencies.push(context.channel(0).unwrap().frequency().unwrap());
+    }
+
+    // E5C is a finetune of 64, half a semitone up, E58 is no finetune at all, and without E5x
+    // the sample finetune is back
+    assert_eq!(periods, vec![4576.0, 4608.0, 4616.0]);
+    assert!(frequencies[0] > frequencies[1] && frequencies[1] > frequencies[2]);
+}
+
+#[test]
+fn test_panning_envelope_playback() {
+    let mut module = test_module(vec![vec![pattern::XmPatternSlot::new(
+        c4(),
+        Some(1),
+        None,
+        None,
+    )]]);
+    module.instruments[0].0.sample_opts = Some(test_sample_opts(
+        None,
+        Some(test_envelope(&[(0, 0), (5, 64)])),
+    ));
+
+    let mut context = context::XmPlaybackContext::new(&module, 44100);
+
+    let mut pannings = vec![];
+    for _ in 0..6 {
+        context.tick();
+        pannings.push(context.channel(0).unwrap().output_panning());
+    }
+
+    assert!(pannings[0] < 0.01);
+    assert!(pannings.windows(2).all(|e| e[1] > e[0]));
+    assert!(pannings[5] > 0.99);
+}
+
+#[test]
+fn test_id_text_is_retained() {
+    let (_, (header, _, _)) =
+        header::parse(include_bytes!("test_xms/test_wo_mpt_ext.xm"), true).unwrap();
+
+    assert_eq!(header.id_text, "Extended Module: ");
+}
+
+#[test]
+fn test_volume_fadeout() {
+    let mut module = test_module(vec![
+        vec![pattern::XmPatternSlot::new(c4(), Some(1), None, None)],
+        vec![pattern::XmPatternSlot::new(
+            note::XmNote::NoteOff,
+            None,
+            None,
+            None,
+        )],
+        vec![pattern::XmPatternSlot::default()],
+        vec![pattern::XmPatternSlot::default()],
+    ]);
+
+    let mut envelope = test_envelope(&[(0, 64)]);
+    envelope.sustain_point = Some(0);
+    let mut opts = test_sample_opts(Some(envelope), None);
+    opts.volume_fadeout = 0x1000;
+    module.instruments[0].0.sample_opts = Some(opts);
+
+    let mut context = context::XmPlaybackContext::new(&module, 44100);
+
+    for _ in 0..6 {
+        context.tick();
+        assert_eq!(context.channel(0).unwrap().output_volume(), 1.0);
+    }
+
+    // 0x1000 / 65536 takes 16 ticks to fade out, starting with the note off
+    for i in 1..16 {
+        context.tick();
+        let volume = context.channel(0).unwrap().output_volume();
+        assert!((volume - (1.0 - i as f32 / 16.0)).abs() < 1e-6);
+    }
+
+    context.tick();
+    assert!(context.channel(0).unwrap().frequency().is_none());
+}
+
+#[test]
+fn test_key_off_effect() {
+    let mut module = test_module(vec![vec![pattern::XmPatternSlot::new(
+        c4(),
+        Some(1),
+        None,
+        Some(effect::XmEffect::KeyOff(2)),
+    )]]);
+
+    let mut envelope = test_envelope(&[(0, 64)]);
+    envelope.sustain_point = Some(0);
+    let mut opts = test_sample_opts(Some(envelope), None);
+    opts.volume_fadeout = 0x1000;
+    module.instruments[0].0.sample_opts = Some(opts);
+
+    let mut context = context::XmPlaybackContext::new(&module, 44100);
+    let volumes = (0..4)
+        .map(|_| {
+            context.tick();
+            context.channel(0).unwrap().output_volume()
+        })
+        .collect::<Vec<_>>();
+
+    // the note is held for 2 ticks, then fades out by 1/16 every tick
+    assert_eq!(volumes, vec![1.0, 1.0, 15.0 / 16.0, 14.0 / 16.0]);
+}
+
+#[test]
+fn test_pan_law_center_gain() {
+    let (left, right) = context::XmPanLaw::ConstantPower.gains(0.5);
+    assert!((left - 0.5f32.sqrt()).abs() < 1e-6);
+    assert!((right - 0.5f32.sqrt()).abs() < 1e-6);
+
+    assert_eq!(context::XmPanLaw::Balance.gains(0.5), (1.0, 1.0));
+    assert_eq!(context::XmPanLaw::Balance.gains(0.0), (1.0, 0.0));
+    assert_eq!(context::XmPanLaw::Balance.gains(0.75), (0.5, 1.0));
+
+    assert_eq!(context::XmPanLaw::Linear.gains(0.5), (0.5, 0.5));
+    assert_eq!(context::XmPanLaw::Linear.gains(0.75), (0.25, 0.75));
+}
+
+#[test]
+fn test_pan_law_center_output() {
+    let module = test_module(vec![vec![pattern::XmPatternSlot::new(
+        c4(),
+        Some(1),
+        None,
+        None,
+    )]]);
+
+    // no stereo separation puts the channel exactly in the center
+    let render = |pan_law| {
+        let config = context::XmPlaybackConfig {
+            stereo_separation: 0,
+            pan_law,
+            ..Default::default()
+        };
+        let mut context = context::XmPlaybackContext::new_with_config(&module, 44100, config);
+        let mut out = vec![0.0; 512];
+        context.render(&mut out);
+        out
+    };
+
+    let constant_power = render(context::XmPanLaw::ConstantPower);
+    let linear = render(context::XmPanLaw::Linear);
+    assert!(linear.iter().any(|e| *e != 0.0));
+
+    for (constant_power, linear) in constant_power.chunks_exact(2).zip(linear.chunks_exact(2)) {
+        assert_eq!(linear[0], linear[1]);
+        assert!((constant_power[0] - linear[0] * 2.0f32.sqrt()).abs() < 1e-6);
+        assert!((constant_power[1] - linear[1] * 2.0f32.sqrt()).abs() < 1e-6);
+    }
+}
+
+#[test]
+fn test_set_volume_effect() {
+    let volume = |effect| {
+        // the volume column sets full volume first
+        let module = test_module(vec![vec![pattern::XmPatternSlot::new(
+            c4(),
+            Some(1),
+            Some(effect::XmVolumeColumn(0x50)),
+            Some(effect),
+        )]]);
+        let mut context = context::XmPlaybackContext::new(&module, 44100);
+        context.tick();
+        context.channel(0).unwrap().volume().0
+    };
+
+    assert_eq!(volume(effect::XmEffect::SetVolume(0x20)), 0.5);
+    assert_eq!(volume(effect::XmEffect::SetVolume(0)), 0.0);
+    // clamped to 64 like the volume column
+    assert_eq!(volume(effect::XmEffect::SetVolume(0x50)), 1.0);
+}
+
+#[test]
+fn test_panbrello() {
+    use effect::XmEffect;
+
+    let pannings = |rows: Vec<Option<XmEffect>>| {
+        let rows = rows
+            .into_iter()
+            .enumerate()
+            .map(|(i, effect)| {
+                let note = if i == 0 { c4() } else { note::XmNote::NoNote };
+                vec![pattern::XmPatternSlot::new(note, Some(1), None, effect)]
+            })
+            .collect();
+        let module = test_module(rows);
+        let mut context = context::XmPlaybackContext::new(&module, 44100);
+
+        let mut pannings = vec![];
+        for _ in 0..module.patterns[0].1 .0.len() * 6 {
+            context.tick();
+            pannings.push(context.channel(0).unwrap().output_panning());
+        }
+        pannings
+    };
+    let base = 128.0 / 255.0;
+    let panbrello = Some(XmEffect::Panbrello(effect::DoubleU4::from_bits(0x8F)));
+
+    // 3 full cycles of 32 ticks, then a row without the effect
+    let mut rows = vec![panbrello.clone(); 16];
+    rows.push(None);
+    let sine = pannings(rows);
+    let (swinging, after) = sine.split_at(16 * 6);
+    let mean = swinging.iter().sum::<f32>() / swinging.len() as f32;
+
+    assert!((mean - base).abs() < 1e-3, "{}", mean);
+    assert!(swinging.iter().any(|e| *e > base + 0.4));
+    assert!(swinging.iter().any(|e| *e < base - 0.4));
+    assert!(swinging.iter().all(|e| (0.0..=1.0).contains(e)));
+    assert!(after.iter().all(|e| *e == base));
+
+    // the parameters are remembered, and the square wave only takes on the two extremes
+    let mut rows = vec![Some(XmEffect::SetPanbrelloWaveform(2)), panbrello];
+    rows.extend(vec![
+        Some(XmEffect::Panbrello(effect::DoubleU4::from_bits(
+            0
+        )));
+        4
+    ]);
+    let square = pannings(rows);
+    let offset = 15.0 * 8.0 / 256.0;
+    for panning in &square[6..] {
+        assert!([base + offset, base - offset]
+            .iter()
+            .any(|e| (panning - e).abs() < 1e-6));
+    }
+    assert!(square[6..].iter().any(|e| *e < base));
+}
+
+#[test]
+fn test_stereo_separation() {
+    let module = test_module(vec![vec![pattern::XmPatternSlot::new(
+        c4(),
+        Some(1),
+        None,
+        Some(effect::XmEffect::SetPanningFine(0)),
+    )]]);
+
+    let render = |stereo_separation| {
+        let config = context::XmPlaybackConfig {
+            stereo_separation,
+            ..Default::default()
+        };
+        let mut context = context::XmPlaybackContext::new_with_config(&module, 44100, config);
+        let mut out = vec![0.0; 512];
+        context.render(&mut out);
+
+        let difference = out
+            .chunks_exact(2)
+            .map(|e| (e[0] - e[1]).abs())
+            .sum::<f32>();
+        let right = out.chunks_exact(2).map(|e| e[1].abs()).sum::<f32>();
+        (difference, right)
+    };
+
+    // hard left stays on the left only, narrowing it leaks into the right side
+    let (full, full_right) = render(100);
+    let (half, half_right) = render(50);
+    let (mono, _) = render(0);
+    assert!(full > 0.0);
+    assert_eq!(full_right, 0.0);
+    assert!(half < full && half_right > 0.0);
+    assert!(mono < 1e-3);
+}
+
+#[test]
+fn test_autovibrato_sweep() {
+    let mut module = test_module(vec![
+        vec![pattern::XmPatternSlot::new(c4(), Some(1), None, None)],
+        vec![pattern::XmPatternSlot::default()],
+    ]);
+    let mut opts = test_sample_opts(None, None);
+    opts.vibrato = instrument::XmVibratoOpts {
+        kind: instrument::XmVibratoType::Sine,
+        sweep: 8,
+        depth: 15,
+        // a quarter of a cycle per tick, so every other tick lands on a peak
+        rate: 64,
+    };
+    module.instruments[0].0.sample_opts = Some(opts);
+
+    let mut context = context::XmPlaybackContext::new(&module, 44100);
+
+    let mut deviations = vec![];
+    for _ in 0..10 {
+        context.tick();
+        let frequency = context.channel(0).unwrap().frequency().unwrap();
+        deviations.push((frequency - 8363.0).abs());
+    }
+
+    let peaks = deviations.iter().skip(1).step_by(2).collect::<Vec<_>>();
+    assert!(peaks.windows(2).all(|e| e[1] > e[0]));
+    assert!(deviations[0] < 1e-2);
+}
+
+/// Builds a minimal XM file with a single pattern of empty rows and no instruments
+fn test_xm_bytes(channels_num: u16, rows_num: u16) -> Vec<u8> {
+    let mut data = vec![];
+    data.extend(b"Extended Module: ");
+    data.extend([0; 20]); // Module name
+    data.push(0x1A);
+    data.extend([0; 20]); // Tracker name
+    data.extend(0x0104u16.to_le_bytes()); // Version
+    data.extend(276u32.to_le_bytes()); // Header size
+    data.extend(1u16.to_le_bytes()); // Song length
+    data.extend(0u16.to_le_bytes()); // Restart position
+    data.extend(channels_num.to_le_bytes());
+    data.extend(1u16.to_le_bytes()); // Number of patterns
+    data.extend(0u16.to_le_bytes()); // Number of instruments
+    data.extend(1u16.to_le_bytes()); // Flags
+    data.extend(6u16.to_le_bytes()); // Default tempo
+    data.extend(125u16.to_le_bytes()); // Default BPM
+    data.extend([0; 256]); // Pattern order table
+
+    data.extend(9u32.to_le_bytes()); // Pattern header length
+    data.push(0); // Packing type
+    data.extend(rows_num.to_le_bytes());
+    // every slot is packed as a single "nothing follows" flags byte
+    let packed_data_size = channels_num * rows_num;
+    data.extend(packed_data_size.to_le_bytes());
+    data.extend(vec![0x80; packed_data_size as usize]);
+
+    data
+}
+
+#[test]
+fn test_zero_channel_module() {
+    let data = test_xm_bytes(0, 64);
+    let (input, module) = parse(&data).unwrap();
+    assert!(input.is_empty());
+    assert_eq!(module.patterns[0].1 .0.len(), 64);
+    assert!(module.patterns[0].1 .0.iter().all(|row| row.0.is_empty()));
+
+    let mut context = context::XmPlaybackContext::new(&module, 44100);
+    let mut out = vec![1.0; 44100 * 2];
+    context.render(&mut out);
+    assert!(out.iter().all(|e| *e == 0.0));
+}
+
+#[test]
+fn test_many_channel_module() {
+    for channels_num in [64, 127] {
+        let data = test_xm_bytes(channels_num, 64);
+        let (input, module) = parse(&data).unwrap();
+        assert!(input.is_empty());
+        assert!(module.patterns[0]
+            .1
+             .0
+            .iter()
+            .all(|row| row.0.len() == channels_num as usize));
+    }
+    assert!(parse(&test_xm_bytes(128, 64)).is_err());
+
+    // every channel plays a note, then one row is rendered
+    let row = vec![pattern::XmPatternSlot::new(c4(), Some(1), None, None); 64];
+    let mut module = test_module(vec![row]);
+    module.instruments[0].0.sample_opts = Some(test_sample_opts(None, None));
+    let (_, module) = parse(&write::write(&module)).unwrap();
+    assert_eq!(module.header.channels_num, 64);
+
+    let mut context = context::XmPlaybackContext::new(&module, 44100);
+    let mut out = vec![0.0; 882 * 6 * 2];
+    context.render(&mut out);
+    assert!(out.iter().all(|e| e.is_finite()));
+    assert!(out.iter().any(|e| *e != 0.0));
+    assert_eq!(context.channel_levels().len(), 64);
+}
+
+#[test]
+fn test_retrigger() {
+    let restarts = |effect| {
+        let mut module = test_module(vec![
+            vec![pattern::XmPatternSlot::new(
+                c4(),
+                Some(1),
+                None,
+                Some(effect),
+            )],
+            vec![pattern::XmPatternSlot::default()],
+        ]);
+        let (mut header, data) = test_sample(vec![0; 100000]);
+        header.kind = header
+            .kind
+            .with_loop_type(instrument::XmSampleLoopType::NoLoop);
+        module.instruments[0].1[0] = (header, data);
+
+        let mut context = context::XmPlaybackContext::new(&module, 44100);
+
+        // a tick lasts 882 frames at 125 BPM, stop just before the pattern starts over
+        let mut restarts = 0;
+        let mut last_position = 0.0;
+        let mut out = [0.0; 2];
+        for _ in 0..882 * 11 {
+            context.render(&mut out);
+
+            let position = context.channel(0).unwrap().sample_position().unwrap();
+            if position < last_position {
+                restarts += 1;
+            }
+            last_position = position;
+        }
+
+        (restarts, context.channel(0).unwrap().volume().0)
+    };
+
+    assert_eq!(restarts(effect::XmEffect::Retrigger(3)), (1, 1.0));
+    assert_eq!(restarts(effect::XmEffect::Retrigger(2)), (2, 1.0));
+    assert_eq!(restarts(effect::XmEffect::Retrigger(1)), (5, 1.0));
+
+    // Rxy halves the volume on every retrigger
+    assert_eq!(
+        restarts(effect::XmEffect::RetriggerWithVolume(
+            effect::DoubleU4::from_bits(0x72)
+        )),
+        (2, 0.25)
+    );
+}
+
+#[test]
+fn test_playback_step() {
+    use frequency::FrequencyTable;
+
+    let mut sample = test_sample(vec![]).0;
+    let step = |sample: &instrument::XmSampleHeader, note: &note::XmNote, table| {
+        context::playback_step(note, sample, table, 8363).unwrap()
+    };
+
+    // C-4 plays samples at 8363 Hz with either table
+    assert!((step(&sample, &c4(), FrequencyTable::Linear) - 1.0).abs() < 1e-4);
+    assert!((step(&sample, &c4(), FrequencyTable::Amiga) - 1.0).abs() < 1e-3);
+
+    let c5 = c4().transpose(12).unwrap();
+    assert!((step(&sample, &c5, FrequencyTable::Linear) - 2.0).abs() < 1e-4);
+
+    // the sample's relative note and finetune are applied
+    sample.relative_note_num = -12;
+    assert!((step(&sample, &c5, FrequencyTable::Linear) - 1.0).abs() < 1e-4);
+    sample.finetune = 64;
+    let half_up = 2.0_f32.powf(0.5 / 12.0);
+    assert!((step(&sample, &c5, FrequencyTable::Linear) - half_up).abs() < 1e-4);
+
+    let note_off = context::playback_step(
+        &note::XmNote::NoteOff,
+        &sample,
+        FrequencyTable::Linear,
+        8363,
+    );
+    assert!(note_off.is_none());
+}
+
+#[test]
+fn test_resampling_type() {
+    use instrument::XmResamplingType;
+
+    let module = test_module(vec![vec![pattern::XmPatternSlot::new(
+        c4(),
+        Some(1),
+        None,
+        None,
+    )]]);
+    let render = |resampling| {
+        let config = context::XmPlaybackConfig {
+            resampling,
+            ..Default::default()
+        };
+        let mut context = context::XmPlaybackContext::new_with_config(&module, 44100, config);
+        let mut out = vec![0.0; 512];
+        context.render(&mut out);
+        out
+    };
+
+    let none = render(XmResamplingType::NoInterpolation);
+    let linear = render(XmResamplingType::LinearInterpolation);
+    let cubic = render(XmResamplingType::CubicInterpolation);
+
+    let difference =
+        |a: &[f32], b: &[f32]| a.iter().zip(b).map(|(a, b)| (a - b).abs()).sum::<f32>();
+    assert!(difference(&none, &cubic) > 1.0);
+    assert!(difference(&linear, &cubic) > 0.1);
+    assert_eq!(linear, render(XmResamplingType::default()));
+}
+
+#[test]
+fn test_cubic_interpolation() {
+    use interpolation::CubicInterpolation;
+
+    // passes through both middle points, and follows a straight line exactly
+    assert_eq!(
+        CubicInterpolation::interpolate([1.0, 2.0, 4.0, 8.0], 0.0),
+        2.0
+    );
+    assert_eq!(
+        CubicInterpolation::interpolate([1.0, 2.0, 4.0, 8.0], 1.0),
+        4.0
+    );
+    assert_eq!(
+        CubicInterpolation::interpolate([0.0, 1.0, 2.0, 3.0], 0.5),
+        1.5
+    );
+}
+
+#[test]
+fn test_tremor_duty_cycle() {
+    let tremor = || {
+        vec![pattern::XmPatternSlot::new(
+            note::XmNote::NoNote,
+            None,
+            None,
+            Some(effect::XmEffect::Tremor(effect::DoubleU4::from_bits(0x12))),
+        )]
+    };
+    let module = test_module(vec![
+        vec![pattern::XmPatternSlot::new(
+            c4(),
+            Some(1),
+            None,
+            Some(effect::XmEffect::Tremor(effect::DoubleU4::from_bits(0x12))),
+        )],
+        tremor(),
+    ]);
+    let mut context = context::XmPlaybackContext::new(&module, 44100);
+
+    let mut gates = vec![];
+    for _ in 0..12 {
+        context.tick();
+        gates.push(context.channel(0).unwrap().output_volume() > 0.0);
+    }
+
+    // T12 is on for 2 ticks and off for 3, carrying on across rows
+    let expected = [
+        true, true, false, false, false, true, true, false, false, false, true, true,
+    ];
+    assert_eq!(gates, expected);
+}
+
+#[test]
+fn test_interpolation_on_tiny_buffers() {
+    use instrument::{XmResamplingType, XmSamplePcmData};
+
+    let linear = |data: &XmSamplePcmData, pos, reverse| {
+        data.get_interpolated(pos, reverse, XmResamplingType::LinearInterpolation)
+    };
+
+    let empty = XmSamplePcmData::Bit8Data(vec![]);
+    assert_eq!(linear(&empty, 0.0, false), None);
+    assert_eq!(linear(&empty, 0.5, true), None);
+
+    // a single sample degrades to nearest neighbour
+    let single = XmSamplePcmData::Bit8Data(vec![64]);
+    for pos in [0.0, 0.25, 0.5, 0.99] {
+        assert_eq!(linear(&single, pos, false), Some(0.5));
+        assert_eq!(linear(&single, pos, true), Some(0.5));
+    }
+    assert_eq!(linear(&single, 1.0, false), None);
+
+    let pair = XmSamplePcmData::Bit8Data(vec![0, 64]);
+    assert_eq!(linear(&pair, 0.0, false), Some(0.0));
+    assert_eq!(linear(&pair, 0.5, false), Some(0.25));
+    // the last sample has no next neighbour to blend towards
+    assert_eq!(linear(&pair, 1.5, false), Some(0.5));
+    assert_eq!(linear(&pair, 1.5, true), Some(0.5));
+    // playing backwards interpolates between the same neighbours as forwards
+    assert_eq!(linear(&pair, 0.25, true), Some(0.125));
+    assert_eq!(linear(&pair, 2.0, false), None);
+    assert_eq!(linear(&pair, -0.5, false), None);
+}
+
+#[test]
+fn test_fine_portamento() {
+    // period offsets from C-4 for every tick of the row
+    let period_offsets = |effect| {
+        let module = test_module(vec![
+            vec![pattern::XmPatternSlot::new(
+                c4(),
+                Some(1),
+                None,
+                Some(effect),
+            )],
+            vec![pattern::XmPatternSlot::default()],
+        ]);
+        let mut context = context::XmPlaybackContext::new(&module, 44100);
+
+        (0..6)
+            .map(|_| {
+                context.tick();
+                let frequency = context.channel(0).unwrap().frequency().unwrap();
+                (768.0 * (8363.0 / frequency).log2()).round()
+            })
+            .collect::<Vec<f32>>()
+    };
+
+    // one-shot on the first tick, nothing afterwards
+    assert_eq!(
+        period_offsets(effect::XmEffect::FinePortamentoUp(4)),
+        vec![-16.0; 6]
+    );
+    assert_eq!(
+        period_offsets(effect::XmEffect::FinePortamentoDown(4)),
+        vec![16.0; 6]
+    );
+    assert_eq!(
+        period_offsets(effect::XmEffect::ExtraFinePortamentoUp(4)),
+        vec![-4.0; 6]
+    );
+    assert_eq!(
+        period_offsets(effect::XmEffect::ExtraFinePortamentoDown(4)),
+        vec![4.0; 6]
+    );
+}
+
+#[test]
+fn test_restart_position_skips_markers() {
+    let set_tempo = |value| {
+        vec![pattern::XmPatternSlot::new(
+            note::XmNote::NoNote,
+            None,
+            None,
+            Some(effect::XmEffect::SetTempo(value)),
+        )]
+    };
+
+    let mut module = test_module(vec![set_tempo(3)]);
+    let second_pattern = test_module(vec![set_tempo(4)]).patterns.remove(0);
+    module.patterns.push(second_pattern);
+    module.header.patterns_num = 2;
+    module.pattern_order_table = vec![0, pattern::XM_ORDER_SKIP_MARKER, 1];
+    module.header.song_length = 3;
+    module.header.restart_pos = 1;
+
+    assert_eq!(module.restart_order(), Some(2));
+
+    let mut context = context::XmPlaybackContext::new(&module, 44100);
+
+    // the first pattern lasts 3 ticks, then the skip marker leads to the second one
+    for _ in 0..3 {
+        context.tick();
+        assert_eq!(context.tempo(), 3);
+    }
+
+    // looping lands back on the second pattern, so the tempo never goes back to 3
+    for _ in 0..20 {
+        context.tick();
+        assert_eq!(context.tempo(), 4);
+    }
+
+    module.header.restart_pos = 3;
+    assert_eq!(module.restart_order(), None);
+}
+
+#[test]
+fn test_order_markers() {
+    use pattern::XmOrderEntry::*;
+
+    let set_tempo = |value| {
+        vec![pattern::XmPatternSlot::new(
+            note::XmNote::NoNote,
+            None,
+            None,
+            Some(effect::XmEffect::SetTempo(value)),
+        )]
+    };
+
+    let mut module = test_module(vec![set_tempo(3)]);
+    let second_pattern = test_module(vec![set_tempo(4)]).patterns.remove(0);
+    module.patterns.push(second_pattern);
+    module.header.patterns_num = 2;
+    module.pattern_order_table = vec![
+        0,
+        pattern::XM_ORDER_SKIP_MARKER,
+        1,
+        pattern::XM_ORDER_END_MARKER,
+        1,
+    ];
+    module.header.song_length = 5;
+
+    assert_eq!(
+        module.order_entries().collect::<Vec<_>>(),
+        [Pattern(0), Skip, Pattern(1), End, Pattern(1)]
+    );
+    assert_eq!(module.ordered_patterns().count(), 2);
+    for entry in module.order_entries() {
+        assert_eq!(pattern::XmOrderEntry::from(u8::from(entry)), entry);
+    }
+
+    // the skip marker leads to the second pattern, the end marker back to the first one
+    let mut context = context::XmPlaybackContext::new(&module, 44100);
+    for expected in [3, 3, 3, 4, 4, 4, 4, 3, 3, 3] {
+        context.tick();
+        assert_eq!(context.tempo(), expected);
+        assert_ne!(context.current_order(), 4);
+    }
+
+    assert_eq!(module.duration(8000).as_secs_f64(), 7.0 * 0.02);
+}
+
+#[test]
+fn test_missing_pattern_order() {
+    let mut module = test_module(vec![vec![pattern::XmPatternSlot::default()]]);
+    module.header.default_tempo = 1;
+    module.header.song_length = 3;
+    module.pattern_order_table = vec![0, 5, 0];
+
+    // FT2 plays the missing pattern as 64 empty rows
+    let mut context = context::XmPlaybackContext::new(&module, 44100);
+    context.tick();
+    for row in 0..pattern::XM_EMPTY_PATTERN_ROWS as u32 {
+        context.tick();
+        assert_eq!(context.position(), (1, row, 0));
+    }
+    context.tick();
+    assert_eq!(context.position(), (2, 0, 0));
+
+    assert_eq!(context.seek_to(1, 63), Ok(()));
+    assert_eq!(
+        module.duration(50),
+        core::time::Duration::from_secs_f64(66.0 / 50.0)
+    );
+    assert_eq!(module.time_to_position(0.51), Some((1, 24, 0)));
+    assert_eq!(module.ordered_patterns().count(), 2);
+}
+
+#[test]
+fn test_panning_slide() {
+    let row = |note, effect| vec![pattern::XmPatternSlot::new(note, None, None, Some(effect))];
+    let module = test_module(vec![
+        vec![pattern::XmPatternSlot::new(
+            c4(),
+            Some(1),
+            None,
+            Some(effect::XmEffect::SetPanningFine(0x00)),
+        )],
+        row(
+            note::XmNote::NoNote,
+            effect::XmEffect::PanningSlide(effect::DoubleU4::from_bits(0x40)),
+        ),
+        row(note::XmNote::NoNote, effect::XmEffect::SetPanning(0xF)),
+    ]);
+    let mut context = context::XmPlaybackContext::new(&module, 44100);
+    let panning = |context: &context::XmPlaybackContext| context.channel(0).unwrap().volume().1;
+
+    context.tick();
+    assert_eq!(panning(&context), 0.0);
+
+    for _ in 0..5 {
+        context.tick();
+    }
+
+    // the slide leaves the first tick alone, then moves right by 4/255 per tick
+    let mut pannings = vec![];
+    for _ in 0..6 {
+        context.tick();
+        pannings.push(panning(&context));
+    }
+    for (i, panning) in pannings.iter().enumerate() {
+        assert!((panning - i as f32 * 4.0 / 255.0).abs() < 1e-6);
+    }
+
+    context.tick();
+    assert_eq!(panning(&context), 1.0);
+}
+
+#[test]
+fn test_seek_to() {
+    let row = |volume_column: Option<u8>, effect| {
+        vec![pattern::XmPatternSlot::new(
+            note::XmNote::NoNote,
+            None,
+            volume_column.map(effect::XmVolumeColumn::new),
+            effect,
+        )]
+    };
+    let module = test_module(vec![
+        vec![pattern::XmPatternSlot::new(c4(), Some(1), None, None)],
+        row(Some(0x30), Some(effect::XmEffect::SetTempo(4))),
+        row(Some(0x62), None),
+        row(None, None),
+    ]);
+
+    // play up to the last row normally
+    let mut expected = context::XmPlaybackContext::new(&module, 44100);
+    for _ in 0..6 + 4 + 4 {
+        expected.tick();
+    }
+
+    let mut context = context::XmPlaybackContext::new(&module, 44100);
+    assert_eq!(context.seek_to(0, 3), Ok(()));
+    assert_eq!(context.tempo(), expected.tempo());
+    assert_eq!(
+        context.channel(0).unwrap().volume(),
+        expected.channel(0).unwrap().volume()
+    );
+    assert_eq!(context.tempo(), 4);
+    assert_eq!(
+        context.channel(0).unwrap().volume().0,
+        0.5 - 3.0 * 2.0 / 64.0
+    );
+
+    // seeking backwards replays from the start, rows past the end are clamped
+    assert_eq!(context.seek_to(0, 100), Ok(()));
+    assert_eq!(
+        context.channel(0).unwrap().volume().0,
+        0.5 - 3.0 * 2.0 / 64.0
+    );
+    assert_eq!(context.seek_to(0, 0), Ok(()));
+    assert_eq!(context.tempo(), 6);
+
+    assert_eq!(
+        context.seek_to(1, 0),
+        Err(context::XmSeekError::InvalidOrder(1))
+    );
+
+    // the song ends before the third order, so it's never played
+    let mut module = module.clone();
+    module.header.song_length = 3;
+    module.pattern_order_table = vec![0, pattern::XM_ORDER_END_MARKER, 0];
+    let mut context = context::XmPlaybackContext::new(&module, 44100);
+    assert_eq!(
+        context.seek_to(2, 0),
+        Err(context::XmSeekError::InvalidOrder(2))
+    );
+}
+
+#[test]
+fn test_time_position_mapping() {
+    let set_tempo = |value| {
+        vec![pattern::XmPatternSlot::new(
+            note::XmNote::NoNote,
+            None,
+            None,
+            Some(effect::XmEffect::SetTempo(value)),
+        )]
+    };
+
+    // ticks last 20ms at 125 BPM, and 10ms at 250 BPM
+    let module = test_module(vec![
+        vec![pattern::XmPatternSlot::default()], // 0.00s, 6 ticks
+        set_tempo(3),                            // 0.12s, 3 ticks
+        vec![pattern::XmPatternSlot::default()], // 0.18s, 3 ticks
+        set_tempo(250),                          // 0.24s, 3 faster ticks
+    ]);
+
+    assert_eq!(module.time_to_position(0.0), Some((0, 0, 0)));
+    assert_eq!(module.time_to_position(0.13), Some((0, 1, 0)));
+    assert_eq!(module.time_to_position(0.205), Some((0, 2, 1)));
+    assert_eq!(module.time_to_position(0.265), Some((0, 3, 2)));
+    assert_eq!(module.time_to_position(0.3), None);
+
+    let close = |a: Option<f64>, b: f64| (a.unwrap() - b).abs() < 1e-9;
+    assert!(close(module.position_to_time(0, 0, 0), 0.0));
+    assert!(close(module.position_to_time(0, 1, 2), 0.16));
+    assert!(close(module.position_to_time(0, 3, 1), 0.25));
+    assert_eq!(module.position_to_time(0, 1, 3), None);
+    assert_eq!(module.position_to_time(1, 0, 0), None);
+    assert!((module.duration(44100).as_secs_f64() - 0.27).abs() <= 1.0 / 44100.0);
+}
+
+#[test]
+fn test_zero_bpm_timing() {
+    // a BPM of 0 plays like 1, so ticks last 2.5s
+    let mut module = test_module(vec![vec![pattern::XmPatternSlot::default()]; 2]);
+    module.header.default_bpm = 0;
+
+    assert_eq!(module.duration(50), std::time::Duration::from_secs(30));
+    assert_eq!(module.time_to_position(20.0), Some((0, 1, 2)));
+    assert_eq!(module.time_to_position(30.0), None);
+    assert_eq!(module.position_to_time(0, 1, 0), Some(15.0));
+
+    let config = context::XmPlaybackConfig {
+        looping: false,
+        ..Default::default()
+    };
+    let mut context = context::XmPlaybackContext::new_with_config(&module, 50, config);
+    assert_eq!(context.frames().count(), 30 * 50);
+}
+
+#[test]
+fn test_duration_follows_jumps() {
+    let with_effect = |effect| {
+        vec![pattern::XmPatternSlot::new(
+            note::XmNote::NoNote,
+            None,
+            None,
+            Some(effect),
+        )]
+    };
+    let row_secs = |rows: f64| std::time::Duration::from_secs_f64(rows * 0.12);
+
+    // rows 0 and 1 play 3 times, then the break ends the song, skipping row 3
+    let module = test_module(vec![
+        with_effect(effect::XmEffect::PatternLoopStart),
+        with_effect(effect::XmEffect::PatternLoop(2)),
+        with_effect(effect::XmEffect::PatternBreak(0x12)),
+        vec![pattern::XmPatternSlot::default()],
+    ]);
+    assert_eq!(module.duration(8000), row_secs(7.0));
+    assert_eq!(module.position_to_time(0, 3, 0), None);
+
+    // jumping back to the start loops the song, which ends it
+    let mut module = test_module(vec![
+        vec![pattern::XmPatternSlot::default()],
+        with_effect(effect::XmEffect::PositionJump(1)),
+        with_effect(effect::XmEffect::PositionJump(0)),
+    ]);
+    module.header.song_length = 2;
+    module.pattern_order_table = vec![0, 0];
+    assert_eq!(module.duration(8000), row_secs(4.0));
+    assert_eq!(module.time_to_position(0.3), Some((1, 0, 3)));
+
+    // a pattern loop that restarts itself never settles
+    let module = test_module(vec![
+        with_effect(effect::XmEffect::PatternLoop(1)),
+        with_effect(effect::XmEffect::PatternLoop(1)),
+    ]);
+    assert_eq!(module.duration(8000), row_secs(XM_MAX_WALKED_ROWS as f64));
+}
+
+#[test]
+fn test_playback_follows_jumps() {
+    let with_effect = |effect| {
+        vec![pattern::XmPatternSlot::new(
+            note::XmNote::NoNote,
+            None,
+            None,
+            Some(effect),
+        )]
+    };
+
+    // rows 0 and 1 play twice, row 2 three times as long, then the break leads to row 4 of the
+    // second order and the jump back to the start ends the song
+    let mut module = test_module(vec![
+        with_effect(effect::XmEffect::PatternLoopStart),
+        with_effect(effect::XmEffect::PatternLoop(1)),
+        with_effect(effect::XmEffect::PatternDelay(2)),
+        with_effect(effect::XmEffect::PatternBreak(0x04)),
+        vec![pattern::XmPatternSlot::default()],
+        with_effect(effect::XmEffect::PositionJump(0)),
+    ]);
+    module.header.song_length = 2;
+    module.pattern_order_table = vec![0, 0];
+
+    let config = context::XmPlaybackConfig {
+        looping: false,
+        ..Default::default()
+    };
+    let mut context = context::XmPlaybackContext::new_with_config(&module, 8000, config);
+    context.track_events(true);
+
+    // 10 rows of 6 ticks, 160 frames each
+    let frames = context.frames().count();
+    assert_eq!(frames, 60 * 160);
+    assert_eq!(module.duration(8000).as_secs_f64(), frames as f64 / 8000.0);
+
+    let rows = context
+        .drain_events()
+        .filter_map(|e| match e {
+            context::XmPlaybackEvent::RowChanged { order, row } => Some((order, row)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        rows,
+        [
+            (0, 0),
+            (0, 1),
+            (0, 0),
+            (0, 1),
+            (0, 2),
+            (0, 3),
+            (1, 4),
+            (1, 5)
+        ]
+    );
+
+    // the delayed row keeps counting its ticks
+    assert_eq!(module.time_to_position(0.6), Some((0, 2, 6)));
+    assert_eq!(context.seek_to(1, 4), Ok(()));
+    assert_eq!(context.position(), (1, 4, 0));
+    assert_eq!(
+        context.seek_to(1, 0),
+        Err(context::XmSeekError::InvalidOrder(1))
+    );
+}
+
+#[test]
+fn test_playback_events() {
+    use context::XmPlaybackEvent::*;
+
+    let mut module = test_module(vec![vec![pattern::XmPatternSlot::default()]; 2]);
+    module.header.default_tempo = 1;
+    module.header.song_length = 3;
+    module.pattern_order_table = vec![0, pattern::XM_ORDER_SKIP_MARKER, 0];
+
+    // 50 ticks per second at 125 BPM, so every frame is a tick
+    let mut context = context::XmPlaybackContext::new(&module, 50);
+    context.track_events(true);
+
+    context.render(&mut [0.0; 10]);
+    assert_eq!(
+        context.drain_events().collect::<Vec<_>>(),
+        [
+            OrderChanged(0),
+            RowChanged { order: 0, row: 0 },
+            RowChanged { order: 0, row: 1 },
+            OrderChanged(2),
+            RowChanged { order: 2, row: 0 },
+            RowChanged { order: 2, row: 1 },
+            SongEnded,
+            SongLooped(0),
+            OrderChanged(0),
+            RowChanged { order: 0, row: 0 },
+        ]
+    );
+
+    context.seek_to(2, 1).unwrap();
+    context.render(&mut [0.0; 2]);
+    // the last row ends within the frame
+    let events = context.drain_events().collect::<Vec<_>>();
+    let row = RowChanged { order: 2, row: 1 };
+    assert_eq!(events, [OrderChanged(2), row, SongEnded, SongLooped(0)]);
+
+    context.track_events(false);
+    context.render(&mut [0.0; 2]);
+    assert_eq!(context.drain_events().count(), 0);
+
+    // jumping back to the start ends the song just like running past its end
+    let mut module = test_module(vec![
+        vec![pattern::XmPatternSlot::default()],
+        vec![pattern::XmPatternSlot::new(
+            note::XmNote::NoNote,
+            None,
+            None,
+            Some(effect::XmEffect::PositionJump(0)),
+        )],
+    ]);
+    module.header.default_tempo = 1;
+
+    let mut context = context::XmPlaybackContext::new(&module, 50);
+    context.track_events(true);
+    context.render(&mut [0.0; 6]);
+    assert_eq!(
+        context.drain_events().collect::<Vec<_>>(),
+        [
+            OrderChanged(0),
+            RowChanged { order: 0, row: 0 },
+            RowChanged { order: 0, row: 1 },
+            SongEnded,
+            SongLooped(0),
+            RowChanged { order: 0, row: 0 },
+        ]
+    );
+
+    // without looping the song isn't reported to start over
+    let config = context::XmPlaybackConfig {
+        looping: false,
+        ..Default::default()
+    };
+    let mut context = context::XmPlaybackContext::new_with_config(&module, 50, config);
+    context.track_events(true);
+    assert_eq!(context.frames().count(), 2);
+    assert_eq!(
+        context.drain_events().collect::<Vec<_>>(),
+        [
+            OrderChanged(0),
+            RowChanged { order: 0, row: 0 },
+            RowChanged { order: 0, row: 1 },
+            SongEnded,
+        ]
+    );
+}
+
+#[test]
+fn test_playback_position() {
+    let module = test_module(vec![vec![pattern::XmPatternSlot::default()]; 2]);
+
+    // every frame is a tick at 125 BPM
+    let mut context = context::XmPlaybackContext::new(&module, 50);
+    assert_eq!(context.position(), (0, 0, 0));
+    assert_eq!((context.tempo(), context.bpm()), (6, 125));
+
+    context.render(&mut [0.0; 2]);
+    assert_eq!(context.position(), (0, 0, 0));
+
+    context.render(&mut [0.0; 6]);
+    assert_eq!(context.position(), (0, 0, 3));
+
+    context.render(&mut [0.0; 6]);
+    assert_eq!(context.position(), (0, 1, 0));
+    assert_eq!(context.current_row(), 1);
+
+    // the song loops after the last tick of the second row
+    context.render(&mut [0.0; 12]);
+    assert_eq!(context.position(), (0, 0, 0));
+
+    context.seek_to(0, 1).unwrap();
+    assert_eq!(context.position(), (0, 1, 0));
+}
+
+#[test]
+fn test_tick_accounting() {
+    let mut module = test_module(vec![vec![pattern::XmPatternSlot::default()]; 4]);
+    // 50.8 ticks per second, which doesn't divide either sample rate
+    module.header.default_bpm = 127;
+
+    for sample_rate in [8000, 48000] {
+        let mut context = context::XmPlaybackContext::new(&module, sample_rate);
+
+        // 4 rows of 6 ticks end on the frame tick 24 starts at
+        let frames = (24 * sample_rate as usize * 5) / (127 * 2);
+        let mut out = vec![0.0; frames * 2];
+        context.render(&mut out);
+        assert_eq!(context.position(), (0, 3, 5));
+
+        context.render(&mut [0.0; 2]);
+        assert_eq!(context.position(), (0, 0, 0));
+    }
+}
+
+#[test]
+fn test_frames_iterator() {
+    let module = test_module(vec![vec![pattern::XmPatternSlot::new(
+        c4(),
+        Some(1),
+        None,
+        None,
+    )]]);
+
+    // the row lasts 6 ticks of 160 frames at 8000 Hz
+    let config = context::XmPlaybackConfig {
+        looping: false,
+        ..Default::default()
+    };
+    let mut context = context::XmPlaybackContext::new_with_config(&module, 8000, config);
+    let frames = context.frames().collect::<Vec<_>>();
+    assert_eq!(frames.len(), 960);
+    assert_eq!(context.frames().next(), None);
+
+    let mut context = context::XmPlaybackContext::new(&module, 8000);
+    let mut out = vec![0.0; 960 * 2];
+    context.render(&mut out);
+    let rendered = out
+        .chunks_exact(2)
+        .map(|e| (e[0], e[1]))
+        .collect::<Vec<_>>();
+    assert_eq!(frames, rendered);
+
+    // looping goes on past the end of the song
+    let mut context = context::XmPlaybackContext::new(&module, 8000);
+    assert_eq!(context.frames().take(2000).count(), 2000);
+}
+
+#[test]
+fn test_mute_and_solo_channels() {
+    let module = test_module(vec![
+        vec![
+            pattern::XmPatternSlot::new(c4(), Some(1), None, None),
+            pattern::XmPatternSlot::new(c4().transpose(7).unwrap(), Some(1), None, None),
+        ],
+        vec![pattern::XmPatternSlot::default(); 2],
+    ]);
+
+    let mut reference = context::XmPlaybackContext::new(&module, 44100);
+    let mut context = context::XmPlaybackContext::new(&module, 44100);
+    let mut expected = vec![0.0; 2048];
+    let mut out = vec![0.0; 2048];
+
+    context.mute_channel(1);
+    reference.render_channels(&[0], &mut expected);
+    context.render(&mut out);
+    assert_eq!(out, expected);
+
+    context.solo_channel(1);
+    reference.render_channels(&[1], &mut expected);
+    context.render(&mut out);
+    assert_eq!(out, expected);
+
+    // the muted channel kept playing, so unmuting picks up where the reference is
+    context.unmute_channel(0);
+    reference.render(&mut expected);
+    context.render(&mut out);
+    assert_eq!(out, expected);
+
+    context.mute_channel(0);
+    context.mute_channel(1);
+    context.render(&mut out);
+    assert!(out.iter().all(|e| *e == 0.0));
+}
+
+#[test]
+fn test_channel_levels() {
+    let module = test_module(vec![vec![
+        pattern::XmPatternSlot::default(),
+        pattern::XmPatternSlot::new(c4(), Some(1), None, None),
+    ]]);
+    let mut context = context::XmPlaybackContext::new(&module, 44100);
+
+    let mut peaks = [0.0f32; 2];
+    let mut out = [0.0; 2];
+    for _ in 0..1024 {
+        context.render(&mut out);
+
+        for (peak, level) in peaks.iter_mut().zip(context.channel_levels()) {
+            *peak = peak.max(level);
+        }
+    }
+
+    assert!(peaks[0] < 1e-6);
+    assert!(peaks[1] > 0.0);
+}
+
+#[test]
+fn test_output_limiter() {
+    use context::XmOutputLimiter;
+
+    // 8 full scale channels in the center, which all play at full volume with the balance law
+    let mut module = test_module(vec![vec![
+        pattern::XmPatternSlot::new(
+            c4(),
+            Some(1),
+            None,
+            None
+        );
+        8
+    ]]);
+    module.instruments[0].1[0] = test_sample(vec![127, 127, -128, -128]);
+
+    let render = |limiter| {
+        let config = context::XmPlaybackConfig {
+            pan_law: context::XmPanLaw::Balance,
+            limiter,
+            ..Default::default()
+        };
+        let mut context = context::XmPlaybackContext::new_with_config(&module, 44100, config);
+        let mut out = vec![0.0; 2 * 1024];
+        context.render(&mut out);
+        out
+    };
+
+    let unlimited = render(XmOutputLimiter::None);
+    assert!(unlimited.iter().any(|e| e.abs() > 4.0));
+
+    for limiter in [
+        XmOutputLimiter::HardClip,
+        XmOutputLimiter::SoftClip,
+        XmOutputLimiter::Normalize,
+    ] {
+        let out = render(limiter);
+        assert!(
+            out.iter().all(|e| (-1.0..=1.0).contains(e)),
+            "{:?}",
+            limiter
+        );
+        assert!(out.iter().any(|e| e.abs() > 0.9), "{:?}", limiter);
+    }
+
+    // quiet output passes through the soft clip nearly untouched
+    assert_eq!(XmOutputLimiter::HardClip.apply(0.5, 8), 0.5);
+    assert!((XmOutputLimiter::SoftClip.apply(0.05, 8) - 0.05).abs() < 1e-4);
+    assert_eq!(XmOutputLimiter::Normalize.apply(2.0, 16), 0.5);
+}
+
+#[test]
+fn test_render_mono() {
+    // hard left and hard right channels, so the sides differ
+    let module = test_module(vec![vec![
+        pattern::XmPatternSlot::new(
+            c4(),
+            Some(1),
+            None,
+            Some(effect::XmEffect::SetPanningFine(0)),
+        ),
+        pattern::XmPatternSlot::new(
+            c4().transpose(7).unwrap(),
+            Some(1),
+            None,
+            Some(effect::XmEffect::SetPanningFine(0xFF)),
+        ),
+    ]]);
+
+    let mut stereo = vec![0.0; 2 * 2048];
+    context::XmPlaybackContext::new(&module, 44100).render(&mut stereo);
+    let mut mono = vec![0.0; 2048];
+    context::XmPlaybackContext::new(&module, 44100).render_mono_into(&mut mono);
+
+    assert!(stereo.chunks_exact(2).any(|e| e[0] != e[1]));
+    for (frame, mono) in stereo.chunks_exact(2).zip(&mono) {
+        assert_eq!(*mono, (frame[0] + frame[1]) / 2.0);
+    }
+
+    // the row lasts 6 ticks of 882 frames, then the song is over
+    let config = context::XmPlaybackConfig {
+        looping: false,
+        ..Default::default()
+    };
+    let mut mono = vec![1.0; 6000];
+    context::XmPlaybackContext::new_with_config(&module, 44100, config).render_mono_into(&mut mono);
+    assert!(mono[..5292].iter().any(|e| *e != 0.0));
+    assert!(mono[5292..].iter().all(|e| *e == 0.0));
+}
+
+#[test]
+fn test_channel_info() {
+    let mut module = test_module(vec![vec![
+        pattern::XmPatternSlot::new(c4(), Some(1), Some(effect::XmVolumeColumn(0x30)), None),
+        pattern::XmPatternSlot::default(),
+        pattern::XmPatternSlot::new(c4(), Some(2), None, None),
+    ]]);
+    module.instruments[0].0.name = "lead".to_owned();
+    let mut context = context::XmPlaybackContext::new(&module, 44100);
+
+    context.tick();
+    context.mute_channel(0);
+    let info = context.channel_info();
+
+    assert_eq!(info.len(), 3);
+    assert_eq!(info[0].instrument_index, Some(1));
+    assert_eq!(info[0].instrument_name, Some("lead"));
+    assert_eq!(info[0].note, c4());
+    assert!(info[0].playing && info[0].muted);
+    assert_eq!(info[0].volume, 0.5);
+    assert!((info[0].panning - 128.0 / 255.0).abs() < 1e-6);
+
+    assert_eq!(info[1].instrument_index, None);
+    assert_eq!(info[1].instrument_name, None);
+    assert!(!info[1].playing && !info[1].muted);
+
+    // the instrument doesn't exist, so nothing plays
+    assert_eq!(info[2].instrument_index, Some(2));
+    assert_eq!(info[2].instrument_name, None);
+    assert!(!info[2].playing);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_round_trip() {
+    let mut module = test_module(vec![
+        vec![pattern::XmPatternSlot::new(
+            c4(),
+            Some(1),
+            Some(effect::XmVolumeColumn(0x30)),
+            Some(effect::XmEffect::Vibrato(effect::DoubleU4::from_bits(0x47))),
+        )],
+        vec![pattern::XmPatternSlot::new(
+            note::XmNote::NoteOff,
+            None,
+            None,
+            None,
+        )],
+    ]);
+    let mut sample_opts = test_sample_opts(Some(test_envelope(&[(0, 64), (8, 0)])), None);
+    sample_opts.sample_keymap_assignments[95] = 1;
+    module.instruments[0].0.sample_opts = Some(sample_opts);
+
+    let json = serde_json::to_string(&module).unwrap();
+    let restored: XmModule = serde_json::from_str(&json).unwrap();
+
+    assert_eq!(serde_json::to_string(&restored).unwrap(), json);
+    assert!(matches!(
+        restored.patterns[0].1 .0[0].0[0].effect,
+        Some(effect::XmEffect::Vibrato(e)) if e.into_bits() == 0x47
+    ));
+
+    let (_, module) = parse(include_bytes!("test_xms/test_w_mpt_ext.xm")).unwrap();
+    let json = serde_json::to_string(&module).unwrap();
+    let restored: XmModule = serde_json::from_str(&json).unwrap();
+    assert_eq!(serde_json::to_string(&restored).unwrap(), json);
+}
+
+#[test]
+fn test_write_round_trip() {
+    let mut module = test_module(vec![
+        vec![
+            pattern::XmPatternSlot::new(
+                c4(),
+                Some(1),
+                Some(effect::XmVolumeColumn(0x30)),
+                Some(effect::XmEffect::Vibrato(effect::DoubleU4::from_bits(0x47))),
+            ),
+            pattern::XmPatternSlot::new(
+                note::XmNote::NoteOff,
+                None,
+                None,
+                Some(effect::XmEffect::FineVolumeSlideDown(3)),
+            ),
+        ],
+        vec![
+            pattern::XmPatternSlot::default(),
+            pattern::XmPatternSlot::new(
+                note::XmNote::NoNote,
+                None,
+                Some(effect::XmVolumeColumn(0xC8)),
+                Some(effect::XmEffect::PatternLoopStart),
+            ),
+        ],
+    ]);
+
+    let mut volume_envelope = test_envelope(&[(0, 64), (4, 32), (8, 0)]);
+    volume_envelope.sustain_point = Some(1);
+    module.instruments[0].0.name = "sine".to_owned();
+    module.instruments[0].0.sample_opts = Some(test_sample_opts(Some(volume_envelope), None));
+    module.instruments[0].1.push(test_sample(vec![0; 4]));
+    module.instruments[0].1[1].1 =
+        instrument::XmSamplePcmData::Bit16Data(vec![0, 32767, -32768, 1]);
+
+    let written = write::write(&module);
+    let (_, parsed) = parse(&written).unwrap();
+    let rewritten = write::write(&parsed);
+    let (_, reparsed) = parse(&rewritten).unwrap();
+
+    assert!(parsed == reparsed);
+    assert_eq!(written, rewritten);
+    assert!(parsed.patterns[0].1 == module.patterns[0].1);
+    assert!(parsed.instruments[0].1[1].1 == module.instruments[0].1[1].1);
+    assert!(parsed.instruments[0].0.sample_opts == module.instruments[0].0.sample_opts);
+}
+
+#[test]
+#[should_panic(expected = "more than its size field can hold")]
+fn test_write_oversized_pattern() {
+    let slot = pattern::XmPatternSlot::new(
+        c4(),
+        Some(1),
+        Some(effect::XmVolumeColumn(0x30)),
+        Some(effect::XmEffect::Vibrato(effect::DoubleU4::from_bits(0x47))),
+    );
+    // every slot packs into 5 bytes, 256 rows of 64 of them don't fit into 16 bits
+    let module = test_module(vec![vec![slot; 64]; 256]);
+    write::write(&module);
+}
+
+#[test]
+fn test_legacy_layout() {
+    let mut module = test_module(vec![vec![pattern::XmPatternSlot::new(
+        c4(),
+        Some(1),
+        None,
+        Some(effect::XmEffect::PortamentoUp(4)),
+    )]]);
+    module.instruments[0].0.sample_opts = Some(test_sample_opts(None, None));
+
+    let written = write::write(&module);
+    let (_, expected) = parse(&written).unwrap();
+
+    // rearrange the 1.04 layout into header, instrument and sample headers, patterns and then
+    // sample data
+    let packed = write::pack_rows(&module.patterns[0].1);
+    let (header, rest) = written.split_at(80 + 256);
+    let (patterns, rest) = rest.split_at(9 + packed.len());
+    let (instrument_headers, sample_data) = rest.split_at(263 + 40);
+
+    let mut data = [header, instrument_headers, patterns, sample_data].concat();
+    data[58] = 0x03;
+
+    let (_, parsed) = parse(&data).unwrap();
+    assert_eq!(parsed.header.version, 0x0103);
+    assert!(parsed.patterns == expected.patterns);
+    assert!(parsed.instruments == expected.instruments);
+
+    // 1.02 stores the number of rows minus one in a byte
+    let rows_num = module.patterns[0].1 .0.len() as u8;
+    let pattern = [&[8, 0, 0, 0, 0, rows_num - 1], &patterns[7..]].concat();
+    let mut data = [header, instrument_headers, &pattern, sample_data].concat();
+    data[58] = 0x02;
+
+    let (_, parsed) = parse(&data).unwrap();
+    assert_eq!(parsed.patterns[0].0.rows_num, rows_num as u16);
+    assert!(parsed.patterns[0].1 == expected.patterns[0].1);
+    assert!(parsed.instruments == expected.instruments);
+
+    // written back out in the current layout
+    assert_eq!(write::write(&parsed), written);
+}
+
+#[test]
+fn test_lenient_parsing() {
+    let long_pattern = test_module(vec![vec![pattern::XmPatternSlot::default()]; 300]);
+    let data = write::write(&long_pattern);
+
+    assert!(parse(&data).is_err());
+    let (_, parsed) = parse_with_options(&data, ParseOptions { strict: false }).unwrap();
+    assert_eq!(parsed.patterns[0].0.rows_num, 300);
+
+    // there's no volume column command between set volume and volume slide down
+    let unknown_volume = test_module(vec![vec![pattern::XmPatternSlot::new(
+        c4(),
+        Some(1),
+        Some(effect::XmVolumeColumn(0x55)),
+        None,
+    )]]);
+    let data = write::write(&unknown_volume);
+
+    assert!(parse(&data).is_err());
+    let (_, parsed) = parse_with_options(&data, ParseOptions { strict: false }).unwrap();
+    let volume = parsed.patterns[0].1 .0[0].0[0]
+        .volume_column
+        .as_ref()
+        .unwrap();
+    assert!(matches!(
+        volume.command(),
+        effect::XmVolumeColumnCommand::Unknown
+    ));
+    assert_eq!(write::write(&parsed), data);
+}
+
+#[test]
+fn test_unknown_effect() {
+    let unknown = effect::XmEffect::Unknown {
+        command: 0x25,
+        parameter: 0x12,
+    };
+    let module = test_module(vec![vec![pattern::XmPatternSlot::new(
+        c4(),
+        Some(1),
+        None,
+        Some(unknown.clone()),
+    )]]);
+
+    let written = write::write(&module);
+    let (_, parsed) = parse(&written).unwrap();
+
+    assert!(parsed.patterns[0].1 == module.patterns[0].1);
+    assert_eq!(write::write(&parsed), written);
+    assert_eq!(unknown.to_string(), "?12");
+    let unknown = effect::XmEffect::Unknown {
+        command: 0x20,
+        parameter: 0x0F,
+    };
+    assert_eq!(unknown.to_string(), "W0F");
+}
+
+#[test]
+fn test_volume_column_panning() {
+    let panning = |byte| effect::XmVolumeColumn::new(byte).argument();
+
+    assert_eq!(panning(0xC0), 0x00);
+    assert_eq!(panning(0xC8), 0x80);
+    assert_eq!(panning(0xCF), 0xF0);
+
+    assert_eq!(effect::XmVolumeColumn::new(0xC8).to_string(), "p80");
+    assert_eq!(effect::XmVolumeColumn::new(0xCF).to_string(), "pF0");
+}
+
+#[test]
+fn test_extended_effect_mapping() {
+    use effect::XmEffect::*;
+
+    let parse_e = |parameter| {
+        effect::parse_effect(true, true)(&[0x0E, parameter])
+            .unwrap()
+            .1
+    };
+
+    let expected = [
+        Unknown {
+            command: 0x0E,
+            parameter: 0x03,
+        },
+        FinePortamentoUp(3),
+        FinePortamentoDown(3),
+        GlissandoControl(3),
+        SetVibratoWaveform(3),
+        SetFinetune(3),
+        PatternLoop(3),
+        SetTremoloWaveform(3),
+        SetPanning(3),
+        Retrigger(3),
+        FineVolumeSlideUp(3),
+        FineVolumeSlideDown(3),
+        NoteCut(3),
+        NoteDelay(3),
+        PatternDelay(3),
+        SetActiveMacro(3),
+    ];
+
+    for (nibble, effect) in expected.into_iter().enumerate() {
+        let parameter = (nibble as u8) << 4 | 3;
+        assert!(parse_e(parameter) == Some(effect), "E{:02X}", parameter);
+    }
+
+    // only the zero count of E6x is special
+    assert!(parse_e(0x60) == Some(PatternLoopStart));
+    assert!(parse_e(0x61) == Some(PatternLoop(1)));
+    assert!(parse_e(0x6F) == Some(PatternLoop(0xF)));
+}
+
+#[test]
+fn test_sound_control_surround() {
+    let render = |effect| {
+        let slot = pattern::XmPatternSlot::new(c4(), Some(1), None, effect);
+        let module = test_module(vec![vec![slot]]);
+        let mut context = context::XmPlaybackContext::new(&module, 44100);
+        let mut out = vec![0.0; 2 * 1024];
+        context.render(&mut out);
+        out
+    };
+
+    let plain = render(None);
+    let surround = render(Some(effect::XmEffect::SoundControl(0x1)));
+    assert!(plain.iter().any(|e| *e != 0.0));
+
+    // only the right side is inverted
+    for (plain, surround) in plain.chunks_exact(2).zip(surround.chunks_exact(2)) {
+        assert_eq!(surround[0], plain[0]);
+        assert_eq!(surround[1], -plain[1]);
+    }
+    assert_eq!(render(Some(effect::XmEffect::SoundControl(0x0))), plain);
+
+    let flags = |commands: &[u8]| {
+        let rows = commands
+            .iter()
+            .map(|e| {
+                let effect = Some(effect::XmEffect::SoundControl(*e));
+                vec![pattern::XmPatternSlot::new(
+                    note::XmNote::NoNote,
+                    None,
+                    None,
+                    effect,
+                )]
+            })
+            .collect();
+        let module = test_module(rows);
+        let mut context = context::XmPlaybackContext::new(&module, 44100);
+        for _ in 0..commands.len() * 6 {
+            context.tick();
+        }
+        context.channel(0).unwrap().sound_control_flags()
+    };
+
+    assert_eq!(flags(&[0x1, 0x9, 0xD, 0xF]), [true; 4]);
+    assert_eq!(flags(&[0x1, 0x9, 0xD, 0xF, 0x0, 0x8, 0xC, 0xE]), [false; 4]);
+    // surround modes and unknown sub-commands don't toggle anything
+    assert_eq!(flags(&[0xA, 0xB, 0x5]), [false; 4]);
+}
+
+#[test]
+fn test_midi_macros() {
+    use effect::XmEffect;
+    use extension::{XmExtensionChunk, XmMacroCommand, XmMidiMacros};
+
+    let row = |note, effect| {
+        vec![pattern::XmPatternSlot::new(
+            note,
+            Some(1),
+            None,
+            Some(effect),
+        )]
+    };
+    let mut module = test_module(vec![
+        row(c4(), XmEffect::SetActiveMacro(1)),
+        row(note::XmNote::NoNote, XmEffect::MidiMacro(0x30)),
+        row(note::XmNote::NoNote, XmEffect::MidiMacro(0x81)),
+        row(note::XmNote::NoNote, XmEffect::SetActiveMacro(0)),
+        row(note::XmNote::NoNote, XmEffect::SmoothMidiMacro(0x10)),
+    ]);
+
+    // filter state after every row
+    let filters = |module: &XmModule| {
+        let mut context = context::XmPlaybackContext::new(module, 44100);
+        let mut filters = vec![];
+        for _ in 0..5 {
+            for _ in 0..6 {
+                context.tick();
+            }
+            filters.push(context.channel(0).unwrap().filter());
+        }
+        filters
+    };
+
+    // the effects are retained, but without macros they do nothing
+    let (_, parsed) = parse(&write::write(&module)).unwrap();
+    assert!(parsed.patterns[0].1 == module.patterns[0].1);
+    assert!(parsed.extensions.midi_macros().is_none());
+    assert!(filters(&parsed).iter().all(|e| *e == (None, None)));
+
+    let mut macros = XmMidiMacros {
+        global: vec![String::new(); 9],
+        parametered: vec![String::new(); 16],
+        fixed: vec![String::new(); 128],
+    };
+    macros.parametered[0] = "F0F000z".to_owned();
+    macros.parametered[1] = "F0F001z".to_owned();
+    macros.fixed[1] = "F0 F0 01 40".to_owned();
+    module.extensions.chunks = vec![XmExtensionChunk::MidiMacros(macros.clone())];
+
+    let written = write::write(&module);
+    let position = written.windows(4).position(|e| e == b"MIDI").unwrap();
+    assert_eq!(&written[position + 4..position + 8], &4896u32.to_le_bytes());
+
+    let (_, parsed) = parse(&written).unwrap();
+    assert_eq!(parsed.extensions.midi_macros(), Some(&macros));
+    assert_eq!(
+        filters(&parsed),
+        [
+            (None, None),
+            (None, Some(0x30)),
+            (None, Some(0x40)),
+            (None, Some(0x40)),
+            (Some(0x10), Some(0x40)),
+        ]
+    );
+
+    assert_eq!(macros.zxx_macro(1, 0x7F), Some("F0F001z"));
+    assert_eq!(macros.zxx_macro(0x10, 0x00), None);
+    assert_eq!(
+        XmMacroCommand::parse("F0F000z", 0x90),
+        Some(XmMacroCommand::FilterCutoff(0x7F))
+    );
+    assert_eq!(
+        XmMacroCommand::parse("f0 f0 00 2a", 0),
+        Some(XmMacroCommand::FilterCutoff(0x2A))
+    );
+    assert_eq!(XmMacroCommand::parse("F0F002z", 0x10), None);
+    assert_eq!(XmMacroCommand::parse("9n z 7F", 0x10), None);
+}
+
+#[test]
+fn test_parse_reader() {
+    let data = include_bytes!("test_xms/test_wo_mpt_ext.xm");
+    let (_, expected) = parse(data).unwrap();
+
+    let module = parse_reader(std::io::Cursor::new(data)).unwrap();
+    assert!(module == expected);
+
+    let path = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/src/test_xms/test_wo_mpt_ext.xm"
+    );
+    let module = parse_reader(std::fs::File::open(path).unwrap()).unwrap();
+    assert!(module == expected);
+
+    // the id text is checked first
+    let mut data = data.to_vec();
+    data[0] = b'X';
+    assert!(matches!(
+        parse_reader(data.as_slice()),
+        Err(XmError::Parse {
+            offset: 0,
+            kind: nom::error::ErrorKind::Verify
+        })
+    ));
+}
+
+#[test]
+fn test_bogus_counts_fail_fast() {
+    let written = write::write(&test_module(vec![vec![pattern::XmPatternSlot::default()]]));
+    // the header and the single pattern, holding one packed empty slot
+    let header = &written[..80 + 256 + 9 + 1];
+
+    let with_counts = |patterns_num: u16, instruments_num: u16| {
+        let mut data = [header, &[0; 40]].concat();
+        data[70..72].copy_from_slice(&patterns_num.to_le_bytes());
+        data[72..74].copy_from_slice(&instruments_num.to_le_bytes());
+        data
+    };
+
+    let lenient = ParseOptions { strict: false };
+    assert!(parse_with_options(&with_counts(60000, 1), lenient).is_err());
+
+    let eof = |data: &[u8]| {
+        matches!(
+            parse_with_options(data, lenient),
+            Err(nom::Err::Error(nom::error::Error {
+                code: nom::error::ErrorKind::Eof,
+                ..
+            }))
+        )
+    };
+    assert!(eof(&with_counts(256, 1)));
+    assert!(eof(&with_counts(1, 128)));
+
+    // a pattern claiming far more rows than there is data
+    let mut data = written.clone();
+    data[80 + 256 + 5..80 + 256 + 7].copy_from_slice(&u16::MAX.to_le_bytes());
+    assert!(eof(&data));
+}
+
+#[test]
+fn test_fuzz_regressions() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/fuzz/regressions");
+
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let data = std::fs::read(entry.unwrap().path()).unwrap();
+
+        assert!(parse(&data).is_err());
+        assert!(parse_with_options(&data, ParseOptions { strict: false }).is_err());
+    }
+}
+
+#[test]
+fn test_header_size_underflow() {
+    let mut data = include_bytes!("test_xms/test_wo_mpt_ext.xm").to_vec();
+    data[60..64].copy_from_slice(&4u32.to_le_bytes());
+
+    assert!(header::parse(&data, true).is_err());
+    assert!(parse(&data).is_err());
+
+    // an instrument header too short for the sample options it holds is skipped over as is
+    let mut module = test_module(vec![vec![pattern::XmPatternSlot::default()]]);
+    module.instruments[0].0.sample_opts = Some(test_sample_opts(None, None));
+
+    let mut data = write::write(&module);
+    let instrument_offset = 80 + 256 + 9 + 1;
+    data[instrument_offset..instrument_offset + 4].copy_from_slice(&4u32.to_le_bytes());
+
+    let (_, parsed) = parse(&data).unwrap();
+    assert_eq!(parsed.instruments[0].0.header_size, 4);
+    assert!(parsed.instruments[0].1 == module.instruments[0].1);
+}
+
+#[test]
+fn test_used_instruments() {
+    let slot = |instrument| pattern::XmPatternSlot::new(c4(), instrument, None, None);
+    let mut module = test_module(vec![
+        vec![slot(Some(1)), slot(None)],
+        vec![slot(Some(3)), slot(Some(9))],
+    ]);
+    let instrument = module.instruments[0].clone();
+    module.instruments = vec![instrument; 3];
+
+    // instrument 2 in the pattern data is index 1, and there's no instrument 9
+    assert_eq!(
+        module.used_instruments().into_iter().collect::<Vec<_>>(),
+        vec![0, 2]
+    );
+    assert_eq!(
+        module.unused_instruments().into_iter().collect::<Vec<_>>(),
+        vec![1]
+    );
+}
+
+#[test]
+fn test_used_samples() {
+    // the bundled module only has notes on the first 8 channels and effects on the next two,
+    // without ever referring to an instrument
+    let (_, module) = parse(include_bytes!("test_xms/test_w_mpt_ext.xm")).unwrap();
+    assert_eq!(
+        module.used_samples(),
+        vec![BTreeSet::new(), BTreeSet::new()]
+    );
+    assert_eq!(module.used_channels(), (0..10).collect());
+
+    let c5 = note::XmNote::Note {
+        tone: note::XmTone::C,
+        octave: 5,
+    };
+    let mut module = test_module(vec![
+        vec![pattern::XmPatternSlot::new(c4(), Some(1), None, None)],
+        vec![pattern::XmPatternSlot::new(c5, None, None, None)],
+    ]);
+    let mut opts = test_sample_opts(None, None);
+    opts.sample_keymap_assignments[60] = 2;
+    module.instruments[0].0.sample_opts = Some(opts);
+    module.instruments[0].1 = vec![test_sample(vec![0; 4]); 3];
+
+    // C-5 keeps playing instrument 1, and picks its third sample through the keymap
+    let used = module.used_samples();
+    assert_eq!(used[0].iter().copied().collect::<Vec<_>>(), vec![0, 2]);
+    assert_eq!(module.used_channels(), BTreeSet::from([0]));
+}
+
+#[test]
+fn test_keymap_sample_selection() {
+    let c5 = note::XmNote::Note {
+        tone: note::XmTone::C,
+        octave: 5,
+    };
+    let mut module = test_module(vec![
+        vec![pattern::XmPatternSlot::new(c4(), Some(1), None, None)],
+        vec![pattern::XmPatternSlot::new(
+            c5.clone(),
+            Some(1),
+            None,
+            Some(effect::XmEffect::SetVolume(0x08)),
+        )],
+        // no note, so the held C-5 picks the sample for the volume and panning
+        vec![pattern::XmPatternSlot::new(
+            note::XmNote::NoNote,
+            Some(1),
+            None,
+            None,
+        )],
+    ]);
+    // C-4 plays the silent first sample, C-5 the second one
+    let mut opts = test_sample_opts(None, None);
+    opts.sample_keymap_assignments[60] = 1;
+    module.instruments[0].0.sample_opts = Some(opts);
+    module.instruments[0].1 = vec![test_sample(vec![0; 8]), test_sample(vec![64; 8])];
+    module.instruments[0].1[0].0.volume = 0x10;
+    module.instruments[0].1[0].0.panning = 0x00;
+    module.instruments[0].1[1].0.volume = 0x30;
+    module.instruments[0].1[1].0.panning = 0xFF;
+    module.header.default_tempo = 1;
+
+    // every frame is a tick at 125 BPM
+    let mut context = context::XmPlaybackContext::new(&module, 50);
+    let mut levels = vec![];
+    let mut volumes = vec![];
+    for _ in 0..3 {
+        context.render(&mut [0.0; 2]);
+        levels.push(context.channel_levels()[0]);
+        volumes.push(context.channel(0).unwrap().volume());
+    }
+
+    assert_eq!(levels[0], 0.0);
+    assert!(levels[1] > 0.0);
+    assert_eq!(volumes, [(0.25, 0.0), (0.125, 1.0), (0.75, 1.0)]);
+}
+
+#[test]
+fn test_names() {
+    let (_, module) = parse(include_bytes!("test_xms/test_w_mpt_ext.xm")).unwrap();
+
+    // the second instrument has two samples
+    assert_eq!(module.instrument_names(), vec!["test123", ""]);
+    assert_eq!(module.sample_names(), vec!["", "untitled", ""]);
+}
+
+#[test]
+fn test_openmpt_extensions() {
+    let data = include_bytes!("test_xms/test_w_mpt_ext.xm");
+    let (rest, module) = parse(data).unwrap();
+    assert!(rest.is_empty());
+
+    let extensions = &module.extensions;
+    assert!(extensions.chunks.is_empty());
+    assert_eq!(extensions.instrument_properties, Some(vec![]));
+    assert_eq!(
+        extensions
+            .song_properties
+            .iter()
+            .flatten()
+            .map(|e| &e.code)
+            .collect::<Vec<_>>(),
+        [
+            b".BPR", b".MPR", b"..MT", b".MMP", b".VWC", b"VWSL", b".APS", b"VTSV", b".FSM",
+            b"AUTH", b"CCOL",
+        ]
+    );
+    assert_eq!(extensions.song_property(b"AUTH"), Some(&b"alex"[..]));
+    assert_eq!(extensions.song_property(b".BPR"), Some(&[4, 0, 0, 0][..]));
+
+    // written back as is
+    let position = data.windows(4).position(|e| e == b"XTPM").unwrap();
+    let written = write::write(&module);
+    assert!(written.ends_with(&data[position..]));
+    assert_eq!(parse(&written).unwrap().1, module);
+
+    let (_, module) = parse(include_bytes!("test_xms/test_wo_mpt_ext.xm")).unwrap();
+    assert!(module.extensions.is_empty());
+
+    // the chunks before the property blocks
+    let mut module = test_module(vec![vec![pattern::XmPatternSlot::default()]]);
+    module.extensions.chunks = vec![
+        extension::XmExtensionChunk::SongMessage("hello\rworld".to_owned()),
+        extension::XmExtensionChunk::PatternNames(vec!["intro".to_owned()]),
+        extension::XmExtensionChunk::ChannelNames(vec!["kick".to_owned(), "".to_owned()]),
+        extension::XmExtensionChunk::Unknown {
+            id: *b"MIDI",
+            data: vec![1, 2, 3],
+        },
+    ];
+    module.extensions.instrument_properties = Some(vec![extension::XmInstrumentProperty {
+        code: *b"..OF",
+        values: vec![vec![0x10, 0x00]],
+    }]);
+
+    let written = write::write(&module);
+    let (rest, parsed) = parse(&written).unwrap();
+    assert!(rest.is_empty());
+    assert_eq!(parsed.extensions, module.extensions);
+    assert_eq!(parsed.extensions.song_message(), Some("hello\rworld"));
+    assert_eq!(parsed.extensions.pattern_names(), ["intro"]);
+    assert_eq!(parsed.extensions.channel_names(), ["kick", ""]);
+    assert_eq!(parsed.extensions.song_property(b"AUTH"), None);
+
+    // anything else is left alone
+    let mut data = write::write(&test_module(vec![vec![pattern::XmPatternSlot::default()]]));
+    data.extend_from_slice(&[0xFF, 0x00]);
+    let (rest, parsed) = parse(&data).unwrap();
+    assert_eq!(rest, [0xFF, 0x00]);
+    assert!(parsed.extensions.is_empty());
+}
+
+#[test]
+fn test_parse_sample() {
+    let data = include_bytes!("test_xms/test_w_mpt_ext.xm");
+    let (_, module) = parse(data).unwrap();
+
+    // the bundled samples are all empty, so they don't consume anything
+    let (header, pcm) = &module.instruments[1].1[0];
+    let (rest, decoded) = instrument::parse_sample(header, &data[..]).unwrap();
+    assert_eq!(&decoded, pcm);
+    assert_eq!(rest.len(), data.len());
+
+    // 16 bit samples store their length in bytes
+    let (mut header, _) = test_sample(vec![]);
+    header.length = 6;
+    header.kind = header.kind.with_depth(instrument::XmSampleBitDepth::Bit16);
+    let pcm = instrument::XmSamplePcmData::Bit16Data(vec![1, -2, 300]);
+    let mut encoded = instrument::encode_dpcm_data(&pcm);
+    encoded.push(0xAA);
+
+    let (rest, decoded) = instrument::parse_sample(&header, &encoded).unwrap();
+    assert_eq!(decoded, pcm);
+    assert_eq!(rest, [0xAA]);
+
+    // not enough data
+    assert!(instrument::parse_sample(&header, &encoded[..3]).is_err());
+}
+
+#[test]
+fn test_cp437_names() {
+    let mut module = test_module(vec![vec![pattern::XmPatternSlot::default()]]);
+    module.instruments[0].0.sample_opts = Some(test_sample_opts(None, None));
+    module.instruments[0].1[0].0.name = "Stra?e".to_owned();
+
+    // DOS trackers stored ß as 0xE1, which isn't valid UTF-8
+    let mut data = write::write(&module);
+    let position = data.windows(6).position(|e| e == b"Stra?e").unwrap();
+    data[position + 4] = 0xE1;
+
+    let (_, parsed) = parse(&data).unwrap();
+    assert_eq!(parsed.instruments[0].1[0].0.name, "Straße");
+
+    // valid UTF-8 is kept as is
+    assert_eq!(decode_name("Straße\0\0".as_bytes()), "Straße");
+    assert_eq!(decode_name(&[0x80, 0xFF, 0x00]), "Ç\u{A0}");
+}
+
+#[cfg(feature = "wav")]
+#[test]
+fn test_render_to_wav() {
+    use export::wav::{render_to_wav, WavSampleFormat};
+
+    let (_, module) = parse(include_bytes!("test_xms/test_wo_mpt_ext.xm")).unwrap();
+    let frames = (module.duration(8000).as_secs_f64() * 8000.0).round() as usize;
+    assert!(frames > 0);
+
+    for (format, bytes_per_sample) in [(WavSampleFormat::Pcm16, 2), (WavSampleFormat::Float32, 4)] {
+        let mut out = vec![];
+        render_to_wav(&module, 8000, format, &mut out).unwrap();
+
+        let u16_at = |i: usize| u16::from_le_bytes([out[i], out[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes(out[i..i + 4].try_into().unwrap());
+
+        assert_eq!(&out[0..4], b"RIFF");
+        assert_eq!(u32_at(4) as usize, out.len() - 8);
+        assert_eq!(&out[8..16], b"WAVEfmt ");
+        assert_eq!(u16_at(22), 2);
+        assert_eq!(u32_at(24), 8000);
+        assert_eq!(u16_at(32), 2 * bytes_per_sample);
+        assert_eq!(&out[36..40], b"data");
+        assert_eq!(u32_at(40) as usize, frames * 2 * bytes_per_sample as usize);
+        assert_eq!(out.len(), 44 + u32_at(40) as usize);
+    }
+
+    // the data holds the song played once
+    let module = test_module(vec![vec![pattern::XmPatternSlot::new(
+        c4(),
+        Some(1),
+        None,
+        None,
+    )]]);
+    let config = context::XmPlaybackConfig {
+        looping: false,
+        ..Default::default()
+    };
+    let mut context = context::XmPlaybackContext::new_with_config(&module, 8000, config);
+    let frames = context
+        .frames()
+        .flat_map(|(l, r)| [l, r])
+        .collect::<Vec<_>>();
+    assert_eq!(frames.len(), 960 * 2);
+    assert!(frames.iter().any(|e| *e != 0.0));
+
+    let mut out = vec![];
+    render_to_wav(&module, 8000, WavSampleFormat::Float32, &mut out).unwrap();
+    let samples = out[44..]
+        .chunks_exact(4)
+        .map(|e| f32::from_le_bytes(e.try_into().unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(samples, frames);
+
+    let mut out = vec![];
+    render_to_wav(&module, 8000, WavSampleFormat::Pcm16, &mut out).unwrap();
+    let samples = out[44..]
+        .chunks_exact(2)
+        .map(|e| i16::from_le_bytes([e[0], e[1]]))
+        .collect::<Vec<_>>();
+    let expected = frames
+        .iter()
+        .map(|e| (e * i16::MAX as f32) as i16)
+        .collect::<Vec<_>>();
+    assert_eq!(samples, expected);
+
+    let error = render_to_wav(&module, 0, WavSampleFormat::Float32, vec![]).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+
+    // a pattern loop that restarts itself never ends the song, so it's cut off like `duration`
+    let with_loop = || {
+        vec![pattern::XmPatternSlot::new(
+            note::XmNote::NoNote,
+            None,
+            None,
+            Some(effect::XmEffect::PatternLoop(1)),
+        )]
+    };
+    let mut module = test_module(vec![with_loop(), with_loop()]);
+    module.header.default_tempo = 1;
+    module.header.default_bpm = 0xFF;
+    // at 1 Hz the duration is a whole number of frames
+    let frames = module.duration(1).as_secs() as usize;
+
+    let mut out = vec![];
+    render_to_wav(&module, 1, WavSampleFormat::Pcm16, &mut out).unwrap();
+    assert_eq!(out.len(), 44 + frames * 2 * 2);
+}
+
+#[cfg(feature = "wav")]
+#[test]
+fn test_sample_to_wav() {
+    use export::wav::XmSampleWavExt;
+
+    let mut sample = test_sample(vec![0, 64, -64, 127, -128]);
+    sample.0.loop_start = 1;
+    sample.0.loop_length = 3;
+    sample.0.relative_note_num = 12;
+
+    let mut out = vec![];
+    sample.to_wav(&mut out).unwrap();
+
+    let u16_at = |out: &[u8], i: usize| u16::from_le_bytes([out[i], out[i + 1]]);
+    let u32_at = |out: &[u8], i: usize| u32::from_le_bytes(out[i..i + 4].try_into().unwrap());
+
+    assert_eq!(&out[0..4], b"RIFF");
+    assert_eq!(u32_at(&out, 4) as usize, out.len() - 8);
+    assert_eq!(&out[8..16], b"WAVEfmt ");
+    assert_eq!(u16_at(&out, 22), 1);
+    // an octave above C-4
+    assert_eq!(u32_at(&out, 24), 16726);
+    assert_eq!(u16_at(&out, 34), 8);
+    assert_eq!(&out[36..40], b"data");
+    assert_eq!(u32_at(&out, 40), 5);
+    assert_eq!(&out[44..49], [128, 192, 64, 255, 0]);
+
+    // padded to an even size
+    assert_eq!(&out[50..54], b"smpl");
+    assert_eq!(u32_at(&out, 50 + 8 + 28), 1);
+    assert_eq!(u32_at(&out, 50 + 8 + 36 + 4), 0);
+    assert_eq!(u32_at(&out, 50 + 8 + 36 + 8), 1);
+    assert_eq!(u32_at(&out, 50 + 8 + 36 + 12), 3);
+    assert_eq!(out.len(), 50 + 8 + 36 + 24);
+
+    // 16 bit samples count the loop in bytes, non-looping ones have no `smpl` chunk
+    sample.1 = instrument::XmSamplePcmData::Bit16Data(vec![1, -1]);
+    sample.0.kind = instrument::XmSampleType::new().with_depth(instrument::XmSampleBitDepth::Bit16);
+    sample.0.relative_note_num = 0;
+
+    out.clear();
+    sample.to_wav(&mut out).unwrap();
+
+    assert_eq!(u32_at(&out, 24), 8363);
+    assert_eq!(u16_at(&out, 34), 16);
+    assert_eq!(u32_at(&out, 40), 4);
+    assert_eq!(out.len(), 48);
+}
+
+#[test]
+fn test_midi_export() {
+    let module = test_module(vec![
+        vec![pattern::XmPatternSlot::new(
+            c4(),
+            Some(1),
+            Some(effect::XmVolumeColumn(0x30)),
+            None,
+        )],
+        vec![pattern::XmPatternSlot::new(
+            note::XmNote::NoteOff,
+            None,
+            None,
+            None,
+        )],
+    ]);
+
+    let out = export::midi::to_midi(&module);
+
+    assert_eq!(&out[0..4], b"MThd");
+    // format 1, the tempo track and one track for the only channel, 24 ticks per quarter
+    assert_eq!(&out[8..14], &[0, 1, 0, 2, 0, 24]);
+    assert_eq!(&out[14..18], b"MTrk");
+    // 125 BPM
+    assert_eq!(
+        &out[22..30],
+        &[0x00, 0xFF, 0x51, 0x03, 0x07, 0x53, 0x00, 0x00]
+    );
+
+    // the note starts right away at half volume and stops a row, or 6 ticks, later
+    let note_track = &out[22 + 11 + 8..];
+    assert_eq!(&note_track[..8], &[0x00, 0x90, 60, 64, 0x06, 0x80, 60, 0]);
+
+    let (_, module) = parse(include_bytes!("test_xms/test_wo_mpt_ext.xm")).unwrap();
+    let out = export::midi::to_midi(&module);
+    let tracks = out.windows(4).filter(|e| e == b"MTrk").count();
+    assert_eq!(tracks, (module.header.channels_num as usize).min(15) + 1);
+
+    // every channel gets a MIDI channel of its own, skipping the percussion one, and the ones
+    // past the 15th are left out
+    let c4_slot = pattern::XmPatternSlot::new(c4(), Some(1), None, None);
+    let module = test_module(vec![vec![c4_slot; 17]]);
+    let out = export::midi::to_midi(&module);
+    assert_eq!(&out[10..12], &[0, 16]);
+    let note_ons = out
+        .windows(2)
+        .filter(|e| e[0] & 0xF0 == 0x90 && e[1] == 60)
+        .map(|e| e[0] & 0x0F)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        note_ons,
+        [0, 1, 2, 3, 4, 5, 6, 7, 8, 10, 11, 12, 13, 14, 15]
+    );
+
+    let mut encoded = vec![];
+    export::midi::write_variable_length(&mut encoded, 0x7F);
+    export::midi::write_variable_length(&mut encoded, 0x80);
+    export::midi::write_variable_length(&mut encoded, 0x0FFF_FFFF);
+    assert_eq!(encoded, vec![0x7F, 0x81, 0x00, 0xFF, 0xFF, 0xFF, 0x7F]);
+}
+
+#[test]
+fn test_mod_import() {
+    let sine = (0..32)
+        .map(|e| ((e as f32 / 32.0 * std::f32::consts::TAU).sin() * 127.0) as i8)
+        .collect::<Vec<_>>();
+
+    let mut data = b"sine song".to_vec();
+    data.resize(20, 0);
+
+    // the only sample, 16 words long and looping all of it, with a finetune of -1
+    data.extend_from_slice(b"sine");
+    data.resize(20 + 22, 0);
+    data.extend_from_slice(&[0, 16, 0x0F, 64, 0, 0, 0, 16]);
+    data.resize(20 + 31 * 30, 0);
+
+    // song length, restart position, order table and format tag
+    data.extend_from_slice(&[1, 127]);
+    data.resize(data.len() + 128, 0);
+    data.extend_from_slice(b"M.K.");
+
+    // C-2 on the first channel with the sample and C20, every other slot is empty
+    let mut pattern = vec![0; 64 * 4 * 4];
+    pattern[..4].copy_from_slice(&[0x01, 0xAC, 0x1C, 0x20]);
+    // ProTracker's A-3 on the second channel
+    pattern[4..8].copy_from_slice(&[0x00, 0x7F, 0x00, 0x00]);
+    data.extend_from_slice(&pattern);
+    data.extend(sine.iter().map(|e| *e as u8));
+
+    let (_, module) = import::protracker::from_mod(&data).unwrap();
+
+    assert_eq!(module.header.module_name, "sine song");
+    assert_eq!(module.header.channels_num, 4);
+    assert!(module.header.is_amiga);
+    assert_eq!(module.pattern_order_table, vec![0]);
+    assert_eq!(module.instruments.len(), 31);
+
+    let (header, samples) = &module.instruments[0];
+    assert_eq!(header.name, "sine");
+    assert_eq!(samples[0].0.length, 32);
+    assert_eq!(samples[0].0.loop_length, 32);
+    assert_eq!(samples[0].0.finetune, -16);
+    assert!(samples[0].1 == instrument::XmSamplePcmData::Bit8Data(sine));
+
+    let row = &module.patterns[0].1 .0[0].0;
+    let set_volume = Some(effect::XmEffect::SetVolume(0x20));
+    assert!(row[0] == pattern::XmPatternSlot::new(c4(), Some(1), None, set_volume));
+    assert_eq!(row[1].note.to_string(), "A-5");
+    assert!(row[2] == pattern::XmPatternSlot::default());
+
+    // it's a regular module from here on
+    let (_, parsed) = parse(&write::write(&module)).unwrap();
+    assert!(parsed == module);
+
+    let mut context = context::XmPlaybackContext::new(&module, 44100);
+    let mut out = vec![0.0; 512];
+    context.render(&mut out);
+    assert!(out.iter().any(|e| *e != 0.0));
+}
+
+#[test]
+fn test_dump_patterns() {
+    let (_, module) = parse(include_bytes!("test_xms/test_wo_mpt_ext.xm")).unwrap();
+    let dump = module.dump_patterns();
+    let empty = "|...........".repeat(6);
+
+    let expected = [
+        "Order 00, pattern 00".to_owned(),
+        format!(
+            "   {}|",
+            (1..=16)
+                .map(|e| format!("|{:<11}", format!("Ch {}", e)))
+                .collect::<String>()
+        ),
+        format!(
+            "00 |C-0........|C-1........|C-2........|C-3........|C-4........|C-5........\
+             |C-6........|C-7........|.....a01...|........001{}|",
+            empty
+        ),
+        format!(
+            "01 |C#0........|C#1........|C#2........|C#3........|C#4........|C#5........\
+             |C#6........|C#7........|.....b02...|........100{}|",
+            empty
+        ),
+    ];
+
+    assert_eq!(dump.lines().take(4).collect::<Vec<_>>(), expected);
+    assert_eq!(dump.lines().count(), 2 + module.patterns[0].1 .0.len() + 1);
+}
+
+#[test]
+fn test_debug_formatting() {
+    let (_, module) = parse(include_bytes!("test_xms/test_wo_mpt_ext.xm")).unwrap();
+    let slot = &module.patterns[0].1 .0[0].0[0];
+
+    assert_eq!(
+        format!("{:?}", slot),
+        "XmPatternSlot { note: Note { tone: C, octave: 0 }, instrument_index: None, \
+         volume_column: None, effect: None }"
+    );
+
+    let mut context = context::XmPlaybackContext::new(&module, 44100);
+    context.render(&mut [0.0; 2]);
+
+    let debug = format!("{:?}", context);
+    assert!(debug.starts_with("XmPlaybackContext { sample_rate: 44100"));
+    assert!(debug.ends_with(".. }"));
+}
+
+#[test]
+fn test_module_equality() {
+    let data = include_bytes!("test_xms/test_wo_mpt_ext.xm");
+    let (_, module) = parse(data).unwrap();
+
+    assert_eq!(module, parse(data).unwrap().1);
+
+    let mut changed = module.clone();
+    changed.patterns[0].1 .0[0].0[0].note = note::XmNote::NoteOff;
+    assert_ne!(module, changed);
+}
+
+#[test]
+fn test_empty_pattern_data() {
+    // 2 rows without any data
+    let data = [9, 0, 0, 0, 0, 2, 0, 0, 0, 0xAA];
+    let (rest, (header, rows)) = pattern::parse(3, 0x0104, true)(&data).unwrap();
+    assert_eq!(rest, [0xAA]);
+    assert_eq!(header.rows_num, 2);
+    assert_eq!(
+        rows.0,
+        vec![pattern::XmPatternRow(vec![pattern::XmPatternSlot::default(); 3]); 2]
+    );
+
+    // 0 rows load as a 64 row pattern
+    let data = [9, 0, 0, 0, 0, 0, 0, 0, 0];
+    let (_, (header, rows)) = pattern::parse(3, 0x0104, true)(&data).unwrap();
+    assert_eq!(header.rows_num, 64);
+    assert_eq!(rows.0.len(), 64);
+
+    // the patterns after an empty one are still read from the right place
+    let mut module = test_module(vec![vec![pattern::XmPatternSlot::default()]; 4]);
+    let second_pattern = test_module(vec![vec![pattern::XmPatternSlot::new(
+        c4(),
+        Some(1),
+        None,
+        None,
+    )]])
+    .patterns
+    .remove(0);
+    module.patterns.push(second_pattern);
+    module.header.patterns_num = 2;
+
+    let mut data = write::write(&module);
+    let position = 60 + u32::from_le_bytes(data[60..64].try_into().unwrap()) as usize;
+    assert_eq!(&data[position + 7..position + 9], [4, 0]);
+    data[position + 7] = 0;
+    data.drain(position + 9..position + 13);
+
+    let (_, parsed) = parse(&data).unwrap();
+    assert_eq!(parsed.patterns[0].1, module.patterns[0].1);
+    assert_eq!(parsed.patterns[1].1, module.patterns[1].1);
+}
+
+#[test]
+fn test_header_extra_bytes() {
+    let mut module = test_module(vec![vec![pattern::XmPatternSlot::default()]]);
+    module.instruments[0].0.sample_opts = Some(test_sample_opts(None, None));
+
+    let mut data = write::write(&module);
+    let (_, parsed) = parse(&data).unwrap();
+    assert!(parsed.header.extra.is_empty());
+    assert!(parsed.patterns[0].0.extra.is_empty());
+    assert!(parsed.instruments[0].0.extra.is_empty());
+
+    // grow the instrument header by 4 bytes, they sit right before the sample headers
+    let position = data.len() - 40 - 8 - instrument::XM_INSTRUMENT_HEADER_SIZE_W_OPTS;
+    let header_size = instrument::XM_INSTRUMENT_HEADER_SIZE_W_OPTS as u32 + 4;
+    data[position..position + 4].copy_from_slice(&header_size.to_le_bytes());
+    let end = position + instrument::XM_INSTRUMENT_HEADER_SIZE_W_OPTS;
+    data.splice(end..end, [1, 2, 3, 4]);
+
+    let (_, parsed) = parse(&data).unwrap();
+    assert_eq!(parsed.instruments[0].0.header_size, header_size);
+    assert_eq!(parsed.instruments[0].0.extra, [1, 2, 3, 4]);
+    assert_eq!(parsed.instruments[0].1, module.instruments[0].1);
+
+    // all of them are written back
+    let mut module = parsed;
+    module.header.extra = vec![5, 6];
+    module.patterns[0].0.extra = vec![7];
+
+    let (_, parsed) = parse(&write::write(&module)).unwrap();
+    assert_eq!(parsed.header.extra, [5, 6]);
+    assert_eq!(parsed.patterns[0].0.extra, [7]);
+    assert_eq!(parsed.instruments[0].0.extra, [1, 2, 3, 4]);
+    assert_eq!(parsed.patterns[0].1, module.patterns[0].1);
+}
+
+#[test]
+fn test_pattern_packing() {
+    let slots = vec![
+        // empty, 1 byte
+        pattern::XmPatternSlot::default(),
+        // every field, 5 bytes unpacked
+        pattern::XmPatternSlot::new(
+            c4(),
+            Some(1),
+            Some(effect::XmVolumeColumn(0x40)),
+            Some(effect::XmEffect::VolumeSlide(effect::DoubleU4::from_bits(
+                0x0F,
+            ))),
+        ),
+        // every field with a zero effect parameter, 5 bytes packed
+        pattern::XmPatternSlot::new(
+            c4(),
+            Some(1),
+            Some(effect::XmVolumeColumn(0x40)),
+            Some(effect::XmEffect::PortamentoUp(0)),
+        ),
+        // arpeggio 00 is still an effect, 2 bytes
+        pattern::XmPatternSlot::new(
+            note::XmNote::NoNote,
+            None,
+            None,
+            Some(effect::XmEffect::Arpeggio(effect::DoubleU4::from_bits(0))),
+        ),
+        // 2 bytes
+        pattern::XmPatternSlot::new(note::XmNote::NoteOff, None, None, None),
+    ];
+    let module = test_module(vec![slots]);
+
+    assert_eq!(
+        write::pack_rows(&module.patterns[0].1).len(),
+        1 + 5 + 5 + 2 + 2
+    );
+
+    let (_, parsed) = parse(&write::write(&module)).unwrap();
+
+    assert_eq!(parsed.patterns[0].0.packed_data_size, 15);
+    assert!(parsed.patterns[0].1 == module.patterns[0].1);
+}
+
+#[test]
+fn test_dpcm_round_trip() {
+    let sine = (0..256).map(|e| (e as f32 / 256.0 * std::f32::consts::TAU).sin());
+
+    // the jumps between the extremes only fit into the deltas with wrapping arithmetic
+    let mut bit8 = sine.clone().map(|e| (e * 127.0) as i8).collect::<Vec<_>>();
+    bit8.extend([i8::MAX, i8::MIN, i8::MAX]);
+    let mut bit16 = sine.map(|e| (e * 32767.0) as i16).collect::<Vec<_>>();
+    bit16.extend([i16::MAX, i16::MIN, i16::MAX]);
+
+    let bit8 = instrument::XmSamplePcmData::Bit8Data(bit8);
+    let bit16 = instrument::XmSamplePcmData::Bit16Data(bit16);
+
+    for (data, depth) in [
+        (bit8, instrument::XmSampleBitDepth::Bit8),
+        (bit16, instrument::XmSampleBitDepth::Bit16),
+    ] {
+        let encoded = instrument::encode_dpcm_data(&data);
+        let (_, decoded) = instrument::decode_dpcm_data(encoded.len(), depth)(&encoded).unwrap();
+
+        assert!(decoded == data);
+        assert!(instrument::encode_dpcm_data(&decoded) == encoded);
+    }
+}
+
+#[test]
+fn test_dpcm_decode_matches_reference() {
+    // the previous implementation, which collected the deltas before summing them
+    fn reference(data: &[u8], length: usize, bit16: bool) -> (usize, instrument::XmSamplePcmData) {
+        if bit16 {
+            let (input, deltas) =
+                nom::multi::count(nom::number::complete::le_i16::<_, ()>, length / 2)(data)
+                    .unwrap();
+            let mut previous = 0i16;
+            let mut out = vec![];
+            for delta in deltas {
+                previous = delta.wrapping_add(previous);
+                out.push(previous);
+            }
+            (input.len(), instrument::XmSamplePcmData::Bit16Data(out))
+        } else {
+            let (input, deltas) =
+                nom::multi::count(nom::number::complete::i8::<_, ()>, length)(data).unwrap();
+            let mut previous = 0i8;
+            let mut out = vec![];
+            for delta in deltas {
+                previous = delta.wrapping_add(previous);
+                out.push(previous);
+            }
+            (input.len(), instrument::XmSamplePcmData::Bit8Data(out))
+        }
+    }
+
+    let data = (0..4099u32)
+        .map(|e| (e.wrapping_mul(2654435761) >> 13) as u8)
+        .collect::<Vec<_>>();
+
+    for length in [0, 1, 2, 3, 255, 4096, 4097] {
+        for (bit16, depth) in [
+            (false, instrument::XmSampleBitDepth::Bit8),
+            (true, instrument::XmSampleBitDepth::Bit16),
+        ] {
+            let (input, decoded) = instrument::decode_dpcm_data(length, depth)(&data).unwrap();
+
+            assert!((input.len(), decoded) == reference(&data, length, bit16));
+        }
+    }
+
+    let mut truncated = instrument::decode_dpcm_data(8, instrument::XmSampleBitDepth::Bit16);
+    assert!(truncated(&data[..7]).is_err());
+    let mut unknown = instrument::decode_dpcm_data(1, instrument::XmSampleBitDepth::Unknown);
+    assert!(unknown(&data).is_err());
+}
+
+#[test]
+fn test_slot_accessors() {
+    let slot = pattern::XmPatternSlot::new(
+        c4(),
+        Some(3),
+        Some(effect::XmVolumeColumn(0x30)),
+        Some(effect::XmEffect::SetTempo(0x7D)),
+    );
+
+    assert!(*slot.note() == c4());
+    assert_eq!(slot.instrument_index(), Some(3));
+    assert_eq!(slot.volume_column().map(|e| e.argument()), Some(0x20));
+    assert!(matches!(
+        slot.effect(),
+        Some(effect::XmEffect::SetTempo(0x7D))
+    ));
+
+    let empty = pattern::XmPatternSlot::default();
+
+    assert!(*empty.note() == note::XmNote::NoNote);
+    assert_eq!(empty.instrument_index(), None);
+    assert!(empty.volume_column().is_none());
+    assert!(empty.effect().is_none());
+}
+
+#[test]
+fn test_ordered_patterns() {
+    let mut module = test_module(vec![vec![pattern::XmPatternSlot::default()]]);
+    let mut second = module.patterns[0].clone();
+    second.1 .0[0].0[0] = pattern::XmPatternSlot::new(c4(), None, None, None);
+    module.patterns.push(second);
+    module.pattern_order_table = vec![
+        1,
+        pattern::XM_ORDER_SKIP_MARKER,
+        0,
+        1,
+        pattern::XM_ORDER_END_MARKER,
+        0,
+    ];
+    module.header.song_length = module.pattern_order_table.len() as u16;
+
+    let notes = module
+        .ordered_patterns()
+        .flat_map(|(_, rows)| rows)
+        .flatten()
+        .map(|slot| slot.note().clone())
+        .collect::<Vec<_>>();
+
+    assert!(notes == vec![c4(), note::XmNote::NoNote, c4()]);
+}
+
+#[test]
+fn test_note_period() {
+    use frequency::{note_period, Linear};
+
+    // C-4 is period 4608, a finetune of 128 would be a whole semitone (64 units)
+    assert_eq!(note_period::<Linear>(&c4(), 0, 0), Some(4608.0));
+    assert_eq!(note_period::<Linear>(&c4(), 64, 0), Some(4576.0));
+    assert_eq!(note_period::<Linear>(&c4(), -128, 0), Some(4672.0));
+    assert_eq!(note_period::<Linear>(&c4(), 16, -12), Some(5368.0));
+
+    let b7 = note::XmNote::Note {
+        tone: note::XmTone::B,
+        octave: 7,
+    };
+    assert_eq!(
+        note_period::<Linear>(&b7, 32, 0),
+        Some(7680.0 - 95.0 * 64.0 - 16.0)
+    );
+    assert_eq!(note_period::<Linear>(&b7, 0, 1), None);
+
+    assert_eq!(note_period::<Linear>(&note::XmNote::NoNote, 0, 0), None);
+    assert_eq!(note_period::<Linear>(&note::XmNote::NoteOff, 16, 0), None);
+}
+
+#[test]
+fn test_period_to_note() {
+    use frequency::{Amiga, FrequencyCalculator, FrequencyTable, Linear};
+    use note::XmTone;
+
+    for (tone, octave) in [
+        (XmTone::C, 0),
+        (XmTone::FS, 2),
+        (XmTone::C, 4),
+        (XmTone::A, 4),
+        (XmTone::DS, 6),
+        (XmTone::B, 7),
+    ] {
+        for table in [FrequencyTable::Linear, FrequencyTable::Amiga] {
+            let period = table.period(&tone, octave);
+            assert_eq!(table.period_to_note(period), (tone.clone(), octave));
+            // slides in between land on the closest note
+            let slid = period + (table.period(&tone, octave + 1) - period) * 0.04;
+            assert_eq!(table.period_to_note(slid), (tone.clone(), octave));
+        }
+    }
+
+    assert_eq!(Linear::period_to_note(4608.0 - 40.0), (XmTone::CS, 4));
+    assert_eq!(Amiga::period_to_note(1712.0 * 1.05), (XmTone::B, 3));
+
+    // past either end
+    assert_eq!(Linear::period_to_note(100000.0), (XmTone::C, 0));
+    assert_eq!(Linear::period_to_note(-100.0), (XmTone::B, 7));
+    assert_eq!(Amiga::period_to_note(100000.0), (XmTone::C, 0));
+    assert_eq!(Amiga::period_to_note(0.0), (XmTone::B, 7));
+}
+
+#[test]
+fn test_midi_note_round_trip() {
+    assert_eq!(c4().to_midi(), Some(60));
+    assert!(note::XmNote::from_midi(60) == c4());
+
+    for octave in 0..=note::XM_MAX_OCTAVE {
+        for index in 0..note::XM_TONE_COUNT {
+            let note = note::XmNote::Note {
+                tone: note::XmTone::from_index(index).unwrap(),
+                octave,
+            };
+            let midi = note.to_midi().unwrap();
+
+            assert_eq!(midi, 12 + octave * 12 + index);
+            assert!(note::XmNote::from_midi(midi) == note);
+        }
+    }
+
+    assert_eq!(note::XmNote::from_midi(0).to_midi(), Some(12));
+    assert_eq!(note::XmNote::from_midi(127).to_midi(), Some(107));
+    assert_eq!(note::XmNote::NoNote.to_midi(), None);
+    assert_eq!(note::XmNote::NoteOff.to_midi(), None);
+}
+
+#[test]
+fn test_note_display_width() {
+    let notes = (0..=note::XM_MAX_OCTAVE)
+        .flat_map(|octave| {
+            (0..note::XM_TONE_COUNT).map(move |index| note::XmNote::Note {
+                tone: note::XmTone::from_index(index).unwrap(),
+                octave,
+            })
+        })
+        .chain([note::XmNote::NoNote, note::XmNote::NoteOff]);
+
+    for note in notes {
+        assert_eq!(note.to_string().chars().count(), 3, "{}", note);
+    }
+
+    let row = |note| {
+        pattern::XmPatternRow(vec![pattern::XmPatternSlot::new(note, None, None, None)]).to_string()
+    };
+
+    assert_eq!(row(note::XmNote::NoteOff), "|^^^........|");
+    assert_eq!(row(note::XmNote::NoteOff).len(), row(c4()).len());
+
+    // unknown effects take up as much room as the others
+    let effect_row = |effect| {
+        let slot = pattern::XmPatternSlot::new(c4(), None, None, Some(effect));
+        pattern::XmPatternRow(vec![slot]).to_string()
+    };
+    let unknown = effect::XmEffect::Unknown {
+        command: 0x25,
+        parameter: 0x12,
+    };
+    assert_eq!(effect_row(unknown.clone()), "|C-4.....?12|");
+    assert_eq!(
+        effect_row(unknown).len(),
+        effect_row(effect::XmEffect::SetTempo(6)).len()
+    );
+}
+
+#[test]
+fn test_vibrato_type_bytes() {
+    use instrument::XmVibratoType;
+
+    let kinds = [
+        (0, XmVibratoType::Sine, 1.0),
+        (1, XmVibratoType::Square, 1.0),
+        (2, XmVibratoType::RampDown, 0.5),
+        (3, XmVibratoType::RampUp, -0.5),
+    ];
+
+    for (byte, kind, quarter) in kinds {
+        assert!(XmVibratoType::from_bits(byte) == Some(kind.clone()));
+        assert_eq!(kind.clone() as u8, byte);
+        // the waveforms after a quarter of their cycle
+        assert!((context::waveform(&kind, 64) - quarter).abs() < 1e-6);
+    }
+
+    assert!(XmVibratoType::from_bits(4).is_none());
+}
+
+#[test]
+fn test_default_instrument() {
+    use instrument::{XmEnvelope, XmInstrumentHeader, XmSampleHeader, XmSamplePcmData};
+
+    let data = vec![0, 64, 127, 64, 0, -64, -127, -64];
+    let mut header = XmInstrumentHeader::new("lead".to_owned(), 1);
+    header.sample_opts.as_mut().unwrap().volume_envelope =
+        Some(XmEnvelope::new(&[(0, 64), (16, 32)]));
+    let sample = XmSampleHeader {
+        length: data.len() as u32,
+        loop_length: data.len() as u32,
+        kind: instrument::XmSampleType::new()
+            .with_loop_type(instrument::XmSampleLoopType::ForwardLoop),
+        ..Default::default()
+    };
+
+    let slot = pattern::XmPatternSlot::new(c4(), Some(1), None, None);
+    let mut module = test_module(vec![vec![slot]]);
+    module.instruments = vec![(header, vec![(sample, XmSamplePcmData::Bit8Data(data))])];
+
+    let written = write::write(&module);
+    let (rest, parsed) = parse(&written).unwrap();
+    assert!(rest.is_empty());
+    assert_eq!(parsed.instruments, module.instruments);
+
+    let mut context = context::XmPlaybackContext::new(&parsed, 44100);
+    let mut out = vec![0.0; 2 * 1024];
+    context.render(&mut out);
+    assert!(out.iter().any(|e| *e != 0.0));
+
+    // without samples there are no sample options to store
+    let empty = XmInstrumentHeader::new(String::new(), 0);
+    assert_eq!(empty, XmInstrumentHeader::default());
+    assert_eq!(empty.header_size, 29);
+    assert_eq!(
+        XmInstrumentHeader::new(String::new(), 1).header_size,
+        instrument::XM_INSTRUMENT_HEADER_SIZE_W_OPTS as u32
+    );
+    assert_eq!(XmEnvelope::new(&[]), XmEnvelope::default());
+}
+
+#[test]
+fn test_over_length_loop_is_clamped() {
+    let mut module = test_module(vec![
+        vec![pattern::XmPatternSlot::new(c4(), Some(1), None, None)],
+        vec![pattern::XmPatternSlot::default()],
+    ]);
+    module.instruments[0].0.sample_opts = Some(test_sample_opts(None, None));
+    module.instruments[0].1[0].0.loop_start = 4;
+    module.instruments[0].1[0].0.loop_length = 100;
+
+    let (_, parsed) = parse(&write::write(&module)).unwrap();
+    let header = &parsed.instruments[0].1[0].0;
+
+    assert_eq!((header.loop_start, header.loop_length), (4, 4));
+    assert!(header.is_looping());
+
+    let mut context = context::XmPlaybackContext::new(&parsed, 44100);
+    let mut out = vec![0.0; 2];
+    for _ in 0..4096 {
+        context.render(&mut out);
+        assert!(context.channel(0).unwrap().sample_position().unwrap() < 8.0);
+    }
+
+    module.instruments[0].1[0].0.loop_start = 50;
+    let (_, parsed) = parse(&write::write(&module)).unwrap();
+    let header = &parsed.instruments[0].1[0].0;
+
+    assert_eq!((header.loop_start, header.loop_length), (8, 0));
+    assert!(!header.is_looping());
+}
+
+#[test]
+fn test_forward_loop_interpolation_wraps() {
+    use instrument::{XmResamplingType, XmSamplePcmData};
+
+    // the loop covers the last 4 samples
+    let data = XmSamplePcmData::Bit8Data(vec![100, 0, 32, 64, 96]);
+    let looped = |pos, resampling| data.get_interpolated_looped(pos, 1..5, resampling).unwrap();
+    let get = |pos| data.get(pos).unwrap();
+
+    // the last sample blends into the first one of the loop, not into itself
+    let linear = looped(4.5, XmResamplingType::LinearInterpolation);
+    assert!((linear - (get(4) + get(1)) / 2.0).abs() < 1e-6);
+    assert_eq!(looped(4.0, XmResamplingType::LinearInterpolation), get(4));
+    assert_eq!(
+        looped(2.5, XmResamplingType::LinearInterpolation),
+        (get(2) + get(3)) / 2.0
+    );
+
+    let cubic = looped(3.5, XmResamplingType::CubicInterpolation);
+    let points = [get(2), get(3), get(4), get(1)];
+    let expected = interpolation::CubicInterpolation::interpolate(points, 0.5);
+    assert!((cubic - expected).abs() < 1e-6);
+
+    // the first sample of the loop follows its last one
+    let cubic = looped(1.5, XmResamplingType::CubicInterpolation);
+    let points = [get(4), get(1), get(2), get(3)];
+    let expected = interpolation::CubicInterpolation::interpolate(points, 0.5);
+    assert!((cubic - expected).abs() < 1e-6);
+
+    // 4 samples played far faster than their rate, for many frames
+    let mut rows = vec![vec![pattern::XmPatternSlot::default()]; 16];
+    rows[0][0] = pattern::XmPatternSlot::new(
+        note::XmNote::Note {
+            tone: note::XmTone::C,
+            octave: 7,
+        },
+        Some(1),
+        None,
+        None,
+    );
+    let mut module = test_module(rows);
+    module.instruments[0].1[0] = test_sample(vec![-128, -40, 40, 127]);
+
+    for resampling in [
+        XmResamplingType::NoInterpolation,
+        XmResamplingType::LinearInterpolation,
+        XmResamplingType::CubicInterpolation,
+    ] {
+        let config = context::XmPlaybackConfig {
+            resampling,
+            ..Default::default()
+        };
+        let mut context = context::XmPlaybackContext::new_with_config(&module, 8000, config);
+        let mut out = vec![0.0; 2];
+
+        for _ in 0..8192 {
+            context.render(&mut out);
+
+            let position = context.channel(0).unwrap().sample_position().unwrap();
+            assert!((0.0..4.0).contains(&position), "{}", position);
+            assert!(out.iter().all(|e| e.abs() <= 1.0));
+        }
+    }
+}
+
+#[test]
+fn test_bidirectional_loop_stays_in_bounds() {
+    // long enough for the note not to be retriggered while rendering
+    let mut rows = vec![vec![pattern::XmPatternSlot::default()]; 16];
+    rows[0][0] = pattern::XmPatternSlot::new(
+        note::XmNote::Note {
+            tone: note::XmTone::C,
+            octave: 6,
+        },
+        Some(1),
+        None,
+        None,
+    );
+    let mut module = test_module(rows);
+    let (header, data) = &mut module.instruments[0].1[0];
+    header.kind = header
+        .kind
+        .with_loop_type(instrument::XmSampleLoopType::BidirectionalLoop);
+    header.loop_start = 4;
+    header.loop_length = 6;
+    // the loop covers samples 2..5 of a 16-bit sample, everything else is silent
+    *data = instrument::XmSamplePcmData::Bit16Data(vec![0, 0, 100, 200, 300, 0, 0, 0]);
+
+    let mut context = context::XmPlaybackContext::new(&module, 8000);
+    let mut out = vec![0.0; 2];
+    let mut previous = 0.0;
+    let mut turns = 0;
+
+    for _ in 0..8192 {
+        context.render(&mut out);
+
+        let position = context.channel(0).unwrap().sample_position().unwrap();
+        assert!(position <= 5.0, "{}", position);
+
+        // once inside of the loop, the ping-pong never leaves it
+        if previous >= 2.0 {
+            assert!(position >= 2.0, "{}", position);
+            assert!(out[0] != 0.0);
+        }
+
+        turns += (position < previous) as u32;
+        previous = position;
+    }
+
+    assert!(turns > 0);
+}
+
+#[test]
+fn test_sample_shorter_than_header() {
+    let mut rows = vec![vec![pattern::XmPatternSlot::default()]; 16];
+    rows[0][0] = pattern::XmPatternSlot::new(c4(), Some(1), None, None);
+    let mut module = test_module(rows);
+
+    // the loop lies entirely past the end of the decoded data
+    let header = &mut module.instruments[0].1[0].0;
+    header.length = 100;
+    header.loop_start = 50;
+    header.loop_length = 40;
+
+    let mut context = context::XmPlaybackContext::new(&module, 8000);
+    let mut out = vec![0.0; 2];
+    context.render(&mut out);
+    assert!(context.channel(0).unwrap().sample_position().is_some());
+
+    // the sample plays to the end of its data once and then frees the channel
+    let mut out = vec![0.0; 2 * 1024];
+    context.render(&mut out);
+    assert!(context.channel(0).unwrap().sample_position().is_none());
+
+    // a loop which only partially exists is cut off at the end of the data
+    let header = &mut module.instruments[0].1[0].0;
+    header.loop_start = 4;
+    header.loop_length = 40;
+
+    let mut context = context::XmPlaybackContext::new(&module, 8000);
+    let mut out = vec![0.0; 2];
+    for _ in 0..4096 {
+        context.render(&mut out);
+        assert!(context.channel(0).unwrap().sample_position().unwrap() < 8.0);
+    }
+
+    // no data at all
+    module.instruments[0].1[0].1 = instrument::XmSamplePcmData::Bit16Data(vec![]);
+
+    let mut context = context::XmPlaybackContext::new(&module, 8000);
+    let mut out = vec![0.0; 2 * 1024];
+    context.render(&mut out);
+    assert!(out.iter().all(|e| *e == 0.0));
+}
+
+#[test]
+fn test_pcm_data_to_f32() {
+    let bit8 = instrument::XmSamplePcmData::Bit8Data(vec![i8::MIN, -64, 0, 64, i8::MAX]);
+    assert_eq!(bit8.to_f32(), vec![-1.0, -0.5, 0.0, 0.5, 127.0 / 128.0]);
+
+    let bit16 = instrument::XmSamplePcmData::Bit16Data(vec![i16::MIN, -16384, 0, 16384, i16::MAX]);
+    assert_eq!(
+        bit16.to_f32(),
+        vec![-1.0, -0.5, 0.0, 0.5, 32767.0 / 32768.0]
+    );
+
+    // playback reads single samples at the same scale
+    assert_eq!(bit8.get(0), Some(-1.0));
+    assert_eq!(bit8.get(4), Some(127.0 / 128.0));
+    assert_eq!(bit16.get(3), Some(0.5));
+    assert_eq!(bit16.get(5), None);
+}
+
+#[test]
+fn test_pcm_data_slices() {
+    use instrument::{XmResamplingType, XmSampleBitDepth, XmSamplePcmData};
+
+    let bit8 = vec![i8::MIN, -3, 0, 5, i8::MAX];
+    let bit16 = vec![i16::MIN, -300, 0, 500, i16::MAX];
+
+    for (data, depth) in [
+        (
+            XmSamplePcmData::Bit8Data(bit8.clone()),
+            XmSampleBitDepth::Bit8,
+        ),
+        (
+            XmSamplePcmData::Bit16Data(bit16.clone()),
+            XmSampleBitDepth::Bit16,
+        ),
+    ] {
+        let encoded = instrument::encode_dpcm_data(&data);
+        let (_, decoded) = instrument::decode_dpcm_data(encoded.len(), depth)(&encoded).unwrap();
+
+        match decoded {
+            XmSamplePcmData::Bit8Data(_) => {
+                assert_eq!(decoded.as_i8_slice(), Some(&bit8[..]));
+                assert_eq!(decoded.as_i16_slice(), None);
+            }
+            XmSamplePcmData::Bit16Data(_) => {
+                assert_eq!(decoded.as_i8_slice(), None);
+                assert_eq!(decoded.as_i16_slice(), Some(&bit16[..]));
+            }
+        }
+
+        // whole positions read the samples as they are in every mode
+        for pos in 0..decoded.len() {
+            for resampling in [
+                XmResamplingType::NoInterpolation,
+                XmResamplingType::LinearInterpolation,
+                XmResamplingType::CubicInterpolation,
+            ] {
+                let sample = decoded.get_interpolated(pos as f32, false, resampling);
+                assert_eq!(sample, decoded.get(pos));
+            }
+        }
+    }
+}
+
+#[test]
+fn test_pcm_data_len() {
+    let bit8 = instrument::XmSamplePcmData::Bit8Data(vec![1, 2, 3]);
+    let bit16 = instrument::XmSamplePcmData::Bit16Data(vec![1, 2]);
+
+    assert_eq!((bit8.len(), bit8.is_empty()), (3, false));
+    assert_eq!((bit16.len(), bit16.is_empty()), (2, false));
+    assert!(instrument::XmSamplePcmData::Bit8Data(vec![]).is_empty());
+    assert!(instrument::XmSamplePcmData::Bit16Data(vec![]).is_empty());
+}
+
+#[test]
+fn test_frequency_table_selection() {
+    let module = |is_amiga| {
+        let mut module = test_module(vec![vec![pattern::XmPatternSlot::new(
+            c4(),
+            Some(1),
+            None,
+            None,
+        )]]);
+        module.header.is_amiga = is_amiga;
+        module
+    };
+
+    let linear = module(false);
+    let amiga = module(true);
+    assert_eq!(
+        linear.header.frequency_table(),
+        frequency::FrequencyTable::Linear
+    );
+    assert_eq!(
+        amiga.header.frequency_table(),
+        frequency::FrequencyTable::Amiga
+    );
+
+    for (module, period) in [(linear, 4608.0), (amiga, 1712.0)] {
+        let mut context = context::XmPlaybackContext::new(&module, 44100);
+        context.tick();
+
+        let channel = context.channel(0).unwrap();
+        assert_eq!(channel.period(), Some(period));
+        // both tables agree on the pitch of actual notes
+        assert!((channel.frequency().unwrap() - 8363.0).abs() < 1e-2);
+    }
+}
+
+#[test]
+fn test_header_version() {
+    let (_, (mut header, _, _)) =
+        header::parse(include_bytes!("test_xms/test_wo_mpt_ext.xm"), true).unwrap();
+
+    assert_eq!(header.version, header::XM_SUPPORTED_VERSION);
+    assert_eq!((header.version_major(), header.version_minor()), (1, 4));
+    assert!(header.validate_ranges().is_empty());
+
+    header.version = 0x0103;
+    assert!(header.validate_ranges().is_empty());
+
+    // the parser refuses versions it doesn't understand
+    let mut data = include_bytes!("test_xms/test_wo_mpt_ext.xm").to_vec();
+    data[58] = 0x01;
+    assert!(header::parse(&data, true).is_err());
 }
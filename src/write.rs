@@ -0,0 +1,385 @@
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    effect::XmEffect,
+    extension::{self, XmExtensionChunk, XmExtensions},
+    frequency, header,
+    instrument::{self, XmEnvelope, XmInstrumentSampleOpts, XmSamplePcmData},
+    note::{XmNote, XM_NOTE_OFF, XM_NO_NOTE},
+    pattern::{self, XmNoteFlags, XmPatternRows, XmPatternSlot, XM_ORDER_TABLE_SIZE},
+    XmModule, XmPattern, XmSample,
+};
+
+const XM_ENVELOPE_POINTS_NUM: usize = 12;
+
+/// Serializes `module` back into the XM format. Sizes stored in the headers are recomputed
+/// from the data, so the output may not be byte-identical to the file `module` was parsed from.
+///
+/// # Panics
+///
+/// If the packed data of a pattern takes more than `u16::MAX` bytes, which its size field can't
+/// hold.
+pub fn write(module: &XmModule) -> Vec<u8> {
+    let mut out = vec![];
+
+    write_header(&mut out, module);
+
+    for pattern in module.patterns.iter() {
+        write_pattern(&mut out, pattern);
+    }
+
+    for (header, samples) in module.instruments.iter() {
+        write_instrument(&mut out, header, samples);
+    }
+
+    write_extensions(&mut out, &module.extensions, module.instruments.len());
+
+    out
+}
+
+fn write_string(out: &mut Vec<u8>, string: &str, length: usize) {
+    // cut on a char boundary so the parser still sees valid UTF-8
+    let mut end = string.len().min(length);
+    while !string.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    out.extend_from_slice(&string.as_bytes()[..end]);
+    out.resize(out.len() + length - end, 0);
+}
+
+fn write_header(out: &mut Vec<u8>, module: &XmModule) {
+    let header = &module.header;
+    let order_table =
+        &module.pattern_order_table[..module.pattern_order_table.len().min(XM_ORDER_TABLE_SIZE)];
+
+    write_string(out, &header.id_text, 17);
+    write_string(out, &header.module_name, 20);
+    out.push(0x1A);
+    write_string(out, &header.tracker_name, 20);
+    // the data is always laid out like the current version, whatever the module was parsed from
+    out.extend_from_slice(&header::XM_SUPPORTED_VERSION.to_le_bytes());
+    // the header size is counted from this field on
+    out.extend_from_slice(&((20 + XM_ORDER_TABLE_SIZE + header.extra.len()) as u32).to_le_bytes());
+    out.extend_from_slice(&(order_table.len() as u16).to_le_bytes());
+    out.extend_from_slice(&header.restart_pos.to_le_bytes());
+    out.extend_from_slice(&header.channels_num.to_le_bytes());
+    out.extend_from_slice(&(module.patterns.len() as u16).to_le_bytes());
+    out.extend_from_slice(&(module.instruments.len() as u16).to_le_bytes());
+    out.extend_from_slice(&(if header.is_amiga { 0u16 } else { 1u16 }).to_le_bytes());
+    out.extend_from_slice(&header.default_tempo.to_le_bytes());
+    out.extend_from_slice(&header.default_bpm.to_le_bytes());
+
+    out.extend_from_slice(order_table);
+    out.resize(out.len() + XM_ORDER_TABLE_SIZE - order_table.len(), 0);
+    out.extend_from_slice(&header.extra);
+}
+
+fn note_byte(note: &XmNote) -> u8 {
+    match note {
+        XmNote::NoNote => XM_NO_NOTE,
+        XmNote::NoteOff => XM_NOTE_OFF,
+        XmNote::Note { tone, octave } => frequency::tone_to_raw(tone, *octave),
+    }
+}
+
+/// Returns the effect type and parameter bytes of `effect`
+fn effect_bytes(effect: &XmEffect) -> (u8, u8) {
+    match effect {
+        XmEffect::Arpeggio(a) => (0x00, a.into_bits()),
+        XmEffect::PortamentoUp(a) => (0x01, *a),
+        XmEffect::PortamentoDown(a) => (0x02, *a),
+        XmEffect::TonePortamento(a) => (0x03, *a),
+        XmEffect::Vibrato(a) => (0x04, a.into_bits()),
+        XmEffect::VolumeSlideTonePortamento(a) => (0x05, a.into_bits()),
+        XmEffect::VolumeSlideVibrato(a) => (0x06, a.into_bits()),
+        XmEffect::Tremolo(a) => (0x07, a.into_bits()),
+        XmEffect::SetPanningFine(a) => (0x08, *a),
+        XmEffect::SampleOffset(a) => (0x09, *a),
+        XmEffect::VolumeSlide(a) => (0x0A, a.into_bits()),
+        XmEffect::PositionJump(a) => (0x0B, *a),
+        XmEffect::SetVolume(a) => (0x0C, *a),
+        XmEffect::PatternBreak(a) => (0x0D, *a),
+        XmEffect::FinePortamentoUp(a) => (0x0E, 0x10 | a),
+        XmEffect::FinePortamentoDown(a) => (0x0E, 0x20 | a),
+        XmEffect::GlissandoControl(a) => (0x0E, 0x30 | a),
+        XmEffect::SetVibratoWaveform(a) => (0x0E, 0x40 | a),
+        XmEffect::SetFinetune(a) => (0x0E, 0x50 | a),
+        XmEffect::PatternLoopStart => (0x0E, 0x60),
+        XmEffect::PatternLoop(a) => (0x0E, 0x60 | a),
+        XmEffect::SetTremoloWaveform(a) => (0x0E, 0x70 | a),
+        XmEffect::SetPanning(a) => (0x0E, 0x80 | a),
+        XmEffect::Retrigger(a) => (0x0E, 0x90 | a),
+        XmEffect::FineVolumeSlideUp(a) => (0x0E, 0xA0 | a),
+        XmEffect::FineVolumeSlideDown(a) => (0x0E, 0xB0 | a),
+        XmEffect::NoteCut(a) => (0x0E, 0xC0 | a),
+        XmEffect::NoteDelay(a) => (0x0E, 0xD0 | a),
+        XmEffect::PatternDelay(a) => (0x0E, 0xE0 | a),
+        XmEffect::SetActiveMacro(a) => (0x0E, 0xF0 | a),
+        XmEffect::SetTempo(a) => (0x0F, *a),
+        XmEffect::SetGlobalVolume(a) => (0x10, *a),
+        XmEffect::GlobalVolumeSlide(a) => (0x11, a.into_bits()),
+        XmEffect::KeyOff(a) => (0x14, *a),
+        XmEffect::SetEnvelopePosition(a) => (0x15, *a),
+        XmEffect::PanningSlide(a) => (0x19, a.into_bits()),
+        XmEffect::RetriggerWithVolume(a) => (0x1B, a.into_bits()),
+        XmEffect::Tremor(a) => (0x1D, a.into_bits()),
+        XmEffect::ExtraFinePortamentoUp(a) => (0x21, 0x10 | a),
+        XmEffect::ExtraFinePortamentoDown(a) => (0x21, 0x20 | a),
+        XmEffect::SetPanbrelloWaveform(a) => (0x21, 0x50 | a),
+        XmEffect::FinePatternDelay(a) => (0x21, 0x60 | a),
+        XmEffect::SoundControl(a) => (0x21, 0x90 | a),
+        XmEffect::HighOffset(a) => (0x21, 0xA0 | a),
+        XmEffect::Panbrello(a) => (0x22, a.into_bits()),
+        XmEffect::MidiMacro(a) => (0x23, *a),
+        XmEffect::SmoothMidiMacro(a) => (0x24, *a),
+        XmEffect::Unknown { command, parameter } => (*command, *parameter),
+    }
+}
+
+/// Writes the shortest encoding of `slot`. A flag byte is followed by the fields which are
+/// present, unless all of them are, in which case the slot is stored unpacked.
+fn write_slot(out: &mut Vec<u8>, slot: &XmPatternSlot) {
+    let note = (!matches!(slot.note, XmNote::NoNote)).then(|| note_byte(&slot.note));
+    let effect = slot.effect.as_ref().map(effect_bytes);
+
+    // a missing effect type or parameter is read back as zero, but at least one of them has to
+    // be kept for the slot to have an effect at all
+    let (effect_type, effect_parameter) = match effect {
+        Some((0, parameter)) => (None, Some(parameter)),
+        Some((kind, 0)) => (Some(kind), None),
+        Some((kind, parameter)) => (Some(kind), Some(parameter)),
+        None => (None, None),
+    };
+
+    if let (Some(note), Some(instrument_index), Some(volume_column), Some((kind, parameter))) =
+        (note, slot.instrument_index, &slot.volume_column, effect)
+    {
+        if effect_type.is_some() && effect_parameter.is_some() {
+            out.extend_from_slice(&[note, instrument_index, volume_column.0, kind, parameter]);
+            return;
+        }
+    }
+
+    let flags = XmNoteFlags::new()
+        .with_note_follows(note.is_some())
+        .with_instrument_follows(slot.instrument_index.is_some())
+        .with_volume_column_byte_follows(slot.volume_column.is_some())
+        .with_effect_type_follows(effect_type.is_some())
+        .with_effect_parameter_follows(effect_parameter.is_some());
+
+    out.push(flags.into_bits() | 0x80);
+    out.extend(note);
+    out.extend(slot.instrument_index);
+    out.extend(slot.volume_column.as_ref().map(|e| e.0));
+    out.extend(effect_type);
+    out.extend(effect_parameter);
+}
+
+fn write_pattern(out: &mut Vec<u8>, (header, rows): &XmPattern) {
+    let data = pack_rows(rows);
+    assert!(
+        data.len() <= u16::MAX as usize,
+        "the packed pattern data takes {} bytes, more than its size field can hold",
+        data.len()
+    );
+
+    out.extend_from_slice(
+        &((pattern::XM_PATTERN_HEADER_SIZE + header.extra.len()) as u32).to_le_bytes(),
+    );
+    out.push(header.packing_type);
+    out.extend_from_slice(&(rows.0.len() as u16).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u16).to_le_bytes());
+    out.extend_from_slice(&header.extra);
+    out.extend(data);
+}
+
+/// Packs the slots of `rows` into pattern data, as stored after the pattern header
+pub fn pack_rows(rows: &XmPatternRows) -> Vec<u8> {
+    let mut out = vec![];
+
+    for slot in rows.0.iter().flat_map(|e| e.0.iter()) {
+        write_slot(&mut out, slot);
+    }
+
+    out
+}
+
+fn write_envelope_points(out: &mut Vec<u8>, envelope: Option<&XmEnvelope>) {
+    let points = envelope.map_or(&[][..], |e| {
+        &e.points[..e.points.len().min(XM_ENVELOPE_POINTS_NUM)]
+    });
+
+    for point in points {
+        out.extend_from_slice(&point.frame.to_le_bytes());
+        out.extend_from_slice(&point.value.to_le_bytes());
+    }
+
+    out.resize(out.len() + (XM_ENVELOPE_POINTS_NUM - points.len()) * 4, 0);
+}
+
+fn envelope_type(envelope: Option<&XmEnvelope>) -> u8 {
+    let Some(envelope) = envelope else {
+        return 0;
+    };
+
+    instrument::XmEnvelopeType::new()
+        .with_on(true)
+        .with_sustain(envelope.sustain_point.is_some())
+        .with_loop_(envelope.loop_start_point.is_some() && envelope.loop_end_point.is_some())
+        .into_bits()
+}
+
+fn write_sample_opts(out: &mut Vec<u8>, opts: &XmInstrumentSampleOpts) {
+    let envelopes = [
+        opts.volume_envelope.as_ref(),
+        opts.panning_envelope.as_ref(),
+    ];
+
+    out.extend_from_slice(&(instrument::XM_SAMPLE_HEADER_SIZE as u32).to_le_bytes());
+    out.extend_from_slice(&opts.sample_keymap_assignments);
+
+    for envelope in envelopes {
+        write_envelope_points(out, envelope);
+    }
+
+    for envelope in envelopes {
+        out.push(envelope.map_or(0, |e| e.points.len().min(XM_ENVELOPE_POINTS_NUM) as u8));
+    }
+
+    for envelope in envelopes {
+        out.push(envelope.and_then(|e| e.sustain_point).unwrap_or(0));
+        out.push(envelope.and_then(|e| e.loop_start_point).unwrap_or(0));
+        out.push(envelope.and_then(|e| e.loop_end_point).unwrap_or(0));
+    }
+
+    for envelope in envelopes {
+        out.push(envelope_type(envelope));
+    }
+
+    out.push(opts.vibrato.kind.clone() as u8);
+    out.extend_from_slice(&[opts.vibrato.sweep, opts.vibrato.depth, opts.vibrato.rate]);
+    out.extend_from_slice(&opts.volume_fadeout.to_le_bytes());
+
+    // reserved
+    out.resize(out.len() + 22, 0);
+}
+
+fn write_instrument(
+    out: &mut Vec<u8>,
+    header: &instrument::XmInstrumentHeader,
+    samples: &[XmSample],
+) {
+    // the parser only expects the sample options if there are any samples
+    let sample_opts = header.sample_opts.as_ref().filter(|_| !samples.is_empty());
+    let header_size = match sample_opts {
+        Some(_) => instrument::XM_INSTRUMENT_HEADER_SIZE_W_OPTS,
+        None => instrument::XM_INSTRUMENT_HEADER_SIZE,
+    };
+
+    out.extend_from_slice(&((header_size + header.extra.len()) as u32).to_le_bytes());
+    write_string(out, &header.name, 22);
+    out.push(header.kind);
+    out.extend_from_slice(&(sample_opts.map_or(0, |_| samples.len()) as u16).to_le_bytes());
+
+    if let Some(sample_opts) = sample_opts {
+        write_sample_opts(out, sample_opts);
+    }
+    out.extend_from_slice(&header.extra);
+
+    if sample_opts.is_none() {
+        return;
+    }
+
+    for (sample_header, data) in samples {
+        let (length, depth) = match data {
+            XmSamplePcmData::Bit8Data(v) => (v.len(), instrument::XmSampleBitDepth::Bit8),
+            XmSamplePcmData::Bit16Data(v) => (v.len() * 2, instrument::XmSampleBitDepth::Bit16),
+        };
+
+        out.extend_from_slice(&(length as u32).to_le_bytes());
+        out.extend_from_slice(&sample_header.loop_start.to_le_bytes());
+        out.extend_from_slice(&sample_header.loop_length.to_le_bytes());
+        out.push(sample_header.volume);
+        out.push(sample_header.finetune as u8);
+        out.push(sample_header.kind.with_depth(depth).into_bits());
+        out.push(sample_header.panning);
+        out.push(sample_header.relative_note_num as u8);
+        // reserved
+        out.push(0);
+        write_string(out, &sample_header.name, 22);
+    }
+
+    for (_, data) in samples {
+        out.extend(instrument::encode_dpcm_data(data));
+    }
+}
+
+fn write_extensions(out: &mut Vec<u8>, extensions: &XmExtensions, instruments_num: usize) {
+    for chunk in extensions.chunks.iter() {
+        let (id, data) = match chunk {
+            XmExtensionChunk::SongMessage(message) => (b"text", message.as_bytes().to_vec()),
+            XmExtensionChunk::PatternNames(names) => {
+                let mut data = vec![];
+                for name in names {
+                    write_string(&mut data, name, extension::XM_PATTERN_NAME_LENGTH);
+                }
+                (b"PNAM", data)
+            }
+            XmExtensionChunk::ChannelNames(names) => {
+                let mut data = vec![];
+                for name in names {
+                    write_string(&mut data, name, extension::XM_CHANNEL_NAME_LENGTH);
+                }
+                (b"CNAM", data)
+            }
+            XmExtensionChunk::MidiMacros(macros) => {
+                // always the full table, missing macros are left empty
+                let mut data = vec![];
+                for (list, count) in [
+                    (&macros.global, extension::XM_GLOBAL_MACROS_NUM),
+                    (&macros.parametered, extension::XM_PARAMETERED_MACROS_NUM),
+                    (&macros.fixed, extension::XM_FIXED_MACROS_NUM),
+                ] {
+                    for index in 0..count {
+                        let macro_ = list.get(index).map_or("", |e| e.as_str());
+                        write_string(&mut data, macro_, extension::XM_MIDI_MACRO_LENGTH);
+                    }
+                }
+                (b"MIDI", data)
+            }
+            XmExtensionChunk::Unknown { id, data } => (id, data.clone()),
+        };
+
+        out.extend_from_slice(id);
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out.extend(data);
+    }
+
+    if let Some(properties) = &extensions.instrument_properties {
+        out.extend_from_slice(extension::XM_INSTRUMENT_PROPERTIES_ID);
+
+        for property in properties {
+            // every instrument needs a value of the same size
+            let size = property.values.first().map_or(0, |e| e.len());
+            out.extend_from_slice(&property.code);
+            out.extend_from_slice(&(size as u16).to_le_bytes());
+            for index in 0..instruments_num {
+                let value = property.values.get(index).map_or(&[][..], |e| &e[..]);
+                let value = &value[..value.len().min(size)];
+                out.extend_from_slice(value);
+                out.resize(out.len() + size - value.len(), 0);
+            }
+        }
+    }
+
+    if let Some(properties) = &extensions.song_properties {
+        out.extend_from_slice(extension::XM_SONG_PROPERTIES_ID);
+
+        for property in properties {
+            out.extend_from_slice(&property.code);
+            out.extend_from_slice(&(property.value.len() as u16).to_le_bytes());
+            out.extend_from_slice(&property.value);
+        }
+    }
+}
@@ -0,0 +1,11 @@
+//! Parsing and writing only need `alloc`, `cargo test --no-default-features` checks that the
+//! crate still builds and works without `std`
+
+#[test]
+fn test_parse_and_write_without_std() {
+    let (_, module) = xmia::parse(include_bytes!("../src/test_xms/test_wo_mpt_ext.xm")).unwrap();
+    assert!(!module.patterns.is_empty());
+
+    let data = xmia::write::write(&module);
+    assert_eq!(xmia::parse(&data).unwrap().1, module);
+}